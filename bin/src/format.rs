@@ -0,0 +1,648 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use neo4j::{Node, Path, Rel};
+use packstream::Value;
+
+/// How many rows a table is sized from before any is printed. Later rows
+/// have cells that do not fit cut short.
+///
+const TABLE_SAMPLE: usize = 1000;
+
+/// How the shell prints results.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Table,
+    Csv,
+    Tsv,
+    Json,
+    Jsonl,
+    Plain,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "plain" => Ok(Format::Plain),
+            _ => Err(format!(
+                "Unknown format {}, expected table, csv, tsv, json, jsonl or plain",
+                s
+            )),
+        }
+    }
+}
+
+impl Format {
+    /// A writer for rows in this format. Rows are written as they arrive,
+    /// except for the first rows of a table, which are held back until the
+    /// column widths are known.
+    ///
+    pub fn writer<'a, W: Write + 'a>(self, out: W) -> Box<dyn RowWriter + 'a> {
+        match self {
+            Format::Table => Box::new(Table {
+                out,
+                keys: Vec::new(),
+                widths: Vec::new(),
+                sample: Vec::new(),
+                sized: false,
+            }),
+            Format::Csv | Format::Tsv | Format::Plain => Box::new(Delimited { out, format: self }),
+            Format::Json => Box::new(Json {
+                out,
+                keys: Vec::new(),
+                rows: 0,
+                lines: false,
+            }),
+            Format::Jsonl => Box::new(Json {
+                out,
+                keys: Vec::new(),
+                rows: 0,
+                lines: true,
+            }),
+        }
+    }
+}
+
+/// Writes the rows of one result. `header` comes first and `finish` last.
+///
+pub trait RowWriter {
+    fn header(&mut self, keys: &[String]) -> io::Result<()>;
+    fn row(&mut self, values: &[Value]) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// One line per row with the fields between separators, as in CSV, TSV and
+/// plain output. The header names the columns.
+///
+struct Delimited<W> {
+    out: W,
+    format: Format,
+}
+
+impl<W: Write> Delimited<W> {
+    fn field(&self, value: &Value) -> String {
+        match self.format {
+            Format::Csv => csv_field(value),
+            Format::Tsv => tsv_field(value),
+            _ => literal(value),
+        }
+    }
+
+    fn line(&mut self, fields: Vec<String>) -> io::Result<()> {
+        let separator = match self.format {
+            Format::Csv => ",",
+            Format::Tsv => "\t",
+            _ => ", ",
+        };
+        writeln!(self.out, "{}", fields.join(separator))
+    }
+}
+
+impl<W: Write> RowWriter for Delimited<W> {
+    fn header(&mut self, keys: &[String]) -> io::Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let fields = match self.format {
+            Format::Plain => keys.to_vec(),
+            _ => keys
+                .iter()
+                .map(|key| self.field(&Value::from(key.as_str())))
+                .collect(),
+        };
+        self.line(fields)
+    }
+
+    fn row(&mut self, values: &[Value]) -> io::Result<()> {
+        let fields = values.iter().map(|value| self.field(value)).collect();
+        self.line(fields)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// A bordered table with a column for each key. A result without columns,
+/// such as that of a write, prints nothing.
+///
+struct Table<W> {
+    out: W,
+    keys: Vec<String>,
+    widths: Vec<usize>,
+    /// Rows held back until the columns are sized.
+    sample: Vec<Vec<String>>,
+    sized: bool,
+}
+
+impl<W: Write> Table<W> {
+    fn size(&mut self) -> io::Result<()> {
+        self.sized = true;
+        self.widths = self.keys.iter().map(|key| key.chars().count()).collect();
+        for row in &self.sample {
+            for (width, cell) in self.widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let keys = self.keys.clone();
+        self.border()?;
+        self.line(&keys)?;
+        self.border()?;
+        for row in std::mem::take(&mut self.sample) {
+            self.line(&row)?;
+        }
+        Ok(())
+    }
+
+    fn border(&mut self) -> io::Result<()> {
+        let mut line = String::from("+");
+        for &width in &self.widths {
+            line.push_str(&"-".repeat(width + 2));
+            line.push('+');
+        }
+        writeln!(self.out, "{}", line)
+    }
+
+    fn line(&mut self, cells: &[String]) -> io::Result<()> {
+        let mut line = String::from("|");
+        for (cell, &width) in cells.iter().zip(&self.widths) {
+            let length = cell.chars().count();
+            if length > width {
+                let cut: String = cell.chars().take(width.saturating_sub(1)).collect();
+                let _ = write!(line, " {}… |", cut);
+            } else {
+                let _ = write!(line, " {}{} |", cell, " ".repeat(width - length));
+            }
+        }
+        writeln!(self.out, "{}", line)
+    }
+}
+
+impl<W: Write> RowWriter for Table<W> {
+    fn header(&mut self, keys: &[String]) -> io::Result<()> {
+        self.keys = keys.to_vec();
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[Value]) -> io::Result<()> {
+        let cells: Vec<String> = values.iter().map(literal).collect();
+        if self.sized {
+            return self.line(&cells);
+        }
+        self.sample.push(cells);
+        if self.sample.len() == TABLE_SAMPLE {
+            self.size()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.keys.is_empty() {
+            return Ok(());
+        }
+        if !self.sized {
+            self.size()?;
+        }
+        self.border()?;
+        self.out.flush()
+    }
+}
+
+/// A JSON object for each row, keyed by column, either in one array or one
+/// to a line.
+///
+struct Json<W> {
+    out: W,
+    keys: Vec<String>,
+    rows: usize,
+    lines: bool,
+}
+
+impl<W: Write> RowWriter for Json<W> {
+    fn header(&mut self, keys: &[String]) -> io::Result<()> {
+        self.keys = keys.to_vec();
+        if !self.lines {
+            write!(self.out, "[")?;
+        }
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[Value]) -> io::Result<()> {
+        let mut object = String::new();
+        json_object(
+            &mut object,
+            self.keys.iter().map(String::as_str).zip(values),
+        );
+        if self.lines {
+            writeln!(self.out, "{}", object)?;
+        } else if self.rows == 0 {
+            write!(self.out, "\n{}", object)?;
+        } else {
+            write!(self.out, ",\n{}", object)?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.lines {
+            writeln!(self.out, "{}]", if self.rows > 0 { "\n" } else { "" })?;
+        }
+        self.out.flush()
+    }
+}
+
+/// A node, relationship or path, if `value` is one.
+///
+enum Graph {
+    Node(Node),
+    Rel(Rel),
+    Path(Path),
+}
+
+fn graph(value: &Value) -> Option<Graph> {
+    match *value {
+        Value::Structure {
+            signature: 0x4E, ..
+        } => Node::from_value(value.clone()).ok().map(Graph::Node),
+        Value::Structure {
+            signature: 0x52, ..
+        } => Rel::from_value(value.clone()).ok().map(Graph::Rel),
+        Value::Structure {
+            signature: 0x50, ..
+        } => Path::from_value(value.clone()).ok().map(Graph::Path),
+        _ => None,
+    }
+}
+
+/// Map entries in key order, so output does not change between runs.
+///
+fn sorted(map: &HashMap<String, Value>) -> BTreeMap<&str, &Value> {
+    map.iter()
+        .map(|(key, value)| (key.as_str(), value))
+        .collect()
+}
+
+/// `value` written the way Cypher would write it as a literal, with nodes,
+/// relationships and paths in pattern syntax.
+///
+pub fn literal(value: &Value) -> String {
+    let mut out = String::new();
+    write_literal(&mut out, value);
+    out
+}
+
+fn write_literal(out: &mut String, value: &Value) {
+    match *value {
+        Value::String(ref s) => {
+            let _ = write!(out, "{:?}", s);
+        }
+        Value::List(ref values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_literal(out, value);
+            }
+            out.push(']');
+        }
+        Value::Map(ref map) => write_properties(out, map, true),
+        Value::Structure { .. } => match graph(value) {
+            Some(Graph::Node(ref node)) => write_node(out, node),
+            Some(Graph::Rel(ref rel)) => write_rel(out, rel),
+            Some(Graph::Path(ref path)) => {
+                write_node(out, &path.nodes()[0]);
+                for segment in path.segments() {
+                    let forward = segment.rel.src == segment.start.id;
+                    out.push_str(if forward { "-" } else { "<-" });
+                    write_rel(out, segment.rel);
+                    out.push_str(if forward { "->" } else { "-" });
+                    write_node(out, segment.end);
+                }
+            }
+            None => {
+                let _ = write!(out, "{:?}", value);
+            }
+        },
+        _ => {
+            let _ = write!(out, "{:?}", value);
+        }
+    }
+}
+
+fn write_properties(out: &mut String, map: &HashMap<String, Value>, empty: bool) {
+    if map.is_empty() && !empty {
+        return;
+    }
+    if !empty {
+        out.push(' ');
+    }
+    out.push('{');
+    for (i, (key, value)) in sorted(map).into_iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(out, "{}: ", key);
+        write_literal(out, value);
+    }
+    out.push('}');
+}
+
+fn write_node(out: &mut String, node: &Node) {
+    out.push('(');
+    for label in &node.labs {
+        let _ = write!(out, ":{}", label);
+    }
+    write_properties(out, &node.props, false);
+    out.push(')');
+}
+
+fn write_rel(out: &mut String, rel: &Rel) {
+    let _ = write!(out, "[:{}", rel.label);
+    write_properties(out, &rel.props, false);
+    out.push(']');
+}
+
+/// A CSV field, quoted if it holds a separator, quote or line break.
+/// Strings are written as they are; other values as Cypher literals.
+///
+fn csv_field(value: &Value) -> String {
+    let text = match *value {
+        Value::String(ref s) => s.clone(),
+        Value::Null => String::new(),
+        _ => literal(value),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// A TSV field, with tabs, line breaks and backslashes escaped.
+///
+fn tsv_field(value: &Value) -> String {
+    let text = match *value {
+        Value::String(ref s) => s.clone(),
+        Value::Null => String::new(),
+        _ => literal(value),
+    };
+    let mut field = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => field.push_str("\\\\"),
+            '\t' => field.push_str("\\t"),
+            '\n' => field.push_str("\\n"),
+            '\r' => field.push_str("\\r"),
+            _ => field.push(c),
+        }
+    }
+    field
+}
+
+fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn json_object<'a, I>(out: &mut String, entries: I)
+where
+    I: IntoIterator<Item = (&'a str, &'a Value)>,
+{
+    out.push('{');
+    for (i, (key, value)) in entries.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        json_string(out, key);
+        out.push(':');
+        json(out, value);
+    }
+    out.push('}');
+}
+
+fn json_node(out: &mut String, node: &Node) {
+    let _ = write!(out, "{{\"id\":{},\"labels\":[", node.id);
+    for (i, label) in node.labs.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        json_string(out, label);
+    }
+    out.push_str("],\"properties\":");
+    json_object(out, sorted(&node.props));
+    out.push('}');
+}
+
+fn json_rel(out: &mut String, rel: &Rel) {
+    let _ = write!(out, "{{\"id\":{},\"type\":", rel.id);
+    json_string(out, &rel.label);
+    let _ = write!(
+        out,
+        ",\"start\":{},\"end\":{},\"properties\":",
+        rel.src, rel.dst
+    );
+    json_object(out, sorted(&rel.props));
+    out.push('}');
+}
+
+/// `value` as JSON. Nodes, relationships and paths become objects, and
+/// temporal values become strings; numbers JSON cannot hold become `null`.
+///
+fn json(out: &mut String, value: &Value) {
+    match *value {
+        Value::Null => out.push_str("null"),
+        Value::Boolean(b) => {
+            let _ = write!(out, "{}", b);
+        }
+        Value::Integer(i) => {
+            let _ = write!(out, "{}", i);
+        }
+        Value::Float(f) if f.is_finite() => {
+            let _ = write!(out, "{:?}", f);
+        }
+        Value::Float(_) => out.push_str("null"),
+        Value::String(ref s) => json_string(out, s),
+        Value::List(ref values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                json(out, value);
+            }
+            out.push(']');
+        }
+        Value::Map(ref map) => json_object(out, sorted(map)),
+        Value::Structure {
+            signature,
+            ref fields,
+        } => match graph(value) {
+            Some(Graph::Node(ref node)) => json_node(out, node),
+            Some(Graph::Rel(ref rel)) => json_rel(out, rel),
+            Some(Graph::Path(ref path)) => {
+                out.push_str("{\"nodes\":[");
+                for (i, node) in path.nodes().iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    json_node(out, node);
+                }
+                out.push_str("],\"relationships\":[");
+                for (i, rel) in path.rels().iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    json_rel(out, rel);
+                }
+                out.push_str("]}");
+            }
+            None => {
+                let _ = write!(out, "{{\"signature\":{},\"fields\":", signature);
+                json(out, &Value::List(fields.clone()));
+                out.push('}');
+            }
+        },
+        Value::Point2D(ref p) => {
+            let _ = write!(out, "{{\"srid\":{},\"x\":", p.srid);
+            json(out, &Value::Float(p.x));
+            out.push_str(",\"y\":");
+            json(out, &Value::Float(p.y));
+            out.push('}');
+        }
+        Value::Point3D(ref p) => {
+            let _ = write!(out, "{{\"srid\":{},\"x\":", p.srid);
+            json(out, &Value::Float(p.x));
+            out.push_str(",\"y\":");
+            json(out, &Value::Float(p.y));
+            out.push_str(",\"z\":");
+            json(out, &Value::Float(p.z));
+            out.push('}');
+        }
+        _ => json_string(out, &value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use packstream::parameters;
+
+    fn node(id: i64, label: &str, name: &str) -> Value {
+        Value::Structure {
+            signature: 0x4E,
+            fields: vec![
+                id.into(),
+                vec![label].into(),
+                parameters!("name" => name).into(),
+            ],
+        }
+    }
+
+    fn write(format: Format, rows: &[Vec<Value>]) -> String {
+        let mut out = Vec::new();
+        {
+            let mut writer = format.writer(&mut out);
+            writer
+                .header(&[String::from("n"), String::from("x")])
+                .unwrap();
+            for row in rows {
+                writer.row(row).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_literals() {
+        assert_eq!(
+            literal(&node(1, "Person", "Ann")),
+            r#"(:Person {name: "Ann"})"#
+        );
+        let rel = Value::Structure {
+            signature: 0x52,
+            fields: vec![
+                7.into(),
+                1.into(),
+                2.into(),
+                "KNOWS".into(),
+                Value::Map(HashMap::new()),
+            ],
+        };
+        assert_eq!(literal(&rel), "[:KNOWS]");
+        assert_eq!(
+            literal(&parameters!("b" => vec![1.5], "a" => Value::Null).into()),
+            "{a: null, b: [1.5]}"
+        );
+    }
+
+    #[test]
+    fn writes_each_format() {
+        let rows = [
+            vec![node(1, "Person", "Ann"), "a, \"b\"".into()],
+            vec![Value::Null, 2.into()],
+        ];
+        assert_eq!(
+            write(Format::Table, &rows),
+            "\
++-------------------------+------------+
+| n                       | x          |
++-------------------------+------------+
+| (:Person {name: \"Ann\"}) | \"a, \\\"b\\\"\" |
+| null                    | 2          |
++-------------------------+------------+
+"
+        );
+        assert_eq!(
+            write(Format::Csv, &rows),
+            "n,x\n\"(:Person {name: \"\"Ann\"\"})\",\"a, \"\"b\"\"\"\n,2\n"
+        );
+        assert_eq!(
+            write(Format::Tsv, &rows),
+            "n\tx\n(:Person {name: \"Ann\"})\ta, \"b\"\n\t2\n"
+        );
+        assert_eq!(
+            write(Format::Plain, &rows),
+            "n, x\n(:Person {name: \"Ann\"}), \"a, \\\"b\\\"\"\nnull, 2\n"
+        );
+        let object =
+            r#"{"n":{"id":1,"labels":["Person"],"properties":{"name":"Ann"}},"x":"a, \"b\""}"#;
+        assert_eq!(
+            write(Format::Jsonl, &rows),
+            format!("{}\n{{\"n\":null,\"x\":2}}\n", object)
+        );
+        assert_eq!(
+            write(Format::Json, &rows),
+            format!("[\n{},\n{{\"n\":null,\"x\":2}}\n]\n", object)
+        );
+        assert_eq!(write(Format::Json, &[]), "[]\n");
+    }
+}
@@ -1,39 +1,239 @@
-use std::collections::HashMap;
-use std::env;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, IsTerminal, Read},
+    process,
+};
 
-use neo4j::*;
-use packstream::{parameters, Value};
+use neo4j::{Driver, Neo4jDB, NeoResult};
 
-fn main() {
-    let mut args = env::args();
+use crate::{format::Format, script::describe};
 
-    let statement = match args.nth(1) {
-        Some(string) => string,
-        _ => String::from("RETURN $x"),
-    };
-    let parameters = parameters!("x" => 1);
+mod format;
+mod script;
+mod shell;
+
+const USAGE: &str = "\
+Usage: bin [OPTIONS] [STATEMENT]
+
+Runs STATEMENT, or the `;`-separated statements of a script from a file or
+standard input. Starts an interactive shell if there is neither.
 
-    let session = Neo4jDB::connect("[::1]:7687", "neo4j", "password").unwrap();
-    dump(session, &statement[..], parameters);
+Options:
+  -a, --address URI      Server to connect to [env: NEO4J_URI]
+  -u, --user USER        User to log in as [env: NEO4J_USER]
+  -p, --password PASS    Password to log in with [env: NEO4J_PASSWORD]
+  -d, --database NAME    Database to use [env: NEO4J_DATABASE]
+      --format FORMAT    How to print results: table, csv, tsv, json, jsonl
+                         or plain [default: table]
+  -f, --file FILE        Script to run, or - for standard input
+      --single-transaction
+                         Run the whole script in one transaction
+  -h, --help             Print this help
+";
+
+/// Connection settings and what to run, from the command line with
+/// environment variables as fallback.
+///
+#[derive(Debug, PartialEq)]
+struct Options {
+    uri: String,
+    user: String,
+    password: String,
+    database: Option<String>,
+    statement: Option<String>,
+    file: Option<String>,
+    single_transaction: bool,
+    format: Format,
+    help: bool,
 }
 
-fn dump(mut neo: Neo4jDB, statement: &str, parameters: HashMap<&str, Value>) {
-    // execute statement
-    let result = neo.run(statement, parameters).unwrap();
+impl Options {
+    fn parse<I, E>(args: I, env: E) -> Result<Options, String>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let mut options = Options {
+            uri: env("NEO4J_URI").unwrap_or_else(|| String::from("bolt://[::1]:7687")),
+            user: env("NEO4J_USER").unwrap_or_else(|| String::from("neo4j")),
+            password: env("NEO4J_PASSWORD").unwrap_or_else(|| String::from("password")),
+            database: env("NEO4J_DATABASE"),
+            statement: None,
+            file: None,
+            single_transaction: false,
+            format: Format::Table,
+            help: false,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => (&arg[..i], Some(String::from(&arg[i + 1..]))),
+                _ => (&arg[..], None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} needs a value", flag))
+            };
+            match flag {
+                "-a" | "--address" => options.uri = value()?,
+                "-u" | "--user" => options.user = value()?,
+                "-p" | "--password" => options.password = value()?,
+                "-d" | "--database" => options.database = Some(value()?),
+                "--format" => options.format = value()?.parse()?,
+                "-f" | "--file" => options.file = Some(value()?),
+                "--single-transaction" => options.single_transaction = true,
+                "-h" | "--help" => options.help = true,
+                _ if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+                _ if options.statement.is_some() => {
+                    return Err(String::from("Only one statement can be given"))
+                }
+                _ => options.statement = Some(arg.clone()),
+            }
+        }
+        if options.statement.is_some() && options.file.is_some() {
+            return Err(String::from("Give either a statement or a script file"));
+        }
+        Ok(options)
+    }
+
+    /// The script to run, if there is one: the file given, or standard
+    /// input when it is not a terminal and there is no statement.
+    ///
+    fn script(&self) -> io::Result<Option<String>> {
+        let mut script = String::new();
+        match self.file.as_deref() {
+            Some("-") => io::stdin().read_to_string(&mut script)?,
+            Some(file) => return fs::read_to_string(file).map(Some),
+            None if self.statement.is_none() && !io::stdin().is_terminal() => {
+                io::stdin().read_to_string(&mut script)?
+            }
+            None => return Ok(None),
+        };
+        Ok(Some(script))
+    }
+
+    fn connect(&self) -> NeoResult<Neo4jDB> {
+        let driver = Driver::builder(&self.uri)
+            .auth(&self.user, &self.password)
+            .build()?;
+        let mut db = driver.connect()?;
+        db.set_database(self.database.clone())?;
+        Ok(db)
+    }
+}
 
-    // iterate result
-    let mut counter: usize = 0;
-    for record in result {
-        println!("{:?}", record);
-        counter += 1;
+fn main() {
+    let options = match Options::parse(env::args().skip(1), |name| env::var(name).ok()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        print!("{}", USAGE);
+        return;
     }
 
-    println!(
-        "({} record{})",
-        counter,
-        match counter {
-            1 => "",
-            _ => "s",
+    let script = match options.script() {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!(
+                "Could not read {}: {}",
+                options.file.as_deref().unwrap_or("-"),
+                e
+            );
+            process::exit(1);
         }
-    );
+    };
+    let mut db = match options.connect() {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("Could not connect to {}: {}", options.uri, e);
+            process::exit(1);
+        }
+    };
+    if let Some(script) = script {
+        let outcome =
+            script::run_script(&mut db, &script, options.single_transaction, options.format);
+        if let Err(message) = outcome {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+        return;
+    }
+    match options.statement {
+        Some(ref statement) => {
+            match shell::execute(&mut db, statement, &BTreeMap::new(), options.format) {
+                Ok((records, summary)) => eprintln!("({})", describe(records, &summary.counters)),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        None => match shell::Shell::new(options.format) {
+            Ok(mut shell) => shell.run(&mut db),
+            Err(e) => {
+                eprintln!("Could not start the shell: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str], env: &[(&str, &str)]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|&arg| String::from(arg)), |name| {
+            env.iter()
+                .find(|&&(key, _)| key == name)
+                .map(|&(_, value)| String::from(value))
+        })
+    }
+
+    #[test]
+    fn flags_override_environment() {
+        let env = [("NEO4J_URI", "neo4j://db:7687"), ("NEO4J_USER", "reader")];
+        let options = parse(
+            &[
+                "-u",
+                "admin",
+                "--database=sales",
+                "--format",
+                "csv",
+                "RETURN 1",
+            ],
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                uri: String::from("neo4j://db:7687"),
+                user: String::from("admin"),
+                password: String::from("password"),
+                database: Some(String::from("sales")),
+                statement: Some(String::from("RETURN 1")),
+                file: None,
+                single_transaction: false,
+                format: Format::Csv,
+                help: false,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--password"], &[]).is_err());
+        assert!(parse(&["--verbose"], &[]).is_err());
+        assert!(parse(&["--format", "xml"], &[]).is_err());
+        assert!(parse(&["RETURN 1", "RETURN 2"], &[]).is_err());
+        assert!(parse(&["-f", "seed.cypher", "RETURN 1"], &[]).is_err());
+    }
 }
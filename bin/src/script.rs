@@ -0,0 +1,280 @@
+use std::error::Error;
+
+use neo4j::{Counters, Neo4jDB, Neo4jOperations};
+
+use crate::{format::Format, shell::execute};
+
+/// One statement of a script, without its closing `;`.
+///
+#[derive(Debug, PartialEq)]
+pub struct Statement {
+    pub text: String,
+    /// The line the statement starts on, counting from 1.
+    pub line: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Code,
+    /// Inside a string or escaped name opened by this quote character.
+    Quoted(char),
+    /// Just after a backslash inside a string.
+    Escaped(char),
+    LineComment,
+    BlockComment,
+}
+
+/// Splits Cypher into statements at each `;` that is not inside a string,
+/// an escaped name or a comment. Comments before a statement are dropped.
+///
+pub struct Splitter {
+    state: State,
+    text: String,
+    /// Where the statement being read starts, once it has started.
+    start: Option<usize>,
+    lines: usize,
+}
+
+impl Default for Splitter {
+    fn default() -> Self {
+        Splitter {
+            state: State::Code,
+            text: String::new(),
+            start: None,
+            lines: 0,
+        }
+    }
+}
+
+impl Splitter {
+    /// Whether nothing but whitespace and comments has been read since the
+    /// last complete statement.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none() && self.state != State::BlockComment
+    }
+
+    /// Reads one line and returns any statements it completes.
+    ///
+    pub fn push_line(&mut self, line: &str) -> Vec<Statement> {
+        self.lines += 1;
+        let mut statements = Vec::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            self.state = match (self.state, c) {
+                (State::Code, ';') => {
+                    if let Some(line) = self.start.take() {
+                        statements.push(Statement {
+                            text: String::from(self.text.trim_end()),
+                            line,
+                        });
+                    }
+                    self.text.clear();
+                    continue;
+                }
+                (State::Code, '/') if chars.peek() == Some(&'/') => State::LineComment,
+                (State::Code, '/') if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if self.start.is_some() {
+                        self.text.push_str("/*");
+                    }
+                    self.state = State::BlockComment;
+                    continue;
+                }
+                (State::Code, '\'') | (State::Code, '"') | (State::Code, '`') => State::Quoted(c),
+                (State::Code, _) => State::Code,
+                (State::Quoted(quote), '\\') if quote != '`' => State::Escaped(quote),
+                (State::Quoted(quote), _) if c == quote => State::Code,
+                (State::Escaped(quote), _) => State::Quoted(quote),
+                (State::BlockComment, '*') if chars.peek() == Some(&'/') => {
+                    chars.next();
+                    if self.start.is_some() {
+                        self.text.push_str("*/");
+                    }
+                    self.state = State::Code;
+                    continue;
+                }
+                (state, _) => state,
+            };
+            let comment = matches!(self.state, State::LineComment | State::BlockComment);
+            if self.start.is_none() && (comment || c.is_whitespace()) {
+                continue;
+            }
+            self.start.get_or_insert(self.lines);
+            self.text.push(c);
+        }
+        if self.state == State::LineComment {
+            self.state = State::Code;
+        }
+        if self.start.is_some() {
+            self.text.push('\n');
+        }
+        statements
+    }
+
+    /// The statement left without a closing `;` at the end of the input,
+    /// if any.
+    ///
+    pub fn finish(self) -> Option<Statement> {
+        let text = String::from(self.text.trim_end());
+        self.start.map(|line| Statement { text, line })
+    }
+}
+
+/// Splits a whole script into statements. The last one may leave out its
+/// `;`.
+///
+pub fn split(script: &str) -> Vec<Statement> {
+    let mut splitter = Splitter::default();
+    let mut statements = Vec::new();
+    for line in script.lines() {
+        statements.extend(splitter.push_line(line));
+    }
+    statements.extend(splitter.finish());
+    statements
+}
+
+/// How many records a statement returned and what it changed, such as
+/// `2 records, nodes created: 2, properties set: 4`.
+///
+pub fn describe(records: usize, counters: &Counters) -> String {
+    let mut description = format!("{} record{}", records, if records == 1 { "" } else { "s" });
+    let counts = [
+        ("nodes created", counters.nodes_created),
+        ("nodes deleted", counters.nodes_deleted),
+        ("relationships created", counters.relationships_created),
+        ("relationships deleted", counters.relationships_deleted),
+        ("properties set", counters.properties_set),
+        ("labels added", counters.labels_added),
+        ("labels removed", counters.labels_removed),
+        ("indexes added", counters.indexes_added),
+        ("indexes removed", counters.indexes_removed),
+        ("constraints added", counters.constraints_added),
+        ("constraints removed", counters.constraints_removed),
+        ("system updates", counters.system_updates),
+    ];
+    for &(name, count) in counts.iter().filter(|&&(_, count)| count > 0) {
+        description.push_str(&format!(", {}: {}", name, count));
+    }
+    description
+}
+
+/// Runs `statements` one after another, printing a summary of each to
+/// standard error. Stops at the first failure, returning the line of the
+/// statement that failed along with the error.
+///
+fn run<O: Neo4jOperations>(
+    ops: &mut O,
+    statements: &[Statement],
+    format: Format,
+) -> Result<(), (usize, Box<dyn Error>)> {
+    for statement in statements {
+        let (records, summary) = execute(ops, &statement.text, &Default::default(), format)
+            .map_err(|e| (statement.line, e))?;
+        eprintln!(
+            "line {}: {}",
+            statement.line,
+            describe(records, &summary.counters)
+        );
+    }
+    Ok(())
+}
+
+/// Runs each statement of `script` in turn, each in its own transaction,
+/// or all in one if `single_transaction` is set. The first failure stops
+/// the script, rolling back the single transaction if there is one.
+///
+pub fn run_script(
+    db: &mut Neo4jDB,
+    script: &str,
+    single_transaction: bool,
+    format: Format,
+) -> Result<(), String> {
+    let statements = split(script);
+    if !single_transaction {
+        return run(db, &statements, format).map_err(|(line, e)| format!("line {}: {}", line, e));
+    }
+    let mut tx = db.transaction().map_err(|e| e.to_string())?;
+    match run(&mut tx, &statements, format) {
+        Ok(()) => tx
+            .commit()
+            .map(|_| ())
+            .map_err(|e| format!("Could not commit: {}", e)),
+        Err((line, e)) => Err(format!("line {}: {}; rolled back", line, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statements(expected: &[(usize, &str)]) -> Vec<Statement> {
+        expected
+            .iter()
+            .map(|&(line, text)| Statement {
+                text: String::from(text),
+                line,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splits_at_semicolons_outside_strings_and_comments() {
+        let script = "\
+// Seed data; run once
+CREATE (:Person {name: 'O\\'Brien; Jr'});
+
+/* several
+   statements; */ MATCH (p:`Per;son`)
+RETURN p.name AS `a;b`, \"x;y\"; RETURN 1 // trailing;
+;
+RETURN /* inline; */ 2";
+        assert_eq!(
+            split(script),
+            statements(&[
+                (2, "CREATE (:Person {name: 'O\\'Brien; Jr'})"),
+                (5, "MATCH (p:`Per;son`)\nRETURN p.name AS `a;b`, \"x;y\""),
+                (6, "RETURN 1 // trailing;"),
+                (8, "RETURN /* inline; */ 2"),
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_unfinished_statements() {
+        let mut splitter = Splitter::default();
+        assert!(splitter.push_line("  // just a comment").is_empty());
+        assert!(splitter.is_empty());
+        assert!(splitter.push_line("/* open").is_empty());
+        assert!(!splitter.is_empty());
+        assert!(splitter.push_line("still open */ MATCH (n)").is_empty());
+        assert_eq!(
+            splitter.push_line("RETURN n; RETURN 'unclosed;"),
+            vec![Statement {
+                text: String::from("MATCH (n)\nRETURN n"),
+                line: 3,
+            }]
+        );
+        assert_eq!(
+            splitter.finish(),
+            Some(Statement {
+                text: String::from("RETURN 'unclosed;"),
+                line: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn describes_counters() {
+        let counters = Counters {
+            nodes_created: 2,
+            properties_set: 4,
+            ..Counters::default()
+        };
+        assert_eq!(
+            describe(1, &counters),
+            "1 record, nodes created: 2, properties set: 4"
+        );
+        assert_eq!(describe(0, &Counters::default()), "0 records");
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    env,
+    error::Error,
+    fmt, io,
+    path::PathBuf,
+};
+
+use neo4j::{Neo4jDB, Neo4jOperations, Neo4jTransaction, NeoResult, ResultSummary};
+use packstream::{Data, Value};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{
+    format::{literal, Format},
+    script::{describe, Splitter},
+};
+
+const HELP: &str = "\
+Statements run once a line ends with `;`. Commands:
+  :begin                  Open a transaction
+  :commit                 Commit the open transaction
+  :rollback               Roll back the open transaction
+  :use [DATABASE]         Switch database, or back to the home database
+  :param NAME => VALUE    Set a parameter to the value of a Cypher expression
+  :params                 List the parameters
+  :help                   Print this help
+  :exit                   Leave the shell
+";
+
+/// Runs `statement` with `params`, writing each record to standard output
+/// in `format` as it arrives. Returns the number of records and the
+/// summary, for the caller to report out of the way of the output.
+///
+pub fn execute<O: Neo4jOperations>(
+    ops: &mut O,
+    statement: &str,
+    params: &BTreeMap<String, Value>,
+    format: Format,
+) -> Result<(usize, ResultSummary), Box<dyn Error>> {
+    let mut result = ops.run(statement, borrowed(params))?;
+    let stdout = io::stdout();
+    let mut writer = format.writer(stdout.lock());
+    writer.header(&result.keys())?;
+    let mut counter: usize = 0;
+    for Data::Record(values) in result.by_ref() {
+        writer.row(&values)?;
+        counter += 1;
+    }
+    writer.finish()?;
+    Ok((counter, result.consume()?))
+}
+
+/// Asks the server for the value of a Cypher expression, which may refer
+/// to `params`.
+///
+fn evaluate<O: Neo4jOperations>(
+    ops: &mut O,
+    expression: &str,
+    params: &BTreeMap<String, Value>,
+) -> NeoResult<Value> {
+    let mut result = ops.run(&format!("RETURN {} AS value", expression), borrowed(params))?;
+    let value = match result.next() {
+        Some(Data::Record(mut values)) => values.pop(),
+        None => None,
+    };
+    result.consume()?;
+    Ok(value.unwrap_or(Value::Null))
+}
+
+fn borrowed(params: &BTreeMap<String, Value>) -> HashMap<&str, Value> {
+    params
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.clone()))
+        .collect()
+}
+
+/// A command line starting with `:`.
+///
+#[derive(Debug, PartialEq)]
+enum Command {
+    Begin,
+    Commit,
+    Rollback,
+    Use(Option<String>),
+    Param(String, String),
+    Params,
+    Help,
+    Exit,
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        let command = match name {
+            ":begin" => Command::Begin,
+            ":commit" => Command::Commit,
+            ":rollback" => Command::Rollback,
+            ":use" => {
+                let database = rest.trim_matches('`');
+                return Ok(Command::Use(if database.is_empty() {
+                    None
+                } else {
+                    Some(String::from(database))
+                }));
+            }
+            ":param" => {
+                let (param, expression) = match rest.find("=>") {
+                    Some(i) => (rest[..i].trim().trim_matches('`'), rest[i + 2..].trim()),
+                    None => ("", ""),
+                };
+                if param.is_empty() || expression.is_empty() {
+                    return Err(String::from("Usage: :param NAME => VALUE"));
+                }
+                return Ok(Command::Param(
+                    String::from(param),
+                    String::from(expression),
+                ));
+            }
+            ":params" => Command::Params,
+            ":help" => Command::Help,
+            ":exit" | ":quit" => Command::Exit,
+            _ => return Err(format!("Unknown command {}, see :help", name)),
+        };
+        if !rest.is_empty() {
+            return Err(format!("{} takes no arguments", name));
+        }
+        Ok(command)
+    }
+}
+
+/// A complete statement or command read from one or more lines.
+///
+#[derive(Debug, PartialEq)]
+enum Input {
+    Statement(String),
+    Command(String),
+}
+
+/// Collects lines into statements ending in `;`. A line starting with `:`
+/// is a command when no statement is in progress.
+///
+#[derive(Default)]
+struct Buffer {
+    splitter: Splitter,
+}
+
+impl Buffer {
+    fn is_empty(&self) -> bool {
+        self.splitter.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.splitter = Splitter::default();
+    }
+
+    fn push(&mut self, line: &str) -> Vec<Input> {
+        if self.splitter.is_empty() && line.trim_start().starts_with(':') {
+            return vec![Input::Command(String::from(line.trim()))];
+        }
+        self.splitter
+            .push_line(line)
+            .into_iter()
+            .map(|statement| Input::Statement(statement.text))
+            .collect()
+    }
+}
+
+/// What to do once a transaction is over.
+///
+enum Flow {
+    Continue,
+    Exit,
+}
+
+/// An interactive shell with line editing, history kept between runs, and
+/// parameters that every statement is run with.
+///
+pub struct Shell {
+    editor: DefaultEditor,
+    history: Option<PathBuf>,
+    params: BTreeMap<String, Value>,
+    buffer: Buffer,
+    /// Statements and commands read but not yet run.
+    pending: VecDeque<Input>,
+    format: Format,
+}
+
+impl Shell {
+    pub fn new(format: Format) -> rustyline::Result<Shell> {
+        let mut editor = DefaultEditor::new()?;
+        let history =
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".rusty_bolt_history"));
+        if let Some(ref history) = history {
+            let _ = editor.load_history(history);
+        }
+        Ok(Shell {
+            editor,
+            history,
+            params: BTreeMap::new(),
+            buffer: Buffer::default(),
+            pending: VecDeque::new(),
+            format,
+        })
+    }
+
+    pub fn run(&mut self, db: &mut Neo4jDB) {
+        loop {
+            let name = String::from(db.database().unwrap_or("neo4j"));
+            let command = match self.read(&name, false) {
+                Some(Input::Statement(statement)) => {
+                    self.execute(db, &statement);
+                    continue;
+                }
+                Some(Input::Command(command)) => command,
+                None => break,
+            };
+            match Command::parse(&command) {
+                Ok(Command::Begin) => match db.transaction() {
+                    Ok(tx) => {
+                        if let Flow::Exit = self.transaction(tx, &name) {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("{}", e),
+                },
+                Ok(Command::Commit) | Ok(Command::Rollback) => {
+                    eprintln!("There is no open transaction")
+                }
+                Ok(Command::Use(database)) => report(db.set_database(database)),
+                Ok(Command::Exit) => break,
+                Ok(command) => self.common(db, command),
+                Err(message) => eprintln!("{}", message),
+            }
+        }
+        self.save_history();
+    }
+
+    /// Reads and runs statements in `tx` until it is committed or rolled
+    /// back. Leaving the shell rolls it back.
+    ///
+    fn transaction(&mut self, mut tx: Neo4jTransaction<'_>, name: &str) -> Flow {
+        loop {
+            let command = match self.read(name, true) {
+                Some(Input::Statement(statement)) => {
+                    self.execute(&mut tx, &statement);
+                    continue;
+                }
+                Some(Input::Command(command)) => command,
+                None => return Flow::Exit,
+            };
+            match Command::parse(&command) {
+                Ok(Command::Begin) => eprintln!("A transaction is already open"),
+                Ok(Command::Commit) => {
+                    report(tx.commit());
+                    return Flow::Continue;
+                }
+                Ok(Command::Rollback) => {
+                    report(tx.rollback());
+                    return Flow::Continue;
+                }
+                Ok(Command::Use(_)) => {
+                    eprintln!("The database cannot be changed inside a transaction")
+                }
+                Ok(Command::Exit) => return Flow::Exit,
+                Ok(command) => self.common(&mut tx, command),
+                Err(message) => eprintln!("{}", message),
+            }
+        }
+    }
+
+    fn execute<O: Neo4jOperations>(&self, ops: &mut O, statement: &str) {
+        match execute(ops, statement, &self.params, self.format) {
+            Ok((records, summary)) => eprintln!("({})", describe(records, &summary.counters)),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    /// Handles the commands that work the same in or out of a transaction.
+    ///
+    fn common<O: Neo4jOperations>(&mut self, ops: &mut O, command: Command) {
+        match command {
+            Command::Param(name, expression) => match evaluate(ops, &expression, &self.params) {
+                Ok(value) => {
+                    println!("{} => {}", name, literal(&value));
+                    self.params.insert(name, value);
+                }
+                Err(e) => eprintln!("{}", e),
+            },
+            Command::Params => {
+                for (name, value) in &self.params {
+                    println!(":param {} => {}", name, literal(value));
+                }
+            }
+            Command::Help => print!("{}", HELP),
+            _ => unreachable!("{:?} is handled by the caller", command),
+        }
+    }
+
+    /// Reads lines until they make up a statement or command, or returns
+    /// `None` at the end of input. Interrupting a line drops the statement
+    /// in progress.
+    ///
+    fn read(&mut self, name: &str, in_transaction: bool) -> Option<Input> {
+        loop {
+            if let Some(input) = self.pending.pop_front() {
+                return Some(input);
+            }
+            let prompt = if !self.buffer.is_empty() {
+                format!("{}> ", " ".repeat(name.len()))
+            } else if in_transaction {
+                format!("{}# ", name)
+            } else {
+                format!("{}> ", name)
+            };
+            match self.editor.readline(&prompt) {
+                Ok(line) => {
+                    for input in self.buffer.push(&line) {
+                        let entry = match input {
+                            Input::Statement(ref statement) => format!("{};", statement),
+                            Input::Command(ref command) => command.clone(),
+                        };
+                        let _ = self.editor.add_history_entry(entry);
+                        self.pending.push_back(input);
+                    }
+                }
+                Err(ReadlineError::Interrupted) => self.buffer.clear(),
+                Err(ReadlineError::Eof) => return None,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn save_history(&mut self) {
+        if let Some(ref history) = self.history {
+            let _ = self.editor.save_history(history);
+        }
+    }
+}
+
+fn report<T, E: fmt::Display>(result: Result<T, E>) {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements_end_with_a_semicolon() {
+        let mut buffer = Buffer::default();
+        assert_eq!(buffer.push(""), vec![]);
+        assert_eq!(buffer.push("MATCH (n)"), vec![]);
+        assert_eq!(buffer.push(":not a command"), vec![]);
+        assert_eq!(
+            buffer.push("RETURN n; RETURN ';'; // done"),
+            vec![
+                Input::Statement(String::from("MATCH (n)\n:not a command\nRETURN n")),
+                Input::Statement(String::from("RETURN ';'")),
+            ]
+        );
+        assert!(buffer.is_empty());
+        assert_eq!(
+            buffer.push("  :use sales"),
+            vec![Input::Command(String::from(":use sales"))]
+        );
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse(":begin"), Ok(Command::Begin));
+        assert_eq!(Command::parse(":use"), Ok(Command::Use(None)));
+        assert_eq!(
+            Command::parse(":use `sales`"),
+            Ok(Command::Use(Some(String::from("sales"))))
+        );
+        assert_eq!(
+            Command::parse(":param limit => 10 * 2"),
+            Ok(Command::Param(
+                String::from("limit"),
+                String::from("10 * 2")
+            ))
+        );
+        assert!(Command::parse(":param limit").is_err());
+        assert!(Command::parse(":commit now").is_err());
+        assert!(Command::parse(":frobnicate").is_err());
+    }
+}
@@ -0,0 +1,632 @@
+//! A scripted Bolt server for testing clients without a database. A
+//! `BoltStub` listens on localhost, accepts one connection, and plays
+//! through a `Script`: each message the client sends must be the next one
+//! the script expects, and the server's replies are sent back as written.
+//! Once the client has gone, `finish` reports whether the conversation went
+//! as scripted. A stub dropped without calling `finish` panics instead, so
+//! a test fails as soon as its client strays from the script.
+//!
+//! `start_all` plays several scripts, one connection each, for clients
+//! that reconnect. A script marked `!: ALLOW RESTART` or `!: ALLOW
+//! CONCURRENT` is played again to every client that connects, one at a
+//! time or all at once, as for a connection pool or a cluster member.
+//!
+//! ```no_run
+//! use boltstub::BoltStub;
+//!
+//! let stub = BoltStub::start(
+//!     r#"
+//!     !: BOLT 4.4
+//!     C: HELLO *
+//!     S: SUCCESS {"server": "Neo4j/4.4.0"}
+//!     C: GOODBYE
+//!     S: <EXIT>
+//!     "#,
+//! )
+//! .unwrap();
+//! // ... point a client at stub.address() ...
+//! stub.finish().unwrap();
+//! ```
+
+mod script;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    panic,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use packstream::Value;
+
+use script::{describe, Expected, Line, GOODBYE, SUCCESS};
+pub use script::{Script, ScriptError};
+
+/// How long the server waits for the client to connect, or for its next
+/// message, before giving up.
+///
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAX_CHUNK_SIZE: usize = 0xFFFF;
+
+#[derive(Debug)]
+pub enum StubError {
+    Script(ScriptError),
+    Io(io::Error),
+    Handshake(String),
+    /// A script was left unplayed because no client connected for it
+    /// before the timeout, or before the stub was finished.
+    NoConnection,
+    /// The client sent a message other than the one the script expected
+    /// next. `expected` is `None` if the script had already ended.
+    Unexpected {
+        expected: Option<String>,
+        received: String,
+    },
+    /// The client hung up, or went quiet, while the script still expected
+    /// a message from it.
+    Incomplete {
+        expected: String,
+    },
+}
+
+impl fmt::Display for StubError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StubError::Script(ref err) => write!(f, "{}", err),
+            StubError::Io(ref err) => write!(f, "Socket error: {}", err),
+            StubError::Handshake(ref err) => write!(f, "Handshake error: {}", err),
+            StubError::NoConnection => write!(f, "No client connected"),
+            StubError::Unexpected {
+                ref expected,
+                ref received,
+            } => match *expected {
+                Some(ref expected) => {
+                    write!(f, "Expected {} but received C: {}", expected, received)
+                }
+                None => write!(f, "Script ended but received C: {}", received),
+            },
+            StubError::Incomplete { ref expected } => {
+                write!(f, "Client stopped before sending {}", expected)
+            }
+        }
+    }
+}
+
+impl Error for StubError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            StubError::Script(ref err) => Some(err),
+            StubError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScriptError> for StubError {
+    fn from(val: ScriptError) -> Self {
+        StubError::Script(val)
+    }
+}
+
+impl From<io::Error> for StubError {
+    fn from(val: io::Error) -> Self {
+        StubError::Io(val)
+    }
+}
+
+pub struct BoltStub {
+    address: String,
+    connections: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    server: Option<JoinHandle<Result<(), StubError>>>,
+}
+
+impl BoltStub {
+    /// Parses `script` and starts serving it.
+    ///
+    pub fn start(script: &str) -> Result<BoltStub, StubError> {
+        BoltStub::start_all(&[script])
+    }
+
+    /// Parses `scripts` and starts serving them, one connection per script
+    /// in the order the connections arrive.
+    ///
+    pub fn start_all(scripts: &[&str]) -> Result<BoltStub, StubError> {
+        let scripts = scripts
+            .iter()
+            .map(|script| script.parse())
+            .collect::<Result<_, ScriptError>>()?;
+        Ok(BoltStub::serve_on(
+            TcpListener::bind("127.0.0.1:0")?,
+            scripts,
+        )?)
+    }
+
+    /// Starts serving `script` on a free localhost port.
+    ///
+    pub fn serve(script: Script) -> io::Result<BoltStub> {
+        BoltStub::serve_on(TcpListener::bind("127.0.0.1:0")?, vec![script])
+    }
+
+    /// Serves `scripts` on a listener bound beforehand, for scripts that
+    /// need to know the server's own address. Each script is played to its
+    /// own connection, in turn; if the last one allows a restart, it is
+    /// played again to every later connection until the stub is finished.
+    ///
+    pub fn serve_on(listener: TcpListener, scripts: Vec<Script>) -> io::Result<BoltStub> {
+        if scripts.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A stub needs at least one script",
+            ));
+        }
+        let address = listener.local_addr()?.to_string();
+        listener.set_nonblocking(true)?;
+        let connections = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = {
+            let connections = connections.clone();
+            let stop = stop.clone();
+            thread::spawn(move || serve(listener, &scripts, &connections, &stop))
+        };
+        Ok(BoltStub {
+            address,
+            connections,
+            stop,
+            server: Some(server),
+        })
+    }
+
+    /// The `host:port` the server is listening on.
+    ///
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The number of connections accepted so far.
+    ///
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting connections, waits for every conversation to end and
+    /// reports whether the clients kept to the script. The clients should
+    /// be closed first.
+    ///
+    pub fn finish(mut self) -> Result<(), StubError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), StubError> {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.server.take() {
+            Some(server) => server
+                .join()
+                .unwrap_or_else(|panic| panic::resume_unwind(panic)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for BoltStub {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            return;
+        }
+        if let Err(err) = self.join() {
+            panic!("{}", err);
+        }
+    }
+}
+
+/// Plays each script to the next client, then goes on replaying the last
+/// one if it allows that, until the stub is finished. Conversations run
+/// one after another, or side by side if the script allows concurrent
+/// connections. The first failure is reported once they have all ended.
+///
+fn serve(
+    listener: TcpListener,
+    scripts: &[Script],
+    connections: &AtomicUsize,
+    stop: &AtomicBool,
+) -> Result<(), StubError> {
+    let mut conversations = Vec::new();
+    let mut result = Ok(());
+    for n in 0.. {
+        let script = match scripts.get(n) {
+            Some(script) => script,
+            None => match scripts.last() {
+                Some(script) if script.restart || script.concurrent => script,
+                _ => break,
+            },
+        };
+        let socket = match accept(&listener, stop, n < scripts.len()) {
+            Ok(Some(socket)) => socket,
+            Ok(None) => break,
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        };
+        connections.fetch_add(1, Ordering::SeqCst);
+        if script.concurrent {
+            let script = script.clone();
+            conversations.push(thread::spawn(move || {
+                Conversation::new(socket, &script)?.play()
+            }));
+        } else if let Err(err) = Conversation::new(socket, script).and_then(|mut c| c.play()) {
+            result = Err(err);
+            break;
+        }
+    }
+    // refuse anyone else rather than leave them waiting for a handshake
+    drop(listener);
+    for conversation in conversations {
+        let outcome = conversation
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic));
+        result = result.and(outcome);
+    }
+    result
+}
+
+/// Waits for the next client, or returns `None` once the stub has been
+/// finished. A `required` client must turn up within the timeout, and
+/// before the stub is finished.
+///
+fn accept(
+    listener: &TcpListener,
+    stop: &AtomicBool,
+    required: bool,
+) -> Result<Option<TcpStream>, StubError> {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        // read before accepting, so that a client that connected just before
+        // the stub was finished is still served
+        let stopping = stop.load(Ordering::SeqCst);
+        match listener.accept() {
+            Ok((socket, _)) => {
+                socket.set_nonblocking(false)?;
+                socket.set_nodelay(true)?;
+                socket.set_read_timeout(Some(TIMEOUT))?;
+                return Ok(Some(socket));
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if required && (stopping || Instant::now() > deadline) {
+                    return Err(StubError::NoConnection);
+                }
+                if stopping {
+                    return Ok(None);
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+struct Conversation<'a> {
+    socket: TcpStream,
+    script: &'a Script,
+}
+
+impl<'a> Conversation<'a> {
+    fn new(mut socket: TcpStream, script: &'a Script) -> Result<Self, StubError> {
+        let mut handshake = [0; 20];
+        socket.read_exact(&mut handshake)?;
+        if handshake[..4] != [0x60, 0x60, 0xB0, 0x17] {
+            return Err(StubError::Handshake(format!(
+                "bad preamble {:02X?}",
+                &handshake[..4]
+            )));
+        }
+        let (major, minor) = script.version;
+        let offered = handshake[4..].chunks(4).any(|proposal| {
+            // [_, range, minor, major] covers minor-range up to minor
+            proposal[3] == major
+                && minor <= proposal[2]
+                && minor >= proposal[2].saturating_sub(proposal[1])
+        });
+        if !offered {
+            socket.write_all(&[0; 4])?;
+            return Err(StubError::Handshake(format!(
+                "client did not offer Bolt {}.{}: {:02X?}",
+                major,
+                minor,
+                &handshake[4..]
+            )));
+        }
+        socket.write_all(&[0, 0, minor, major])?;
+        Ok(Conversation { socket, script })
+    }
+
+    fn play(&mut self) -> Result<(), StubError> {
+        let mut lines = self.script.lines.iter().peekable();
+        loop {
+            let expected = match lines.peek().copied() {
+                Some(Line::Server(reply)) => {
+                    self.send(reply)?;
+                    lines.next();
+                    continue;
+                }
+                Some(Line::Exit) => return Ok(()),
+                Some(Line::Client(expected)) => Some(expected),
+                None => None,
+            };
+            let message = match read_message(&mut self.socket) {
+                Ok(Some(message)) => message,
+                // once the script is done, the client may hang up however it
+                // likes, or stay connected until the test is over
+                Ok(None) => return done(expected),
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::ConnectionReset
+                        || err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return done(expected)
+                }
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::UnexpectedEof && expected.is_some() =>
+                {
+                    return done(expected)
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let request = Value::unpack(&mut &message[..])
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+            let signature = match request {
+                Value::Structure { signature, .. } => signature,
+                _ => 0,
+            };
+            if expected.is_some_and(|e| e.matches(&request)) {
+                lines.next();
+            } else if self.script.auto.contains(&signature) {
+                self.send(&Value::Structure {
+                    signature: SUCCESS,
+                    fields: vec![Value::Map(HashMap::new())],
+                })?;
+            } else if expected.is_none() && signature == GOODBYE {
+                continue;
+            } else {
+                return Err(StubError::Unexpected {
+                    expected: expected.map(Expected::to_string),
+                    received: describe(&request),
+                });
+            }
+        }
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let data = message
+            .clone()
+            .pack_into()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        let mut out = Vec::with_capacity(data.len() + 4);
+        for chunk in data.chunks(MAX_CHUNK_SIZE) {
+            out.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            out.extend_from_slice(chunk);
+        }
+        out.extend_from_slice(&[0, 0]);
+        self.socket.write_all(&out)
+    }
+}
+
+/// Reads one message, or `None` if the stream closed between messages.
+///
+fn read_message<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut message = Vec::new();
+    let mut header = [0; 2];
+    match reader.read(&mut header[..1])? {
+        0 => return Ok(None),
+        _ => reader.read_exact(&mut header[1..])?,
+    }
+    loop {
+        let size = u16::from_be_bytes(header);
+        if size == 0 {
+            return Ok(Some(message));
+        }
+        reader.take(u64::from(size)).read_to_end(&mut message)?;
+        reader.read_exact(&mut header)?;
+    }
+}
+
+/// The end of a conversation, which is only a success if the script was
+/// not still waiting for `expected`.
+///
+fn done(expected: Option<&Expected>) -> Result<(), StubError> {
+    match expected {
+        Some(expected) => Err(StubError::Incomplete {
+            expected: expected.to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Just enough of a client to talk to the stub.
+    ///
+    struct Client(TcpStream);
+
+    impl Client {
+        fn connect(stub: &BoltStub, proposal: [u8; 4]) -> io::Result<(Client, [u8; 4])> {
+            let mut socket = TcpStream::connect(stub.address())?;
+            socket.write_all(&[0x60, 0x60, 0xB0, 0x17])?;
+            socket.write_all(&proposal)?;
+            socket.write_all(&[0; 12])?;
+            let mut agreed = [0; 4];
+            socket.read_exact(&mut agreed)?;
+            Ok((Client(socket), agreed))
+        }
+
+        fn send(&mut self, signature: u8, fields: Vec<Value>) {
+            let data = Value::Structure { signature, fields }.pack_into().unwrap();
+            let mut out = (data.len() as u16).to_be_bytes().to_vec();
+            out.extend_from_slice(&data);
+            out.extend_from_slice(&[0, 0]);
+            self.0.write_all(&out).unwrap();
+        }
+
+        fn recv(&mut self) -> (u8, Vec<Value>) {
+            let message = read_message(&mut self.0).unwrap().unwrap();
+            match Value::unpack(&mut &message[..]).unwrap() {
+                Value::Structure { signature, fields } => (signature, fields),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    const SCRIPT: &str = r#"
+        !: BOLT 4.4
+        !: AUTO RESET
+
+        C: RUN "RETURN $x AS x" {"x": 1} *
+           PULL {"n": -1, "qid": -1}
+        S: SUCCESS {"fields": ["x"]}
+           RECORD [1]
+           SUCCESS {"type": "r"}
+    "#;
+
+    fn run(client: &mut Client, x: i64) {
+        let mut parameters = HashMap::new();
+        parameters.insert(String::from("x"), Value::from(x));
+        client.send(
+            0x10,
+            vec![
+                "RETURN $x AS x".into(),
+                Value::Map(parameters),
+                Value::Map(HashMap::new()),
+            ],
+        );
+        let mut pull = HashMap::new();
+        pull.insert(String::from("n"), Value::from(-1));
+        pull.insert(String::from("qid"), Value::from(-1));
+        client.send(0x3F, vec![Value::Map(pull)]);
+    }
+
+    #[test]
+    fn plays_through_the_script() {
+        let stub = BoltStub::start(SCRIPT).unwrap();
+        let (mut client, agreed) = Client::connect(&stub, [0x00, 0x02, 0x04, 0x04]).unwrap();
+        assert_eq!(agreed, [0x00, 0x00, 0x04, 0x04]);
+        client.send(0x0F, vec![]);
+        assert_eq!(client.recv().0, 0x70);
+        run(&mut client, 1);
+        assert_eq!(client.recv().0, 0x70);
+        assert_eq!(client.recv(), (0x71, vec![vec![1].into()]));
+        assert_eq!(client.recv().0, 0x70);
+        client.send(0x02, vec![]);
+        drop(client);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn fails_on_the_wrong_message() {
+        let stub = BoltStub::start(SCRIPT).unwrap();
+        let (mut client, _) = Client::connect(&stub, [0x00, 0x00, 0x04, 0x04]).unwrap();
+        run(&mut client, 2);
+        match stub.finish() {
+            Err(StubError::Unexpected {
+                expected: Some(expected),
+                received,
+            }) => {
+                assert!(expected.contains("(line 5)"), "{}", expected);
+                assert!(received.starts_with("RUN \"RETURN $x AS x\""));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fails_when_the_client_stops_early() {
+        let stub = BoltStub::start(SCRIPT).unwrap();
+        let (client, _) = Client::connect(&stub, [0x00, 0x00, 0x04, 0x04]).unwrap();
+        drop(client);
+        match stub.finish() {
+            Err(StubError::Incomplete { expected }) => {
+                assert!(expected.starts_with("C: RUN"), "{}", expected)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refuses_versions_not_offered() {
+        let stub = BoltStub::start(SCRIPT).unwrap();
+        let (_, agreed) = Client::connect(&stub, [0x00, 0x00, 0x02, 0x04]).unwrap();
+        assert_eq!(agreed, [0; 4]);
+        match stub.finish() {
+            Err(StubError::Handshake(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn plays_each_script_in_turn() {
+        let stub = BoltStub::start_all(&[
+            "!: BOLT 4.4\nC: RESET\nS: <EXIT>",
+            "!: BOLT 4.4\nC: COMMIT\nS: SUCCESS {}",
+        ])
+        .unwrap();
+        let (mut client, _) = Client::connect(&stub, [0x00, 0x00, 0x04, 0x04]).unwrap();
+        client.send(0x0F, vec![]);
+        assert!(read_message(&mut client.0).unwrap().is_none());
+        let (mut client, _) = Client::connect(&stub, [0x00, 0x00, 0x04, 0x04]).unwrap();
+        client.send(0x12, vec![]);
+        assert_eq!(client.recv().0, 0x70);
+        drop(client);
+        assert_eq!(stub.connections(), 2);
+        stub.finish().unwrap();
+
+        let stub = BoltStub::start_all(&["!: BOLT 4.4", "!: BOLT 4.4"]).unwrap();
+        drop(Client::connect(&stub, [0x00, 0x00, 0x04, 0x04]).unwrap());
+        match stub.finish() {
+            Err(StubError::NoConnection) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replays_scripts_that_allow_it() {
+        for directive in &["RESTART", "CONCURRENT"] {
+            let stub = BoltStub::start(&format!(
+                "!: BOLT 4.4\n!: ALLOW {}\nC: RESET\nS: SUCCESS {{}}",
+                directive
+            ))
+            .unwrap();
+            let mut clients = Vec::new();
+            for _ in 0..3 {
+                let (mut client, _) = Client::connect(&stub, [0x00, 0x00, 0x04, 0x04]).unwrap();
+                client.send(0x0F, vec![]);
+                assert_eq!(client.recv().0, 0x70);
+                if *directive == "CONCURRENT" {
+                    // keep each client connected while the next one talks
+                    clients.push(client);
+                }
+            }
+            drop(clients);
+            assert_eq!(stub.connections(), 3);
+            stub.finish().unwrap();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Script ended but received C: COMMIT")]
+    fn dropping_a_failed_stub_panics() {
+        let stub = BoltStub::start("!: BOLT 3\nS: SUCCESS {}").unwrap();
+        let (mut client, _) = Client::connect(&stub, [0x00, 0x00, 0x00, 0x03]).unwrap();
+        client.send(0x12, vec![]);
+        drop(stub);
+    }
+}
@@ -0,0 +1,578 @@
+use std::{collections::HashMap, error::Error, fmt, str::FromStr};
+
+use packstream::Value;
+
+/// Message names as they appear in scripts. Where two names share a
+/// signature, the first is the one used when reporting a message.
+///
+const CLIENT_MESSAGES: &[(&str, u8)] = &[
+    ("HELLO", 0x01),
+    ("INIT", 0x01),
+    ("GOODBYE", 0x02),
+    ("ACK_FAILURE", 0x0E),
+    ("RESET", 0x0F),
+    ("RUN", 0x10),
+    ("BEGIN", 0x11),
+    ("COMMIT", 0x12),
+    ("ROLLBACK", 0x13),
+    ("DISCARD", 0x2F),
+    ("DISCARD_ALL", 0x2F),
+    ("PULL", 0x3F),
+    ("PULL_ALL", 0x3F),
+    ("ROUTE", 0x66),
+];
+
+const SERVER_MESSAGES: &[(&str, u8)] = &[
+    ("SUCCESS", 0x70),
+    ("RECORD", 0x71),
+    ("IGNORED", 0x7E),
+    ("FAILURE", 0x7F),
+];
+
+pub(crate) const GOODBYE: u8 = 0x02;
+pub(crate) const SUCCESS: u8 = 0x70;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ScriptError {
+    fn new<S: Into<String>>(line: usize, message: S) -> Self {
+        ScriptError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Script error on line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ScriptError {}
+
+/// A conversation for the stub server to hold with one client. Scripts are
+/// written one message per line:
+///
+/// ```text
+/// !: BOLT 4.4
+/// !: AUTO RESET
+///
+/// C: HELLO {"user_agent": *, "scheme": "basic", "principal": "neo4j", "credentials": *}
+/// S: SUCCESS {"server": "Neo4j/4.4.0"}
+/// C: RUN "RETURN 1 AS n" {} {}
+///    PULL {"n": -1, "qid": -1}
+/// S: SUCCESS {"fields": ["n"]}
+///    RECORD [1]
+///    SUCCESS {}
+/// ```
+///
+/// `C:` lines are messages the client must send, in order, and `S:` lines
+/// are sent back once every client message before them has arrived. An
+/// indented line carries on with the same side. Fields are written much
+/// like JSON, and `*` in a client message matches any value. `S: <EXIT>`
+/// hangs up. `!: BOLT x.y` picks the protocol version to agree to and
+/// `!: AUTO NAME` answers that message with an empty SUCCESS wherever it
+/// turns up unscripted. `!: ALLOW RESTART` plays the script again to each
+/// new connection once the last has ended, and `!: ALLOW CONCURRENT` to
+/// each new connection straight away. Lines starting with `#` are
+/// comments.
+///
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub(crate) version: (u8, u8),
+    pub(crate) auto: Vec<u8>,
+    pub(crate) restart: bool,
+    pub(crate) concurrent: bool,
+    pub(crate) lines: Vec<Line>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Script, ScriptError> {
+        let mut version = None;
+        let mut auto = Vec::new();
+        let mut restart = false;
+        let mut concurrent = false;
+        let mut lines = Vec::new();
+        let mut side = None;
+        for (i, raw) in text.lines().enumerate() {
+            let number = i + 1;
+            let trimmed = raw.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let body = if let Some(rest) = trimmed.strip_prefix("!:") {
+                side = None;
+                let mut words = rest.split_whitespace();
+                match (words.next(), words.next(), words.next()) {
+                    (Some("BOLT"), Some(v), None) => {
+                        version = Some(parse_version(v).ok_or_else(|| {
+                            ScriptError::new(number, format!("bad version {:?}", v))
+                        })?)
+                    }
+                    (Some("AUTO"), Some(name), None) => {
+                        auto.push(lookup(CLIENT_MESSAGES, name).ok_or_else(|| {
+                            ScriptError::new(number, format!("unknown client message {:?}", name))
+                        })?)
+                    }
+                    (Some("ALLOW"), Some("RESTART"), None) => restart = true,
+                    (Some("ALLOW"), Some("CONCURRENT"), None) => concurrent = true,
+                    _ => return Err(ScriptError::new(number, "unknown directive")),
+                }
+                continue;
+            } else if let Some(rest) = trimmed.strip_prefix("C:") {
+                side = Some('C');
+                rest.trim()
+            } else if let Some(rest) = trimmed.strip_prefix("S:") {
+                side = Some('S');
+                rest.trim()
+            } else if raw.starts_with(char::is_whitespace) && side.is_some() {
+                trimmed
+            } else {
+                return Err(ScriptError::new(number, "expected C:, S: or !:"));
+            };
+            let line = match side {
+                Some('C') => Line::Client(Expected::parse(number, body)?),
+                _ if body == "<EXIT>" => Line::Exit,
+                _ => Line::Server(parse_server(number, body)?),
+            };
+            lines.push(line);
+        }
+        let version = version.ok_or_else(|| ScriptError::new(1, "no !: BOLT version given"))?;
+        Ok(Script {
+            version,
+            auto,
+            restart,
+            concurrent,
+            lines,
+        })
+    }
+}
+
+impl FromStr for Script {
+    type Err = ScriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Script::parse(s)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Line {
+    Client(Expected),
+    Server(Value),
+    Exit,
+}
+
+/// A client message the script is waiting for.
+///
+#[derive(Debug, Clone)]
+pub(crate) struct Expected {
+    pub number: usize,
+    pub text: String,
+    signature: u8,
+    fields: Vec<Pattern>,
+}
+
+impl Expected {
+    fn parse(number: usize, body: &str) -> Result<Self, ScriptError> {
+        let (signature, fields) = parse_message(number, body, CLIENT_MESSAGES)?;
+        Ok(Expected {
+            number,
+            text: String::from(body),
+            signature,
+            fields,
+        })
+    }
+
+    pub fn matches(&self, message: &Value) -> bool {
+        match *message {
+            Value::Structure {
+                signature,
+                ref fields,
+            } => {
+                signature == self.signature
+                    && fields.len() == self.fields.len()
+                    && self.fields.iter().zip(fields).all(|(p, v)| p.matches(v))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "C: {} (line {})", self.text, self.number)
+    }
+}
+
+/// Writes a received message the way a script would.
+///
+pub(crate) fn describe(message: &Value) -> String {
+    match *message {
+        Value::Structure {
+            signature,
+            ref fields,
+        } => {
+            let mut text = match CLIENT_MESSAGES.iter().find(|&&(_, s)| s == signature) {
+                Some(&(name, _)) => String::from(name),
+                None => format!("#{:02X}", signature),
+            };
+            for field in fields {
+                text.push_str(&format!(" {:?}", field));
+            }
+            text
+        }
+        _ => format!("{:?}", message),
+    }
+}
+
+/// A field of an expected message: a value, or `*` for anything.
+///
+#[derive(Debug, Clone)]
+enum Pattern {
+    Any,
+    Value(Value),
+    List(Vec<Pattern>),
+    Map(HashMap<String, Pattern>),
+}
+
+impl Pattern {
+    fn matches(&self, value: &Value) -> bool {
+        match (self, value) {
+            (Pattern::Any, _) => true,
+            (Pattern::Value(expected), value) => expected == value,
+            (Pattern::List(patterns), Value::List(values)) => {
+                patterns.len() == values.len()
+                    && patterns.iter().zip(values).all(|(p, v)| p.matches(v))
+            }
+            (Pattern::Map(patterns), Value::Map(values)) => {
+                patterns.len() == values.len()
+                    && patterns
+                        .iter()
+                        .all(|(k, p)| values.get(k).is_some_and(|v| p.matches(v)))
+            }
+            _ => false,
+        }
+    }
+
+    /// The value this pattern stands for, unless it contains a wildcard.
+    ///
+    fn into_value(self) -> Option<Value> {
+        match self {
+            Pattern::Any => None,
+            Pattern::Value(value) => Some(value),
+            Pattern::List(patterns) => patterns
+                .into_iter()
+                .map(Pattern::into_value)
+                .collect::<Option<Vec<_>>>()
+                .map(Value::List),
+            Pattern::Map(patterns) => patterns
+                .into_iter()
+                .map(|(k, p)| p.into_value().map(|v| (k, v)))
+                .collect::<Option<HashMap<_, _>>>()
+                .map(Value::Map),
+        }
+    }
+}
+
+fn lookup(names: &[(&str, u8)], name: &str) -> Option<u8> {
+    names.iter().find(|&&(n, _)| n == name).map(|&(_, s)| s)
+}
+
+fn parse_version(text: &str) -> Option<(u8, u8)> {
+    let mut parts = text.splitn(2, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = match parts.next() {
+        Some(minor) => minor.parse().ok()?,
+        None => 0,
+    };
+    Some((major, minor))
+}
+
+fn parse_message(
+    number: usize,
+    body: &str,
+    names: &[(&str, u8)],
+) -> Result<(u8, Vec<Pattern>), ScriptError> {
+    let name_end = body.find(char::is_whitespace).unwrap_or(body.len());
+    let name = &body[..name_end];
+    let signature = lookup(names, name)
+        .ok_or_else(|| ScriptError::new(number, format!("unknown message {:?}", name)))?;
+    let mut parser = Parser {
+        text: body,
+        pos: name_end,
+    };
+    let mut fields = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        if parser.pos == body.len() {
+            break;
+        }
+        fields.push(
+            parser
+                .pattern()
+                .map_err(|message| ScriptError::new(number, message))?,
+        );
+    }
+    Ok((signature, fields))
+}
+
+fn parse_server(number: usize, body: &str) -> Result<Value, ScriptError> {
+    let (signature, patterns) = parse_message(number, body, SERVER_MESSAGES)?;
+    let fields = patterns
+        .into_iter()
+        .map(Pattern::into_value)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| ScriptError::new(number, "* can only be used in client messages"))?;
+    Ok(Value::Structure { signature, fields })
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected {:?} but found {:?}", expected, c)),
+            None => Err(format!("expected {:?} but the line ended", expected)),
+        }
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                Ok(Pattern::Any)
+            }
+            Some('"') => Ok(Pattern::Value(Value::String(self.string()?))),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                while !self.close(']', items.is_empty())? {
+                    items.push(self.pattern()?);
+                }
+                Ok(list_or_value(items))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut entries = HashMap::new();
+                while !self.close('}', entries.is_empty())? {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    entries.insert(key, self.pattern()?);
+                }
+                Ok(map_or_value(entries))
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+                    self.pos += 1;
+                }
+                match &self.text[start..self.pos] {
+                    "null" => Ok(Pattern::Value(Value::Null)),
+                    "true" => Ok(Pattern::Value(Value::Boolean(true))),
+                    "false" => Ok(Pattern::Value(Value::Boolean(false))),
+                    word => Err(format!("unexpected {:?}", word)),
+                }
+            }
+            Some(c) => Err(format!("unexpected {:?}", c)),
+            None => Err(String::from("expected a value but the line ended")),
+        }
+    }
+
+    /// Steps over the separator between items of a list or map, returning
+    /// whether `end` closed it instead.
+    ///
+    fn close(&mut self, end: char, first: bool) -> Result<bool, String> {
+        self.skip_whitespace();
+        if self.peek() == Some(end) {
+            self.pos += 1;
+            return Ok(true);
+        }
+        if !first {
+            self.expect(',')?;
+            self.skip_whitespace();
+        }
+        Ok(false)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.bump() != Some('"') {
+            return Err(String::from("expected a string"));
+        }
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(string),
+                Some('\\') => string.push(match self.bump() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let end = self.pos + 4;
+                        let code = self
+                            .text
+                            .get(self.pos..end)
+                            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| String::from("bad \\u escape"))?;
+                        self.pos = end;
+                        code
+                    }
+                    Some(c @ '"') | Some(c @ '\\') | Some(c @ '/') => c,
+                    _ => return Err(String::from("bad escape")),
+                }),
+                Some(c) => string.push(c),
+                None => return Err(String::from("unterminated string")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Pattern, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        {
+            self.pos += 1;
+        }
+        let text = &self.text[start..self.pos];
+        let value = if text.contains(['.', 'e', 'E']) {
+            text.parse().map(Value::Float).ok()
+        } else {
+            text.parse().map(Value::Integer).ok()
+        };
+        value
+            .map(Pattern::Value)
+            .ok_or_else(|| format!("bad number {:?}", text))
+    }
+}
+
+fn list_or_value(items: Vec<Pattern>) -> Pattern {
+    match Pattern::List(items.clone()).into_value() {
+        Some(value) => Pattern::Value(value),
+        None => Pattern::List(items),
+    }
+}
+
+fn map_or_value(entries: HashMap<String, Pattern>) -> Pattern {
+    match Pattern::Map(entries.clone()).into_value() {
+        Some(value) => Pattern::Value(value),
+        None => Pattern::Map(entries),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(signature: u8, fields: Vec<Value>) -> Value {
+        Value::Structure { signature, fields }
+    }
+
+    #[test]
+    fn parses_values() {
+        let script = Script::parse(
+            r#"!: BOLT 4
+            S: SUCCESS {"fields": ["n", "m"], "t_first": 12, "ok": true, "x": -1.5e3, "s": "a\"bé", "none": null}"#,
+        )
+        .unwrap();
+        assert_eq!(script.version, (4, 0));
+        let mut metadata = HashMap::new();
+        metadata.insert(String::from("fields"), vec!["n", "m"].into());
+        metadata.insert(String::from("t_first"), Value::Integer(12));
+        metadata.insert(String::from("ok"), Value::Boolean(true));
+        metadata.insert(String::from("x"), Value::Float(-1500.0));
+        metadata.insert(String::from("s"), "a\"b\u{e9}".into());
+        metadata.insert(String::from("none"), Value::Null);
+        match script.lines[0] {
+            Line::Server(ref value) => {
+                assert_eq!(*value, message(0x70, vec![Value::Map(metadata)]))
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn continuation_lines_keep_their_side() {
+        let script = Script::parse(
+            "!: BOLT 3\n!: AUTO RESET\n!: ALLOW RESTART\nC: RUN \"RETURN 1\" {} {}\n   PULL_ALL\nS: SUCCESS {}\n   <EXIT>",
+        )
+        .unwrap();
+        assert_eq!(script.auto, vec![0x0F]);
+        assert!(script.restart && !script.concurrent);
+        match script.lines[..] {
+            [Line::Client(ref run), Line::Client(ref pull), Line::Server(_), Line::Exit] => {
+                assert_eq!(run.number, 4);
+                assert!(pull.matches(&message(0x3F, vec![])));
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wildcards_match_anything() {
+        let script = Script::parse(
+            r#"!: BOLT 4.4
+            C: HELLO {"user_agent": *, "principal": "neo4j"}"#,
+        )
+        .unwrap();
+        let expected = match script.lines[0] {
+            Line::Client(ref expected) => expected.clone(),
+            ref other => panic!("unexpected {:?}", other),
+        };
+        let hello = |principal: &str| {
+            let mut extra = HashMap::new();
+            extra.insert(String::from("user_agent"), Value::from("test/1.0"));
+            extra.insert(String::from("principal"), Value::from(principal));
+            message(0x01, vec![Value::Map(extra)])
+        };
+        assert!(expected.matches(&hello("neo4j")));
+        assert!(!expected.matches(&hello("admin")));
+        assert!(!expected.matches(&message(0x01, vec![])));
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let error = Script::parse("!: BOLT 4.4\nC: HELLO {}\nS: SUCCESS {\"a\": *}").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(
+            Script::parse("!: BOLT 4.4\nC: FETCH").unwrap_err(),
+            ScriptError::new(2, "unknown message \"FETCH\"")
+        );
+        assert_eq!(
+            Script::parse("C: RESET").unwrap_err().message,
+            "no !: BOLT version given"
+        );
+        assert!(Script::parse("!: BOLT 4.4\nC: RUN \"RETURN 1\" {").is_err());
+        assert!(Script::parse("!: BOLT 4.4\n  RESET").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::{io, time::Duration};
+
+use log::debug;
+use packstream::Value;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+    time::timeout,
+};
+
+use super::{chunk::AsyncChunkStream, transport::AsyncTransport};
+use crate::bolt::{
+    BoltError, ConnectOptions, ProtocolVersion, Request, Response, Result, HANDSHAKE,
+};
+
+/// The async counterpart of `BoltStream`. Requests are queued with `push`
+/// and written together by `send`; responses are read one message at a
+/// time with `receive`.
+///
+/// A read or write that fails or is cut short, by the read timeout or by
+/// its future being dropped, can stop part-way through a message. The
+/// stream is then broken: every later call fails with a socket error
+/// rather than reading from the middle of a message.
+///
+pub struct AsyncBoltStream<T = AsyncTransport> {
+    stream: AsyncChunkStream<T>,
+    requests: Vec<Vec<u8>>,
+    read_timeout: Option<Duration>,
+    protocol_version: ProtocolVersion,
+    broken: bool,
+}
+
+impl AsyncBoltStream<AsyncTransport> {
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> Result<Self> {
+        AsyncBoltStream::open(address, "", &ConnectOptions::default()).await
+    }
+
+    /// Connects with the given socket settings. `host` is only used to
+    /// verify the server certificate when TLS is enabled, which needs the
+    /// `tokio-rustls` feature.
+    ///
+    pub async fn open<A: ToSocketAddrs>(
+        address: A,
+        host: &str,
+        options: &ConnectOptions,
+    ) -> Result<Self> {
+        let connect_error = |err| BoltError::Connect(format!("Error on connect: {:?}", err));
+        let socket = match options.connect_timeout {
+            None => TcpStream::connect(address).await.map_err(connect_error)?,
+            Some(limit) => timeout(limit, TcpStream::connect(address))
+                .await
+                .map_err(|_| connect_error(io::ErrorKind::TimedOut.into()))?
+                .map_err(connect_error)?,
+        };
+        // messages are written a chunk at a time, so don't hold them back
+        socket.set_nodelay(true)?;
+        #[cfg(feature = "tokio-rustls")]
+        let transport = match options.tls {
+            Some(ref config) => {
+                AsyncTransport::Tls(Box::new(config.connect_async(host, socket).await?))
+            }
+            None => AsyncTransport::Plain(socket),
+        };
+        #[cfg(not(feature = "tokio-rustls"))]
+        let transport = {
+            let _ = host;
+            #[cfg(feature = "rustls")]
+            if options.tls.is_some() {
+                return Err(BoltError::Tls(String::from(
+                    "Encrypted async connections need the tokio-rustls feature",
+                )));
+            }
+            AsyncTransport::Plain(socket)
+        };
+        let mut bolt = AsyncBoltStream::handshake(transport).await?;
+        bolt.read_timeout = options.read_timeout;
+        bolt.stream.set_max_message_size(options.max_message_size);
+        Ok(bolt)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncBoltStream<T> {
+    /// Agrees a protocol version over an already connected stream.
+    ///
+    pub async fn handshake(mut stream: T) -> Result<Self> {
+        debug!("C: <HANDSHAKE {:02X?}>", &HANDSHAKE[4..]);
+        stream
+            .write_all(&HANDSHAKE)
+            .await
+            .map_err(|_| BoltError::Handshake(String::from("Error on write")))?;
+        let raw = stream
+            .read_u32()
+            .await
+            .map_err(|_| BoltError::Handshake(String::from("Error on read")))?;
+        Ok(AsyncBoltStream {
+            stream: AsyncChunkStream::new(stream),
+            requests: Vec::new(),
+            read_timeout: None,
+            protocol_version: ProtocolVersion::negotiate(raw)?,
+            broken: false,
+        })
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    /// Queues a request to go out with the next `send`.
+    ///
+    pub fn push(&mut self, request: Request) -> Result<()> {
+        self.requests.push(request.pack()?);
+        Ok(())
+    }
+
+    /// Whether an earlier read or write stopped part-way through a message,
+    /// leaving the connection unusable.
+    ///
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Send all queued outgoing messages.
+    ///
+    pub async fn send(&mut self) -> Result<()> {
+        debug!("C: <SEND>");
+        self.check_broken()?;
+        let requests = std::mem::take(&mut self.requests);
+        // stays set if the write fails or is cancelled
+        self.broken = true;
+        self.stream.send(requests.iter().map(|r| &r[..])).await?;
+        self.broken = false;
+        Ok(())
+    }
+
+    /// Reads the next response message, waiting no longer than the read
+    /// timeout. A timeout breaks the connection.
+    ///
+    pub async fn receive(&mut self) -> Result<Response> {
+        self.check_broken()?;
+        // stays set if the read fails, times out or is cancelled
+        self.broken = true;
+        let message = match self.read_timeout {
+            None => self.stream.recv().await?,
+            Some(limit) => timeout(limit, self.stream.recv())
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??,
+        };
+        self.broken = false;
+        Response::from_value(Value::unpack(&mut &message[..])?)
+    }
+
+    fn check_broken(&self) -> Result<()> {
+        if self.broken {
+            return Err(BoltError::Socket(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Connection broken by an earlier failed read or write",
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn timeout_mid_message_breaks_the_stream() {
+        let (client, mut server) = duplex(64);
+        server.write_all(&[0x00, 0x00, 0x04, 0x04]).await.unwrap();
+        let mut bolt = AsyncBoltStream::handshake(client).await.unwrap();
+        bolt.read_timeout = Some(Duration::from_millis(20));
+        // the first half of a SUCCESS {} message
+        server.write_all(&[0x00, 0x03, 0xB1]).await.unwrap();
+        match bolt.receive().await {
+            Err(BoltError::Socket(ref e)) if e.kind() == io::ErrorKind::TimedOut => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        assert!(bolt.is_broken());
+        server.write_all(&[0x70, 0xA0, 0x00, 0x00]).await.unwrap();
+        assert!(bolt.receive().await.is_err());
+        assert!(bolt.send().await.is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::chunk::encode;
+
+/// The async counterpart of `ChunkStream`.
+///
+pub struct AsyncChunkStream<T> {
+    stream: T,
+    max_message_size: Option<usize>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncChunkStream<T> {
+    pub fn new(stream: T) -> Self {
+        AsyncChunkStream {
+            stream,
+            max_message_size: None,
+        }
+    }
+
+    /// Fails `recv` with `InvalidData` once a message grows past `size`
+    /// bytes, before the rest of it is read.
+    ///
+    pub fn set_max_message_size(&mut self, size: Option<usize>) {
+        self.max_message_size = size;
+    }
+
+    /// Writes several messages at once and flushes them.
+    ///
+    pub async fn send<'a, I>(&mut self, messages: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut out = Vec::new();
+        for message in messages {
+            encode(message, &mut out);
+        }
+        self.stream.write_all(&out).await?;
+        self.stream.flush().await
+    }
+
+    pub async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        let mut ret = Vec::new();
+        let mut size = usize::from(self.stream.read_u16().await?);
+        while size != 0 {
+            if let Some(max) = self.max_message_size {
+                if ret.len() + size > max {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Message exceeds the maximum size of {} bytes", max),
+                    ));
+                }
+            }
+            let start = ret.len();
+            ret.resize(start + size, 0);
+            self.stream.read_exact(&mut ret[start..]).await?;
+            size = usize::from(self.stream.read_u16().await?);
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let mut c = AsyncChunkStream::new(Cursor::new(Vec::new()));
+        let big: Vec<u8> = (0..0x1_0010).map(|i| i as u8).collect();
+        c.send(vec![&[1, 2, 3][..], &big[..]]).await.unwrap();
+        c.stream.set_position(0);
+        assert_eq!(c.recv().await.unwrap(), vec![1, 2, 3]);
+        assert_eq!(c.recv().await.unwrap(), big);
+    }
+
+    #[tokio::test]
+    async fn max_message_size() {
+        let buf = vec![0, 3, 0, 1, 2, 0, 3, 3, 4, 5, 0, 0];
+        let mut c = AsyncChunkStream::new(Cursor::new(buf));
+        c.set_max_message_size(Some(5));
+        let err = c.recv().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,604 @@
+use std::{collections::HashMap, sync::Arc};
+
+use futures_util::{stream, Stream};
+use log::info;
+use packstream::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::{bolt::AsyncBoltStream, transport::AsyncTransport};
+use crate::{
+    bolt::{
+        BoltError, BoltSummary, ConnectOptions, ProtocolVersion, Request, Response, ServerError,
+    },
+    cypher::{begin_extra, host, DEFAULT_FETCH_SIZE, USER_AGENT},
+    Neo4jError, NeoResult, Record, ResultSummary, TransactionConfig,
+};
+
+/// Where the stream is in reading the result of the last RUN.
+///
+enum Streaming {
+    /// Every response has been read.
+    Idle,
+    /// Records or a PULL summary are still to come.
+    Records,
+}
+
+/// The async counterpart of `CypherStream`. At most one result is open at
+/// a time; starting another request first discards whatever is left of
+/// the previous result.
+///
+pub struct AsyncCypherStream<T = AsyncTransport> {
+    bolt: AsyncBoltStream<T>,
+    server_version: Option<String>,
+    bookmark: Option<String>,
+    fetch_size: i64,
+    streaming: Streaming,
+    footer: Option<HashMap<String, Value>>,
+}
+
+impl AsyncCypherStream<AsyncTransport> {
+    pub async fn connect(address: &str, user: &str, password: &str) -> crate::bolt::Result<Self> {
+        let options = ConnectOptions::default();
+        AsyncCypherStream::open(address, user, password, USER_AGENT, &options).await
+    }
+
+    /// Connects with the given socket settings and user agent. The host part
+    /// of `address` is the name any TLS certificate is checked against.
+    ///
+    pub async fn open(
+        address: &str,
+        user: &str,
+        password: &str,
+        user_agent: &str,
+        options: &ConnectOptions,
+    ) -> crate::bolt::Result<Self> {
+        info!("Connecting to {} as {}", address, user);
+        let bolt = AsyncBoltStream::open(address, host(address), options).await?;
+        AsyncCypherStream::login(bolt, user, password, user_agent).await
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncCypherStream<T> {
+    /// Authenticates on a stream that has completed its handshake.
+    ///
+    pub async fn login(
+        mut bolt: AsyncBoltStream<T>,
+        user: &str,
+        password: &str,
+        user_agent: &str,
+    ) -> crate::bolt::Result<Self> {
+        if bolt.protocol_version().major >= 3 {
+            bolt.push(Request::hello(user_agent, user, password))?;
+        } else {
+            bolt.push(Request::init(user_agent, user, password))?;
+        }
+        bolt.send().await?;
+        let server_version = match bolt.receive().await? {
+            Response::Summary(BoltSummary::Success(metadata)) => match metadata.get("server") {
+                Some(Value::String(string)) => Some(string.clone()),
+                _ => None,
+            },
+            Response::Summary(BoltSummary::Failure(metadata)) => {
+                return Err(ServerError::from_metadata(&metadata).into())
+            }
+            other => {
+                return Err(BoltError::Protocol(format!(
+                    "Unexpected response to INIT/HELLO: {:?}",
+                    other
+                )))
+            }
+        };
+        info!("Connected to server version {:?}", server_version);
+        Ok(AsyncCypherStream {
+            bolt,
+            server_version,
+            bookmark: None,
+            fetch_size: DEFAULT_FETCH_SIZE,
+            streaming: Streaming::Idle,
+            footer: None,
+        })
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.bolt.protocol_version()
+    }
+
+    pub fn server_version(&self) -> &str {
+        self.server_version.as_deref().unwrap_or("")
+    }
+
+    pub fn bookmark(&self) -> &Option<String> {
+        &self.bookmark
+    }
+
+    /// How many records to pull from the server at a time, or -1 for all
+    /// of them at once. Only Bolt v4 and above can pull in batches.
+    ///
+    pub fn set_fetch_size(&mut self, fetch_size: i64) {
+        self.fetch_size = fetch_size;
+    }
+
+    fn pull(&self) -> Request {
+        if self.protocol_version().major >= 4 {
+            Request::pull(self.fetch_size, -1)
+        } else {
+            Request::pull_all(self.protocol_version())
+        }
+    }
+
+    /// Runs a statement and returns its result, which reads records from
+    /// the server as they are asked for.
+    ///
+    pub async fn run(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+    ) -> NeoResult<AsyncQueryResult<'_, T>> {
+        self.finish().await?;
+        let version = self.protocol_version();
+        self.bolt.push(Request::run(
+            version,
+            statement,
+            Some(parameters.into()),
+            None,
+        ))?;
+        let pull = self.pull();
+        self.bolt.push(pull)?;
+        self.bolt.send().await?;
+        let header = match self.summary().await? {
+            BoltSummary::Success(header) => header,
+            BoltSummary::Failure(metadata) => {
+                // the PULL that followed is ignored
+                self.summary().await?;
+                self.ack_failure().await?;
+                return Err(Neo4jError::RunFailure(ServerError::from_metadata(
+                    &metadata,
+                )));
+            }
+            BoltSummary::Ignored(_) => {
+                self.summary().await?;
+                return Err(Neo4jError::Ignored);
+            }
+        };
+        self.streaming = Streaming::Records;
+        self.footer = None;
+        let keys = match header.get("fields") {
+            Some(Value::List(fields)) => fields
+                .iter()
+                .filter_map(|f| f.clone().into_string())
+                .collect(),
+            _ => Arc::from(Vec::new()),
+        };
+        Ok(AsyncQueryResult {
+            conn: self,
+            keys,
+            header,
+        })
+    }
+
+    /// Opens a transaction that waits for the server to have caught up
+    /// with every one of `bookmarks`.
+    ///
+    pub async fn begin_transaction(&mut self, bookmarks: &[String]) -> NeoResult<()> {
+        info!("BEGIN {:?}->|...|", bookmarks);
+        let version = self.protocol_version();
+        let extra = begin_extra(version, bookmarks, &TransactionConfig::default())?;
+        if version.major >= 3 {
+            self.request(vec![Request::begin(extra)]).await
+        } else {
+            self.request(vec![
+                Request::run(version, "BEGIN", extra, None),
+                Request::discard_all(version),
+            ])
+            .await
+        }
+        .map_err(|e| match e {
+            Neo4jError::RunFailure(e) => Neo4jError::BeginFailure(e),
+            e => e,
+        })?;
+        Ok(())
+    }
+
+    /// Commits the open transaction and returns the server's metadata,
+    /// keeping the bookmark it contains for `bookmark`.
+    ///
+    pub async fn commit_transaction(&mut self) -> NeoResult<HashMap<String, Value>> {
+        let metadata = self.end_transaction("COMMIT").await?;
+        self.bookmark = match metadata.get("bookmark") {
+            Some(Value::String(bookmark)) => Some(bookmark.clone()),
+            _ => None,
+        };
+        info!("COMMIT |...|->{:?}", self.bookmark);
+        Ok(metadata)
+    }
+
+    pub async fn rollback_transaction(&mut self) -> NeoResult<()> {
+        self.end_transaction("ROLLBACK").await?;
+        Ok(())
+    }
+
+    async fn end_transaction(&mut self, statement: &str) -> NeoResult<HashMap<String, Value>> {
+        let version = self.protocol_version();
+        if version.major >= 3 {
+            let request = match statement {
+                "COMMIT" => Request::commit(),
+                _ => Request::rollback(),
+            };
+            self.request(vec![request]).await
+        } else {
+            self.request(vec![
+                Request::run(version, statement, None, None),
+                Request::discard_all(version),
+            ])
+            .await
+        }
+    }
+
+    pub async fn reset(&mut self) -> NeoResult<()> {
+        self.request(vec![Request::reset()]).await?;
+        Ok(())
+    }
+
+    /// Sends requests that return no records and reads their summaries.
+    /// Returns the metadata of the last one, or the first failure.
+    ///
+    async fn request(&mut self, requests: Vec<Request>) -> NeoResult<HashMap<String, Value>> {
+        self.finish().await?;
+        let count = requests.len();
+        for request in requests {
+            self.bolt.push(request)?;
+        }
+        self.bolt.send().await?;
+        let mut result = Ok(HashMap::new());
+        let mut failed = false;
+        for _ in 0..count {
+            match self.summary().await? {
+                BoltSummary::Success(metadata) => {
+                    if result.is_ok() {
+                        result = Ok(metadata);
+                    }
+                }
+                BoltSummary::Failure(metadata) => {
+                    failed = true;
+                    result = Err(Neo4jError::RunFailure(ServerError::from_metadata(
+                        &metadata,
+                    )));
+                }
+                BoltSummary::Ignored(_) => {
+                    if result.is_ok() {
+                        result = Err(Neo4jError::Ignored);
+                    }
+                }
+            }
+        }
+        if failed {
+            self.ack_failure().await?;
+        }
+        result
+    }
+
+    /// Reads the summary that ends the response to a request that returns
+    /// no records.
+    ///
+    async fn summary(&mut self) -> crate::bolt::Result<BoltSummary> {
+        match self.bolt.receive().await? {
+            Response::Summary(summary) => Ok(summary),
+            Response::Record(data) => {
+                Err(BoltError::Protocol(format!("Unexpected record {:?}", data)))
+            }
+        }
+    }
+
+    /// Clears a failure so that the connection accepts requests again.
+    ///
+    async fn ack_failure(&mut self) -> crate::bolt::Result<()> {
+        self.bolt
+            .push(Request::ack_failure(self.protocol_version()))?;
+        self.bolt.send().await?;
+        self.summary().await?;
+        Ok(())
+    }
+
+    /// Reads the next record of the open result, pulling another batch if
+    /// the server has more. With `discard`, the records still on the server
+    /// are thrown away instead.
+    ///
+    async fn advance(&mut self, discard: bool) -> Option<NeoResult<Vec<Value>>> {
+        loop {
+            if let Streaming::Idle = self.streaming {
+                return None;
+            }
+            let response = match self.bolt.receive().await {
+                Ok(response) => response,
+                Err(e) => {
+                    self.streaming = Streaming::Idle;
+                    return Some(Err(e.into()));
+                }
+            };
+            match response {
+                Response::Record(_) if discard => continue,
+                Response::Record(data) => return Some(Ok(data)),
+                Response::Summary(BoltSummary::Success(metadata)) => {
+                    if let Some(Value::Boolean(true)) = metadata.get("has_more") {
+                        let next = if discard {
+                            Request::discard(-1, -1)
+                        } else {
+                            self.pull()
+                        };
+                        let sent = match self.bolt.push(next) {
+                            Ok(()) => self.bolt.send().await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = sent {
+                            self.streaming = Streaming::Idle;
+                            return Some(Err(e.into()));
+                        }
+                        continue;
+                    }
+                    self.streaming = Streaming::Idle;
+                    self.footer = Some(metadata);
+                    return None;
+                }
+                Response::Summary(BoltSummary::Failure(metadata)) => {
+                    self.streaming = Streaming::Idle;
+                    if let Err(e) = self.ack_failure().await {
+                        return Some(Err(e.into()));
+                    }
+                    return Some(Err(Neo4jError::RunFailure(ServerError::from_metadata(
+                        &metadata,
+                    ))));
+                }
+                Response::Summary(BoltSummary::Ignored(_)) => {
+                    self.streaming = Streaming::Idle;
+                    return Some(Err(Neo4jError::Ignored));
+                }
+            }
+        }
+    }
+
+    /// Discards the rest of the open result, if any.
+    ///
+    async fn finish(&mut self) -> NeoResult<()> {
+        while let Some(next) = self.advance(true).await {
+            next?;
+        }
+        Ok(())
+    }
+}
+
+/// The records of a statement run on an `AsyncCypherStream`, read from the
+/// server as they are asked for. Anything left unread is discarded by the
+/// next request on the same stream.
+///
+pub struct AsyncQueryResult<'a, T = AsyncTransport> {
+    conn: &'a mut AsyncCypherStream<T>,
+    keys: Arc<[String]>,
+    header: HashMap<String, Value>,
+}
+
+impl<'a, T: AsyncRead + AsyncWrite + Unpin> AsyncQueryResult<'a, T> {
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// The next record, or `None` once the result is exhausted.
+    ///
+    pub async fn next_record(&mut self) -> Option<NeoResult<Record>> {
+        let values = self.conn.advance(false).await?;
+        Some(values.map(|values| Record::new(self.keys.clone(), values)))
+    }
+
+    /// Turns the result into a `Stream` of records. Each batch is pulled
+    /// from the server only once the previous one has been consumed.
+    ///
+    pub fn into_stream(self) -> impl Stream<Item = NeoResult<Record>> + 'a
+    where
+        T: 'a,
+    {
+        stream::unfold(self, |mut result| async move {
+            let record = result.next_record().await?;
+            Some((record, result))
+        })
+    }
+
+    /// Discards any remaining records and returns the summary the server
+    /// sent at the end of the result.
+    ///
+    pub async fn consume(self) -> NeoResult<ResultSummary> {
+        self.conn.finish().await?;
+        match self.conn.footer.take() {
+            Some(footer) => Ok(ResultSummary::new(&self.header, &footer)),
+            None => Err(BoltError::Protocol(String::from("No summary for PULL")).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use boltstub::BoltStub;
+    use futures_util::StreamExt;
+    use packstream::parameters;
+
+    async fn connect(stub: &BoltStub) -> AsyncCypherStream {
+        AsyncCypherStream::connect(stub.address(), "neo4j", "password")
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn streams_records_in_batches() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            !: AUTO HELLO
+            C: RUN "UNWIND range(0, 4) AS n RETURN n" {} {}
+               PULL {"n": 2, "qid": -1}
+            S: SUCCESS {"fields": ["n"]}
+               RECORD [0]
+               RECORD [1]
+               SUCCESS {"has_more": true}
+            C: PULL {"n": 2, "qid": -1}
+            S: RECORD [2]
+               RECORD [3]
+               SUCCESS {"has_more": true}
+            C: PULL {"n": 2, "qid": -1}
+            S: RECORD [4]
+               SUCCESS {"type": "r"}
+            "#,
+        )
+        .unwrap();
+        let mut conn = connect(&stub).await;
+        conn.set_fetch_size(2);
+        let result = conn
+            .run("UNWIND range(0, 4) AS n RETURN n", parameters!())
+            .await;
+        let records: Vec<_> = result
+            .unwrap()
+            .into_stream()
+            .map(|record| record.unwrap().get::<i64>("n").unwrap())
+            .collect()
+            .await;
+        assert_eq!(records, vec![0, 1, 2, 3, 4]);
+        drop(conn);
+        stub.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn unread_records_are_discarded() {
+        // a second PULL in place of either DISCARD would mean records were
+        // fetched before they were asked for
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            !: AUTO HELLO
+            C: RUN "RETURN n" {} {}
+               PULL {"n": 2, "qid": -1}
+            S: SUCCESS {"fields": ["n"]}
+               RECORD [0]
+               RECORD [1]
+               SUCCESS {"has_more": true}
+            C: DISCARD {"n": -1, "qid": -1}
+            S: SUCCESS {"type": "r"}
+            C: RUN "RETURN n" {} {}
+               PULL {"n": 2, "qid": -1}
+            S: SUCCESS {"fields": ["n"]}
+               RECORD [0]
+               RECORD [1]
+               SUCCESS {"has_more": true}
+            C: DISCARD {"n": -1, "qid": -1}
+            S: SUCCESS {"type": "r"}
+            "#,
+        )
+        .unwrap();
+        let mut conn = connect(&stub).await;
+        conn.set_fetch_size(2);
+        let mut result = conn.run("RETURN n", parameters!()).await.unwrap();
+        assert!(result.next_record().await.unwrap().is_ok());
+        drop(result);
+
+        let result = conn.run("RETURN n", parameters!()).await.unwrap();
+        assert_eq!(result.keys(), ["n"]);
+        let summary = result.consume().await.unwrap();
+        assert_eq!(summary.query_type, Some(crate::QueryType::ReadOnly));
+        drop(conn);
+        stub.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn pulls_everything_before_bolt_4() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 3
+            !: AUTO HELLO
+            C: RUN "RETURN n" {} {}
+               PULL_ALL
+            S: SUCCESS {"fields": ["n"]}
+               RECORD [0]
+               RECORD [1]
+               RECORD [2]
+               SUCCESS {"type": "r"}
+            "#,
+        )
+        .unwrap();
+        let mut conn = connect(&stub).await;
+        let result = conn.run("RETURN n", parameters!()).await.unwrap();
+        let records: Vec<_> = result.into_stream().collect().await;
+        assert_eq!(records.len(), 3);
+        drop(conn);
+        stub.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn failure_leaves_connection_usable() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            !: AUTO HELLO
+            !: AUTO RESET
+            C: RUN "oops" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: FAILURE {"code": "Neo.ClientError.Statement.SyntaxError", "message": "Invalid input"}
+               IGNORED
+            C: RUN "RETURN 1 AS x" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["x"]}
+               RECORD [1]
+               SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut conn = connect(&stub).await;
+        match conn.run("oops", parameters!()).await {
+            Err(Neo4jError::RunFailure(e)) => assert!(e.code.ends_with("SyntaxError")),
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("expected a failure"),
+        }
+        let mut result = conn.run("RETURN 1 AS x", parameters!()).await.unwrap();
+        let record = result.next_record().await.unwrap().unwrap();
+        assert_eq!(record.get::<i64>("x").unwrap(), 1);
+        assert!(result.next_record().await.is_none());
+        drop(conn);
+        stub.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn transaction_keeps_bookmark() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            !: AUTO HELLO
+            C: BEGIN {}
+            S: SUCCESS {}
+            C: RUN "RETURN n" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["n"]}
+               RECORD [0]
+               SUCCESS {"type": "r"}
+            C: COMMIT
+            S: SUCCESS {"bookmark": "bm:1"}
+            "#,
+        )
+        .unwrap();
+        let mut conn = connect(&stub).await;
+        conn.begin_transaction(&[]).await.unwrap();
+        conn.run("RETURN n", parameters!()).await.unwrap();
+        conn.commit_transaction().await.unwrap();
+        assert_eq!(conn.bookmark().as_deref(), Some("bm:1"));
+        drop(conn);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn futures_are_send() {
+        fn send<F: Send>(_: F) {}
+        send(async {
+            let mut conn = AsyncCypherStream::connect("localhost:7687", "", "")
+                .await
+                .unwrap();
+            let result = conn.run("RETURN 1", parameters!()).await.unwrap();
+            let _ = result.into_stream().collect::<Vec<_>>().await;
+        });
+    }
+}
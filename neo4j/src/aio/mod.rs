@@ -0,0 +1,20 @@
+//! An async client on top of tokio, enabled by the `tokio` feature.
+//!
+//! `AsyncBoltStream` and `AsyncCypherStream` mirror their blocking
+//! counterparts in `bolt` and `cypher`, and build and parse messages with
+//! the same code, so both send exactly the same bytes. Results are read
+//! lazily: records are only pulled from the server, a batch at a time, as
+//! the `Stream` returned by `AsyncQueryResult::into_stream` is polled.
+//! With the `tokio-rustls` feature on, the `tls` setting of
+//! `ConnectOptions` encrypts connections just as it does for the blocking
+//! client. With `rustls` alone, async connections cannot be encrypted and
+//! asking for TLS fails.
+
+mod bolt;
+mod chunk;
+mod cypher;
+mod transport;
+
+pub use self::bolt::AsyncBoltStream;
+pub use self::cypher::{AsyncCypherStream, AsyncQueryResult};
+pub use self::transport::AsyncTransport;
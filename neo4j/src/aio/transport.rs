@@ -0,0 +1,65 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+#[cfg(feature = "tokio-rustls")]
+use tokio_rustls::client::TlsStream;
+
+/// The async counterpart of `Transport`: either a bare TCP socket or one
+/// wrapped in a TLS session.
+///
+pub enum AsyncTransport {
+    Plain(TcpStream),
+    #[cfg(feature = "tokio-rustls")]
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for AsyncTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match *self.get_mut() {
+            AsyncTransport::Plain(ref mut stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tokio-rustls")]
+            AsyncTransport::Tls(ref mut stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AsyncTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match *self.get_mut() {
+            AsyncTransport::Plain(ref mut stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tokio-rustls")]
+            AsyncTransport::Tls(ref mut stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match *self.get_mut() {
+            AsyncTransport::Plain(ref mut stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tokio-rustls")]
+            AsyncTransport::Tls(ref mut stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match *self.get_mut() {
+            AsyncTransport::Plain(ref mut stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tokio-rustls")]
+            AsyncTransport::Tls(ref mut stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
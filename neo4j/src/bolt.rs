@@ -5,30 +5,45 @@ use std::{
     io::{self, prelude::*},
     net::{TcpStream, ToSocketAddrs},
     result,
+    time::Duration,
 };
 
-use packstream::{parameters, Data, Value};
+use packstream::{parameters, Data, ListRef, PackError, UnpackError, Value, ValueRef};
 
 use byteorder::{BigEndian, ReadBytesExt};
 use log::debug;
 
-use crate::chunk::ChunkStream;
+#[cfg(feature = "rustls")]
+use crate::tls::TlsConfig;
+use crate::{chunk::ChunkStream, transport::Transport};
 
-const HANDSHAKE: [u8; 20] = [
+/// Preamble followed by the proposed versions, most preferred first. Each
+/// proposal is `[0, range, minor, major]`, where `range` covers that many
+/// minor versions below the one given (Bolt 4.3+ servers understand ranges).
+#[rustfmt::skip]
+pub(crate) const HANDSHAKE: [u8; 20] = [
     0x60, 0x60, 0xB0, 0x17,
+    0x00, 0x01, 0x04, 0x04,
+    0x00, 0x00, 0x00, 0x04,
+    0x00, 0x00, 0x00, 0x03,
     0x00, 0x00, 0x00, 0x01,
-    0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00,
 ];
 
 mod sig {
     pub const INIT: u8 = 0x01;
+    pub const HELLO: u8 = 0x01;
+    pub const GOODBYE: u8 = 0x02;
     pub const RUN: u8 = 0x10;
+    pub const BEGIN: u8 = 0x11;
+    pub const COMMIT: u8 = 0x12;
+    pub const ROLLBACK: u8 = 0x13;
     pub const DISCARD_ALL: u8 = 0x2F;
+    pub const DISCARD: u8 = 0x2F;
     pub const PULL_ALL: u8 = 0x3F;
+    pub const PULL: u8 = 0x3F;
     pub const ACK_FAILURE: u8 = 0x0E;
     pub const RESET: u8 = 0x0F;
+    pub const ROUTE: u8 = 0x66;
     pub const RECORD: u8 = 0x71;
     pub const SUCCESS: u8 = 0x70;
     pub const FAILURE: u8 = 0x7F;
@@ -40,6 +55,11 @@ pub enum BoltError {
     Connect(String),
     Handshake(String),
     Socket(io::Error),
+    Pack(PackError),
+    Unpack(UnpackError),
+    Protocol(String),
+    Failure(ServerError),
+    Tls(String),
 }
 
 impl fmt::Display for BoltError {
@@ -48,16 +68,23 @@ impl fmt::Display for BoltError {
             BoltError::Connect(ref err) => write!(f, "Connect error: {}", err),
             BoltError::Handshake(ref err) => write!(f, "Handshake error: {}", err),
             BoltError::Socket(ref err) => write!(f, "Socket error: {}", err),
+            BoltError::Pack(ref err) => write!(f, "Pack error: {}", err),
+            BoltError::Unpack(ref err) => write!(f, "Unpack error: {}", err),
+            BoltError::Protocol(ref err) => write!(f, "Protocol error: {}", err),
+            BoltError::Failure(ref err) => write!(f, "Server failure: {}", err),
+            BoltError::Tls(ref err) => write!(f, "TLS error: {}", err),
         }
     }
 }
 
 impl Error for BoltError {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         match *self {
-            BoltError::Connect(ref err) => err,
-            BoltError::Handshake(ref err) => err,
-            BoltError::Socket(ref err) => err.description(),
+            BoltError::Socket(ref err) => Some(err),
+            BoltError::Pack(ref err) => Some(err),
+            BoltError::Unpack(ref err) => Some(err),
+            BoltError::Failure(ref err) => Some(err),
+            _ => None,
         }
     }
 }
@@ -68,150 +95,377 @@ impl From<io::Error> for BoltError {
     }
 }
 
+impl From<PackError> for BoltError {
+    fn from(val: PackError) -> Self {
+        BoltError::Pack(val)
+    }
+}
+
+impl From<UnpackError> for BoltError {
+    fn from(val: UnpackError) -> Self {
+        BoltError::Unpack(val)
+    }
+}
+
+impl From<ServerError> for BoltError {
+    fn from(val: ServerError) -> Self {
+        BoltError::Failure(val)
+    }
+}
+
+/// The broad category of a server error, taken from the second part of its
+/// status code, e.g. `Neo.ClientError.Statement.SyntaxError`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Classification {
+    ClientError,
+    TransientError,
+    DatabaseError,
+    Unknown,
+}
+
+/// An error reported by the server in a FAILURE message.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerError {
+    pub code: String,
+    pub message: String,
+    pub classification: Classification,
+}
+
+impl ServerError {
+    pub fn new(code: &str, message: &str) -> Self {
+        let classification = match code.split('.').nth(1) {
+            Some("ClientError") => Classification::ClientError,
+            Some("TransientError") => Classification::TransientError,
+            Some("DatabaseError") => Classification::DatabaseError,
+            _ => Classification::Unknown,
+        };
+        ServerError {
+            code: String::from(code),
+            message: String::from(message),
+            classification,
+        }
+    }
+
+    pub fn from_metadata(metadata: &HashMap<String, Value>) -> Self {
+        let field = |key| match metadata.get(key) {
+            Some(Value::String(value)) => &value[..],
+            _ => "",
+        };
+        ServerError::new(field("code"), field("message"))
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl Error for ServerError {}
+
+/// A Bolt protocol version as agreed during the handshake.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProtocolVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl ProtocolVersion {
+    pub const fn new(major: u8, minor: u8) -> Self {
+        ProtocolVersion { major, minor }
+    }
+
+    /// Decodes the four bytes a server answers the handshake with. A zero
+    /// response means that none of the proposed versions were acceptable.
+    ///
+    fn from_handshake(raw: u32) -> Option<Self> {
+        match raw {
+            0 => None,
+            _ => Some(ProtocolVersion::new(raw as u8, (raw >> 8) as u8)),
+        }
+    }
+
+    /// Checks the server's answer to the handshake against what was
+    /// proposed.
+    ///
+    pub(crate) fn negotiate(raw: u32) -> Result<Self> {
+        let version = match ProtocolVersion::from_handshake(raw) {
+            Some(version) if version.is_proposed() => version,
+            Some(version) => {
+                return Err(BoltError::Handshake(format!(
+                    "Server chose unsupported version {}",
+                    version
+                )))
+            }
+            None => {
+                return Err(BoltError::Handshake(String::from(
+                    "Server supports none of the proposed versions",
+                )))
+            }
+        };
+        debug!("S: <VERSION {}>", version);
+        Ok(version)
+    }
+
+    fn is_proposed(self) -> bool {
+        HANDSHAKE[4..].chunks(4).any(|p| {
+            self.major == p[3] && self.minor <= p[2] && self.minor >= p[2].saturating_sub(p[1])
+        })
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
 pub struct BoltStream {
-    stream: ChunkStream<TcpStream>,
+    stream: ChunkStream<Transport>,
     requests: Vec<Vec<u8>>,
     responses: VecDeque<BoltResponse>,
     responses_done: usize,
     current_response_index: usize,
-    protocol_version: u32,
+    protocol_version: ProtocolVersion,
+    /// Holds each incoming message while it is decoded.
+    buffer: Vec<u8>,
 }
 
 pub type Result<T> = result::Result<T, BoltError>;
 
-impl BoltStream {
-    pub fn connect<A: ToSocketAddrs>(address: A) -> Result<BoltStream> {
-        match TcpStream::connect(address) {
-            Ok(mut stream) => match stream.write(&HANDSHAKE) {
-                Ok(_) => match stream.read_u32::<BigEndian>() {
-                    Ok(protocol_version) => {
-                        debug!("S: <VERSION {}>", protocol_version);
-                        Ok(BoltStream {
-                            stream: ChunkStream::new(stream),
-                            requests: Vec::new(),
-                            responses: VecDeque::new(),
-                            responses_done: 0,
-                            current_response_index: 0,
-                            protocol_version,
-                        })
+/// Settings applied to the socket when a connection is opened.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub max_message_size: Option<usize>,
+    #[cfg(feature = "rustls")]
+    pub tls: Option<TlsConfig>,
+}
+
+fn tcp_connect<A: ToSocketAddrs>(address: A, options: &ConnectOptions) -> Result<TcpStream> {
+    let connect_error = |err| BoltError::Connect(format!("Error on connect: {:?}", err));
+    let stream = match options.connect_timeout {
+        None => TcpStream::connect(address).map_err(connect_error)?,
+        Some(timeout) => {
+            let mut last_error =
+                io::Error::new(io::ErrorKind::InvalidInput, "Address resolved to nothing");
+            let mut connected = None;
+            for addr in address.to_socket_addrs().map_err(connect_error)? {
+                match TcpStream::connect_timeout(&addr, timeout) {
+                    Ok(stream) => {
+                        connected = Some(stream);
+                        break;
                     }
-                    Err(_) => Err(BoltError::Handshake(String::from("Error on read"))),
-                },
-                Err(_) => Err(BoltError::Handshake(String::from("Error on write"))),
-            },
-            Err(err) => Err(BoltError::Connect(format!("Error on connect: {:?}", err))),
+                    Err(err) => last_error = err,
+                }
+            }
+            connected.ok_or_else(|| connect_error(last_error))?
         }
+    };
+    // messages are written a chunk at a time, so don't hold them back
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(options.read_timeout)?;
+    Ok(stream)
+}
+
+impl BoltStream {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> Result<BoltStream> {
+        BoltStream::open(address, "", &ConnectOptions::default())
     }
 
-    pub fn protocol_version(&self) -> u32 {
+    /// Connects over TLS, verifying the server certificate against `host`
+    /// as configured.
+    ///
+    #[cfg(feature = "rustls")]
+    pub fn connect_tls<A: ToSocketAddrs>(
+        address: A,
+        host: &str,
+        config: &TlsConfig,
+    ) -> Result<BoltStream> {
+        let options = ConnectOptions {
+            tls: Some(config.clone()),
+            ..ConnectOptions::default()
+        };
+        BoltStream::open(address, host, &options)
+    }
+
+    /// Connects with the given socket settings. `host` is only used to
+    /// verify the server certificate when TLS is enabled.
+    ///
+    pub fn open<A: ToSocketAddrs>(
+        address: A,
+        host: &str,
+        options: &ConnectOptions,
+    ) -> Result<BoltStream> {
+        let socket = tcp_connect(address, options)?;
+        #[cfg(feature = "rustls")]
+        let transport = match options.tls {
+            Some(ref config) => Transport::Tls(Box::new(config.connect(host, socket)?)),
+            None => Transport::Plain(socket),
+        };
+        #[cfg(not(feature = "rustls"))]
+        let transport = {
+            let _ = host;
+            Transport::Plain(socket)
+        };
+        let mut bolt = BoltStream::handshake(transport)?;
+        bolt.stream.set_max_message_size(options.max_message_size);
+        Ok(bolt)
+    }
+
+    fn handshake(mut stream: Transport) -> Result<BoltStream> {
+        debug!("C: <HANDSHAKE {:02X?}>", &HANDSHAKE[4..]);
+        stream
+            .write_all(&HANDSHAKE)
+            .map_err(|_| BoltError::Handshake(String::from("Error on write")))?;
+        let raw = stream
+            .read_u32::<BigEndian>()
+            .map_err(|_| BoltError::Handshake(String::from("Error on read")))?;
+        let protocol_version = ProtocolVersion::negotiate(raw)?;
+        Ok(BoltStream {
+            stream: ChunkStream::new(stream),
+            requests: Vec::new(),
+            responses: VecDeque::new(),
+            responses_done: 0,
+            current_response_index: 0,
+            protocol_version,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
         self.protocol_version
     }
 
-    /// Pack an INIT message.
+    fn push(&mut self, request: Request) -> Result<()> {
+        self.requests.push(request.pack()?);
+        Ok(())
+    }
+
+    /// Pack an INIT message (Bolt v1 and v2).
     ///
-    pub fn init(&mut self, user_agent: &str, user: &str, password: &str) {
-        debug!(
-            "C: INIT {:?} {{\"scheme\": \"basic\", \"principal\": {:?}, \"credentials\": \"...\"}}",
-            user_agent, user
-        );
-        self.requests.push(
-            Value::Structure {
-                signature: sig::INIT,
-                fields: vec![
-                    user_agent.into(),
-                    parameters!(
-                        "scheme" => "basic",
-                        "principal" => user,
-                        "credentials" => password
-                    )
-                    .into(),
-                ],
-            }
-            .pack_into()
-            .unwrap(),
-        );
+    pub fn init(&mut self, user_agent: &str, user: &str, password: &str) -> Result<()> {
+        self.push(Request::init(user_agent, user, password))
     }
 
-    /// Pack an ACK_FAILURE message.
+    /// Pack a HELLO message (Bolt v3 and above).
     ///
-    pub fn ack_failure(&mut self) {
-        debug!("C: ACK_FAILURE");
-        self.requests.push(
-            Value::Structure {
-                signature: sig::ACK_FAILURE,
-                fields: vec![],
-            }
-            .pack_into()
-            .unwrap(),
-        );
+    pub fn hello(&mut self, user_agent: &str, user: &str, password: &str) -> Result<()> {
+        self.push(Request::hello(user_agent, user, password))
+    }
+
+    /// Pack a GOODBYE message (Bolt v3 and above).
+    ///
+    pub fn goodbye(&mut self) -> Result<()> {
+        self.push(Request::goodbye())
+    }
+
+    /// Pack an ACK_FAILURE message. Bolt v3 dropped ACK_FAILURE, so from
+    /// that version on a RESET is packed instead.
+    ///
+    pub fn ack_failure(&mut self) -> Result<()> {
+        self.push(Request::ack_failure(self.protocol_version))
     }
 
     /// Pack a RESET message.
     ///
-    pub fn reset(&mut self) {
-        debug!("C: RESET");
-        self.requests.push(
-            Value::Structure {
-                signature: sig::RESET,
-                fields: vec![],
-            }
-            .pack_into()
-            .unwrap(),
-        );
+    pub fn reset(&mut self) -> Result<()> {
+        self.push(Request::reset())
     }
 
-    /// Pack a RUN message.
+    /// Pack a RUN message. `extra` holds the transaction configuration of
+    /// an auto-commit query (Bolt v3 and above) and is dropped before that.
     ///
-    pub fn run(&mut self, statement: &str, parameters: Option<Value>) {
-        debug!("C: RUN {:?} {:?}", statement, parameters);
-        self.requests.push(
-            Value::Structure {
-                signature: sig::RUN,
-                fields: vec![
-                    statement.into(),
-                    parameters.unwrap_or_else(|| Value::Map(HashMap::new())),
-                ],
-            }
-            .pack_into()
-            .unwrap(),
-        );
+    pub fn run(
+        &mut self,
+        statement: &str,
+        parameters: Option<Value>,
+        extra: Option<Value>,
+    ) -> Result<()> {
+        self.push(Request::run(
+            self.protocol_version,
+            statement,
+            parameters,
+            extra,
+        ))
     }
 
-    /// Pack a DISCARD_ALL message.
+    /// Pack a BEGIN message (Bolt v3 and above).
     ///
-    pub fn discard_all(&mut self) {
-        debug!("C: DISCARD_ALL");
-        self.requests.push(
-            Value::Structure {
-                signature: sig::DISCARD_ALL,
-                fields: vec![],
-            }
-            .pack_into()
-            .unwrap(),
-        );
+    pub fn begin(&mut self, extra: Option<Value>) -> Result<()> {
+        self.push(Request::begin(extra))
     }
 
-    /// Pack a PULL_ALL message.
+    /// Pack a COMMIT message (Bolt v3 and above).
     ///
-    pub fn pull_all(&mut self) {
-        debug!("C: PULL_ALL");
-        self.requests.push(
-            Value::Structure {
-                signature: sig::PULL_ALL,
-                fields: vec![],
-            }
-            .pack_into()
-            .unwrap(),
-        );
+    pub fn commit(&mut self) -> Result<()> {
+        self.push(Request::commit())
+    }
+
+    /// Pack a ROLLBACK message (Bolt v3 and above).
+    ///
+    pub fn rollback(&mut self) -> Result<()> {
+        self.push(Request::rollback())
+    }
+
+    /// Pack a ROUTE message (Bolt v4.3 and above), asking for the routing
+    /// table of `db`, or of the default database if `None`.
+    ///
+    pub fn route(
+        &mut self,
+        routing_context: Value,
+        bookmarks: Vec<String>,
+        db: Option<&str>,
+    ) -> Result<()> {
+        self.push(Request::route(
+            self.protocol_version,
+            routing_context,
+            bookmarks,
+            db,
+        ))
+    }
+
+    /// Pack a DISCARD_ALL message, or a DISCARD for all records of the last
+    /// statement on Bolt v4 and above.
+    ///
+    pub fn discard_all(&mut self) -> Result<()> {
+        self.push(Request::discard_all(self.protocol_version))
+    }
+
+    /// Pack a PULL_ALL message, or a PULL for all records of the last
+    /// statement on Bolt v4 and above.
+    ///
+    pub fn pull_all(&mut self) -> Result<()> {
+        self.push(Request::pull_all(self.protocol_version))
+    }
+
+    /// Pack a DISCARD message (Bolt v4 and above). An `n` of -1 discards all
+    /// remaining records and a `qid` of -1 refers to the last statement.
+    ///
+    pub fn discard(&mut self, n: i64, qid: i64) -> Result<()> {
+        self.push(Request::discard(n, qid))
+    }
+
+    /// Pack a PULL message (Bolt v4 and above). An `n` of -1 pulls all
+    /// remaining records and a `qid` of -1 refers to the last statement.
+    ///
+    pub fn pull(&mut self, n: i64, qid: i64) -> Result<()> {
+        self.push(Request::pull(n, qid))
     }
 
     /// Send all queued outgoing messages.
     ///
-    pub fn send(&mut self) {
+    pub fn send(&mut self) -> Result<()> {
         debug!("C: <SEND>");
         for req in self.requests.drain(..) {
-            self.stream.send(&req[..]).unwrap();
+            self.stream.send(&req[..])?;
         }
+        Ok(())
     }
 
     pub fn collect_response(&mut self) -> usize {
@@ -255,13 +509,7 @@ impl BoltStream {
             .iter_mut()
             .rev()
             .skip(from_end)
-            .skip_while(|r| match r.summary {
-                Some(ref s) => match *s {
-                    BoltSummary::Failure(_) => false,
-                    _ => true,
-                },
-                None => true,
-            });
+            .skip_while(|r| !matches!(r.summary, Some(BoltSummary::Failure(_))));
         match iter.next() {
             Some(r) => r.summary.take(),
             None => None,
@@ -271,106 +519,370 @@ impl BoltStream {
     /// Fetches the next response message for the designated response,
     /// assuming that response is not already completely buffered.
     ///
-    pub fn fetch_record(&mut self, response_id: usize) -> Option<Data> {
+    pub fn fetch_record(&mut self, response_id: usize) -> Result<Option<Data>> {
         let response_index = response_id - self.responses_done;
         while self.current_response_index < response_index {
-            self.fetch();
+            self.fetch()?;
         }
         if self.current_response_index == response_index {
-            self.fetch();
+            self.fetch()?;
+        }
+        match self.responses[response_index].detail.pop_front() {
+            Some(packed) => match Response::from_value(Value::unpack(&mut &packed[..])?)? {
+                Response::Record(data) => Ok(Some(Data::Record(data))),
+                Response::Summary(_) => unreachable!("only records are queued"),
+            },
+            None => Ok(None),
         }
-        self.responses[response_index].detail.pop_front()
     }
 
     /// Fetches all response messages for the designated response,
     /// assuming that response is not already completely buffered.
     ///
-    pub fn fetch_summary(&mut self, response_id: usize) -> Option<BoltSummary> {
+    pub fn fetch_summary(&mut self, response_id: usize) -> Result<Option<BoltSummary>> {
         let response_index = response_id - self.responses_done;
         while self.current_response_index <= response_index {
-            self.fetch();
+            self.fetch()?;
         }
         let response = &mut self.responses[response_index];
         response.done = true;
-        response.summary.take()
+        Ok(response.summary.take())
     }
 
-    fn receive(&mut self) -> Value {
-        Value::unpack(&mut &self.stream.recv().unwrap()[..]).unwrap()
+    /// Like `fetch_record`, but hands the fields of the record to `f` as
+    /// they sit in the receive buffer, rather than decoding them into a
+    /// `Data`. Records that arrived ahead of time, while reading another
+    /// response, are queued still packed and are read the same way.
+    ///
+    pub fn fetch_record_with<T, F>(&mut self, response_id: usize, f: F) -> Result<Option<T>>
+    where
+        F: FnOnce(ListRef<'_>) -> T,
+    {
+        let response_index = response_id - self.responses_done;
+        while self.current_response_index < response_index {
+            self.fetch()?;
+        }
+        if let Some(packed) = self.responses[response_index].detail.pop_front() {
+            return Ok(record_fields(&packed)?.map(|data| {
+                debug!("S: RECORD {:?}", data);
+                f(data)
+            }));
+        }
+        if self.current_response_index > response_index {
+            return Ok(None);
+        }
+        self.stream.recv_into(&mut self.buffer)?;
+        if let Some(data) = record_fields(&self.buffer)? {
+            debug!("S: RECORD {:?}", data);
+            return Ok(Some(f(data)));
+        }
+        let msg = Value::unpack(&mut &self.buffer[..])?;
+        self.accept(msg)?;
+        Ok(None)
     }
 
-    /// Reads the next message from the stream into the read buffer.
+    /// Reads the next message from the stream into the response it
+    /// answers. Records are queued as they arrived, still packed, and are
+    /// only decoded when they are read.
     ///
-    fn fetch(&mut self) {
-        let msg = self.receive();
-        let response = &mut self.responses[self.current_response_index];
-        match msg {
-            Value::Structure {
-                signature,
-                mut fields,
-            } => match signature {
-                sig::SUCCESS => {
-                    self.current_response_index += 1;
-                    if fields.is_empty() {
-                        debug!("S: SUCCESS {{}}");
-                        response.summary = Some(BoltSummary::Success(HashMap::new()));
-                    } else {
-                        match fields.remove(0) {
-                            Value::Map(metadata) => {
-                                debug!("S: SUCCESS({:?})", metadata);
-                                response.summary = Some(BoltSummary::Success(metadata));
-                            }
-                            _ => panic!("Non-map metadata"),
-                        }
-                    }
-                }
-                sig::RECORD => {
-                    if fields.is_empty() {
-                        debug!("S: RECORD {{}}");
-                        response.detail.push_back(Data::Record(Vec::new()));
-                    } else {
-                        match fields.remove(0) {
-                            Value::List(data) => {
-                                debug!("S: RECORD {:?}", data);
-                                response.detail.push_back(Data::Record(data));
-                            }
-                            _ => panic!("Non-list data"),
-                        }
-                    }
+    fn fetch(&mut self) -> Result<()> {
+        self.stream.recv_into(&mut self.buffer)?;
+        if is_record(&self.buffer) {
+            let packed = std::mem::take(&mut self.buffer);
+            return match self.responses.get_mut(self.current_response_index) {
+                Some(response) => {
+                    response.detail.push_back(packed);
+                    Ok(())
                 }
-                sig::IGNORED => {
-                    self.current_response_index += 1;
-                    if fields.is_empty() {
-                        debug!("S: IGNORED {{}}");
-                        response.summary = Some(BoltSummary::Ignored(HashMap::new()));
-                    } else {
-                        match fields.remove(0) {
-                            Value::Map(metadata) => {
-                                debug!("S: IGNORED({:?})", metadata);
-                                response.summary = Some(BoltSummary::Ignored(metadata));
-                            }
-                            _ => panic!("Non-map metadata"),
-                        }
-                    }
+                None => Err(BoltError::Protocol(String::from(
+                    "Unexpected RECORD with no request outstanding",
+                ))),
+            };
+        }
+        let msg = Value::unpack(&mut &self.buffer[..])?;
+        self.accept(msg)
+    }
+
+    /// Adds a message to the response it answers.
+    ///
+    fn accept(&mut self, msg: Value) -> Result<()> {
+        let response = match self.responses.get_mut(self.current_response_index) {
+            Some(response) => response,
+            None => {
+                return Err(BoltError::Protocol(format!(
+                    "Unexpected message {:?} with no request outstanding",
+                    msg
+                )))
+            }
+        };
+        match Response::from_value(msg)? {
+            // only a RECORD with a long structure header gets this far
+            Response::Record(data) => {
+                let record = Value::Structure {
+                    signature: sig::RECORD,
+                    fields: vec![Value::List(data)],
+                };
+                response.detail.push_back(record.pack_into()?)
+            }
+            Response::Summary(summary) => {
+                self.current_response_index += 1;
+                response.summary = Some(summary);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A packed empty list, for records sent without any fields.
+///
+const EMPTY_LIST: &[u8] = &[0x90];
+
+/// Whether a packed message is a RECORD, going by its first two bytes.
+///
+fn is_record(packed: &[u8]) -> bool {
+    match *packed {
+        [marker, signature, ..] => marker & 0xF0 == 0xB0 && signature == sig::RECORD,
+        _ => false,
+    }
+}
+
+/// The fields of a packed RECORD message, or `None` for any other message.
+///
+fn record_fields(packed: &[u8]) -> Result<Option<ListRef<'_>>> {
+    match ValueRef::unpack(packed)? {
+        ValueRef::Structure {
+            signature: sig::RECORD,
+            fields,
+        } => match fields.get(0) {
+            Some(ValueRef::List(data)) => Ok(Some(data)),
+            Some(other) => Err(protocol_error("Non-list data", other.to_value())),
+            None => Ok(Some(ValueRef::unpack(EMPTY_LIST)?.as_list().unwrap())),
+        },
+        _ => Ok(None),
+    }
+}
+
+fn protocol_error(message: &str, value: Value) -> BoltError {
+    BoltError::Protocol(format!("{}: {:?}", message, value))
+}
+
+/// A request message. Both the blocking and the async streams build their
+/// requests here, so they send exactly the same bytes.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    pub signature: u8,
+    pub fields: Vec<Value>,
+}
+
+impl Request {
+    fn new(signature: u8, fields: Vec<Value>) -> Self {
+        Request { signature, fields }
+    }
+
+    pub fn init(user_agent: &str, user: &str, password: &str) -> Self {
+        debug!(
+            "C: INIT {:?} {{\"scheme\": \"basic\", \"principal\": {:?}, \"credentials\": \"...\"}}",
+            user_agent, user
+        );
+        Request::new(
+            sig::INIT,
+            vec![
+                user_agent.into(),
+                parameters!(
+                    "scheme" => "basic",
+                    "principal" => user,
+                    "credentials" => password
+                )
+                .into(),
+            ],
+        )
+    }
+
+    pub fn hello(user_agent: &str, user: &str, password: &str) -> Self {
+        debug!(
+            "C: HELLO {{\"user_agent\": {:?}, \"scheme\": \"basic\", \"principal\": {:?}, \"credentials\": \"...\"}}",
+            user_agent, user
+        );
+        Request::new(
+            sig::HELLO,
+            vec![parameters!(
+                "user_agent" => user_agent,
+                "scheme" => "basic",
+                "principal" => user,
+                "credentials" => password
+            )
+            .into()],
+        )
+    }
+
+    pub fn goodbye() -> Self {
+        debug!("C: GOODBYE");
+        Request::new(sig::GOODBYE, vec![])
+    }
+
+    pub fn ack_failure(version: ProtocolVersion) -> Self {
+        if version.major >= 3 {
+            Request::reset()
+        } else {
+            debug!("C: ACK_FAILURE");
+            Request::new(sig::ACK_FAILURE, vec![])
+        }
+    }
+
+    pub fn reset() -> Self {
+        debug!("C: RESET");
+        Request::new(sig::RESET, vec![])
+    }
+
+    pub fn run(
+        version: ProtocolVersion,
+        statement: &str,
+        parameters: Option<Value>,
+        extra: Option<Value>,
+    ) -> Self {
+        debug!("C: RUN {:?} {:?} {:?}", statement, parameters, extra);
+        let mut fields = vec![
+            statement.into(),
+            parameters.unwrap_or_else(|| Value::Map(HashMap::new())),
+        ];
+        if version.major >= 3 {
+            fields.push(extra.unwrap_or_else(|| Value::Map(HashMap::new())));
+        }
+        Request::new(sig::RUN, fields)
+    }
+
+    pub fn begin(extra: Option<Value>) -> Self {
+        debug!("C: BEGIN {:?}", extra);
+        Request::new(
+            sig::BEGIN,
+            vec![extra.unwrap_or_else(|| Value::Map(HashMap::new()))],
+        )
+    }
+
+    pub fn commit() -> Self {
+        debug!("C: COMMIT");
+        Request::new(sig::COMMIT, vec![])
+    }
+
+    pub fn rollback() -> Self {
+        debug!("C: ROLLBACK");
+        Request::new(sig::ROLLBACK, vec![])
+    }
+
+    pub fn route(
+        version: ProtocolVersion,
+        routing_context: Value,
+        bookmarks: Vec<String>,
+        db: Option<&str>,
+    ) -> Self {
+        debug!("C: ROUTE {:?} {:?} {:?}", routing_context, bookmarks, db);
+        let db = if version >= ProtocolVersion::new(4, 4) {
+            let mut extra = HashMap::new();
+            if let Some(db) = db {
+                extra.insert(String::from("db"), Value::from(db));
+            }
+            Value::Map(extra)
+        } else {
+            db.map_or(Value::Null, Value::from)
+        };
+        Request::new(sig::ROUTE, vec![routing_context, bookmarks.into(), db])
+    }
+
+    pub fn discard_all(version: ProtocolVersion) -> Self {
+        if version.major >= 4 {
+            Request::discard(-1, -1)
+        } else {
+            debug!("C: DISCARD_ALL");
+            Request::new(sig::DISCARD_ALL, vec![])
+        }
+    }
+
+    pub fn pull_all(version: ProtocolVersion) -> Self {
+        if version.major >= 4 {
+            Request::pull(-1, -1)
+        } else {
+            debug!("C: PULL_ALL");
+            Request::new(sig::PULL_ALL, vec![])
+        }
+    }
+
+    pub fn discard(n: i64, qid: i64) -> Self {
+        debug!("C: DISCARD {{\"n\": {}, \"qid\": {}}}", n, qid);
+        Request::new(
+            sig::DISCARD,
+            vec![parameters!("n" => n, "qid" => qid).into()],
+        )
+    }
+
+    pub fn pull(n: i64, qid: i64) -> Self {
+        debug!("C: PULL {{\"n\": {}, \"qid\": {}}}", n, qid);
+        Request::new(sig::PULL, vec![parameters!("n" => n, "qid" => qid).into()])
+    }
+
+    pub fn pack(self) -> Result<Vec<u8>> {
+        let message = Value::Structure {
+            signature: self.signature,
+            fields: self.fields,
+        };
+        Ok(message.pack_into()?)
+    }
+}
+
+/// A response message: either one record of a result or the summary that
+/// ends the response to a request.
+///
+#[derive(Clone, Debug)]
+pub enum Response {
+    Record(Vec<Value>),
+    Summary(BoltSummary),
+}
+
+impl Response {
+    pub fn from_value(msg: Value) -> Result<Response> {
+        let (signature, mut fields) = match msg {
+            Value::Structure { signature, fields } => (signature, fields),
+            other => {
+                return Err(protocol_error(
+                    "Response message is not a data or a summary",
+                    other,
+                ))
+            }
+        };
+        let (name, summary): (_, fn(_) -> _) = match signature {
+            sig::RECORD => {
+                if fields.is_empty() {
+                    debug!("S: RECORD {{}}");
+                    return Ok(Response::Record(Vec::new()));
                 }
-                sig::FAILURE => {
-                    self.current_response_index += 1;
-                    if fields.is_empty() {
-                        debug!("S: FAILURE {{}}");
-                        response.summary = Some(BoltSummary::Failure(HashMap::new()));
-                    } else {
-                        match fields.remove(0) {
-                            Value::Map(metadata) => {
-                                debug!("S: FAILURE({:?})", metadata);
-                                response.summary = Some(BoltSummary::Failure(metadata));
-                            }
-                            _ => panic!("Non-map metadata"),
-                        }
+                return match fields.remove(0) {
+                    Value::List(data) => {
+                        debug!("S: RECORD {:?}", data);
+                        Ok(Response::Record(data))
                     }
-                }
-                _ => panic!("Unknown response message with signature {:02X}", signature),
-            },
-            _ => panic!("Response message is not a data or a summary"),
+                    other => Err(protocol_error("Non-list data", other)),
+                };
+            }
+            sig::SUCCESS => ("SUCCESS", BoltSummary::Success),
+            sig::IGNORED => ("IGNORED", BoltSummary::Ignored),
+            sig::FAILURE => ("FAILURE", BoltSummary::Failure),
+            _ => {
+                return Err(BoltError::Protocol(format!(
+                    "Unknown response message with signature {:02X}",
+                    signature
+                )))
+            }
+        };
+        if fields.is_empty() {
+            debug!("S: {} {{}}", name);
+            return Ok(Response::Summary(summary(HashMap::new())));
+        }
+        match fields.remove(0) {
+            Value::Map(metadata) => {
+                debug!("S: {}({:?})", name, metadata);
+                Ok(Response::Summary(summary(metadata)))
+            }
+            other => Err(protocol_error("Non-map metadata", other)),
         }
     }
 }
@@ -393,7 +905,8 @@ impl fmt::Debug for BoltSummary {
 
 #[derive(Clone, Default)]
 pub struct BoltResponse {
-    detail: VecDeque<Data>,
+    /// Records received for this response and not yet read, still packed.
+    detail: VecDeque<Vec<u8>>,
     summary: Option<BoltSummary>,
     done: bool,
 }
@@ -427,3 +940,129 @@ impl fmt::Debug for BoltResponse {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{net::TcpListener, thread};
+
+    fn handshake_with(response: [u8; 4]) -> Result<BoltStream> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut handshake = [0; 20];
+            socket.read_exact(&mut handshake).unwrap();
+            assert_eq!(handshake, HANDSHAKE);
+            socket.write_all(&response).unwrap();
+        });
+        let bolt = BoltStream::connect(address);
+        server.join().unwrap();
+        bolt
+    }
+
+    #[test]
+    fn negotiates_ranged_version() {
+        let bolt = handshake_with([0x00, 0x00, 0x03, 0x04]).unwrap();
+        assert_eq!(bolt.protocol_version(), ProtocolVersion::new(4, 3));
+    }
+
+    #[test]
+    fn negotiates_legacy_version() {
+        let bolt = handshake_with([0x00, 0x00, 0x00, 0x01]).unwrap();
+        assert_eq!(bolt.protocol_version(), ProtocolVersion::new(1, 0));
+    }
+
+    #[test]
+    fn rejects_unproposed_version() {
+        match handshake_with([0x00, 0x00, 0x02, 0x04]) {
+            Err(BoltError::Handshake(_)) => (),
+            _ => panic!("4.2 was never proposed"),
+        }
+        match handshake_with([0x00, 0x00, 0x00, 0x00]) {
+            Err(BoltError::Handshake(_)) => (),
+            _ => panic!("no version was agreed"),
+        }
+    }
+
+    #[test]
+    fn dropped_connection_is_an_error() {
+        // the server thread has closed its socket once the handshake is done
+        let mut bolt = handshake_with([0x00, 0x00, 0x04, 0x04]).unwrap();
+        bolt.reset().unwrap();
+        let reset = bolt.collect_response();
+        let _ = bolt.send();
+        match bolt.fetch_summary(reset) {
+            Err(BoltError::Socket(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn queued_records_stay_packed_until_read() {
+        let stub = boltstub::BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: RUN "RETURN n, name" * *
+               PULL {"n": -1, "qid": -1}
+               RESET
+            S: SUCCESS {"fields": ["n", "name"]}
+               RECORD [1, "one"]
+               RECORD [2, "two"]
+               SUCCESS {}
+               SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut bolt = BoltStream::connect(stub.address()).unwrap();
+        bolt.run("RETURN n, name", None, None).unwrap();
+        let run = bolt.collect_response();
+        bolt.pull(-1, -1).unwrap();
+        let pull = bolt.collect_response();
+        bolt.reset().unwrap();
+        let reset = bolt.collect_response();
+        bolt.send().unwrap();
+        assert!(bolt.fetch_summary(run).unwrap().is_some());
+        // reading ahead to the RESET queues both records
+        assert!(bolt.fetch_summary(reset).unwrap().is_some());
+        let name = bolt
+            .fetch_record_with(pull, |fields| {
+                fields.get(1).and_then(|v| v.as_str()).map(String::from)
+            })
+            .unwrap();
+        assert_eq!(name, Some(Some(String::from("one"))));
+        match bolt.fetch_record(pull).unwrap() {
+            Some(Data::Record(fields)) => assert_eq!(fields, vec![2.into(), "two".into()]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(bolt.fetch_record_with(pull, |_| ()).unwrap().is_none());
+        drop(bolt);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn server_error_from_metadata() {
+        let metadata = parameters!(
+            "code" => "Neo.TransientError.Transaction.DeadlockDetected",
+            "message" => "Deadlock"
+        )
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        let error = ServerError::from_metadata(&metadata);
+        assert_eq!(error.classification, Classification::TransientError);
+        assert_eq!(
+            error.to_string(),
+            "Neo.TransientError.Transaction.DeadlockDetected: Deadlock"
+        );
+        assert_eq!(
+            ServerError::new("Neo.ClientError.Statement.SyntaxError", "").classification,
+            Classification::ClientError
+        );
+        assert_eq!(
+            ServerError::new("", "").classification,
+            Classification::Unknown
+        );
+    }
+}
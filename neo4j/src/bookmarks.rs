@@ -0,0 +1,125 @@
+//! Bookmarks for causal consistency. Each committed transaction leaves a
+//! bookmark, and a transaction begun with that bookmark will not start
+//! until the server it runs on has caught up with the commit, so later
+//! work sees earlier writes even on another connection or cluster member.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A set of bookmarks that several sessions share. Each transaction they
+/// begin waits for all the bookmarks held here, and each commit replaces
+/// the bookmarks its transaction waited for with the one it produced. A
+/// clone shares the same set.
+///
+#[derive(Clone, Debug, Default)]
+pub struct BookmarkManager {
+    bookmarks: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl BookmarkManager {
+    pub fn new() -> Self {
+        BookmarkManager::default()
+    }
+
+    pub fn with_bookmarks<I, S>(bookmarks: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        BookmarkManager {
+            bookmarks: Arc::new(Mutex::new(bookmarks.into_iter().map(Into::into).collect())),
+        }
+    }
+
+    pub fn bookmarks(&self) -> Vec<String> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Records that a transaction which waited for `previous` committed,
+    /// leaving `bookmark`. Bookmarks added by other sessions in the
+    /// meantime are kept.
+    ///
+    pub fn update(&self, previous: &[String], bookmark: &str) {
+        let mut bookmarks = self.lock();
+        for old in previous {
+            bookmarks.remove(old);
+        }
+        bookmarks.insert(String::from(bookmark));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeSet<String>> {
+        self.bookmarks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use boltstub::BoltStub;
+    use packstream::parameters;
+
+    use crate::{Neo4jDB, Neo4jOperations};
+
+    #[test]
+    fn commits_replace_what_they_waited_for() {
+        let manager = BookmarkManager::with_bookmarks(vec!["bm:1", "bm:2"]);
+        let seen = manager.bookmarks();
+        manager.update(&[], "bm:3");
+        manager.update(&seen, "bm:4");
+        assert_eq!(manager.bookmarks(), vec!["bm:3", "bm:4"]);
+        assert_eq!(manager.clone().bookmarks(), manager.bookmarks());
+    }
+
+    fn session(bookmarks_sent: &str, bookmark_received: &str) -> BoltStub {
+        BoltStub::start(&format!(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {{"server": "Neo4j/4.4.0"}}
+            C: BEGIN {}
+            S: SUCCESS {{}}
+            C: RUN "CREATE ()" {{}} {{}}
+               PULL {{"n": 1000, "qid": -1}}
+            S: SUCCESS {{"fields": []}}
+               SUCCESS {{}}
+            C: COMMIT
+            S: SUCCESS {{"bookmark": "{}"}}
+            "#,
+            bookmarks_sent, bookmark_received
+        ))
+        .unwrap()
+    }
+
+    fn create(db: &mut Neo4jDB) {
+        let mut tx = db.transaction().unwrap();
+        tx.run("CREATE ()", parameters!()).unwrap();
+        tx.commit().unwrap();
+    }
+
+    #[test]
+    fn sessions_read_each_others_writes() {
+        let manager = BookmarkManager::new();
+        let first = session("{}", "bm:1");
+        let second = session(r#"{"bookmarks": ["bm:1", "bm:x"]}"#, "bm:2");
+
+        let mut a = Neo4jDB::connect(first.address(), "neo4j", "password").unwrap();
+        a.set_bookmark_manager(manager.clone());
+        create(&mut a);
+        assert_eq!(a.last_bookmark(), Some("bm:1"));
+
+        let mut b = Neo4jDB::connect(second.address(), "neo4j", "password").unwrap();
+        b.set_bookmarks(vec![String::from("bm:x")]);
+        b.set_bookmark_manager(manager.clone());
+        create(&mut b);
+        assert_eq!(b.last_bookmark(), Some("bm:2"));
+        assert_eq!(b.bookmarks(), ["bm:2"]);
+
+        assert_eq!(manager.bookmarks(), vec!["bm:2"]);
+        drop((a, b));
+        first.finish().unwrap();
+        second.finish().unwrap();
+    }
+}
@@ -1,39 +1,67 @@
 use std::io::{self, Read, Write};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt};
 
 const MAX_CHUNK_SIZE: usize = 0xFFFF;
 
 type ChunkResult<T> = Result<T, io::Error>;
 
+/// Appends `buf` to `out` as one message: a run of chunks, each prefixed
+/// with its length, ended by an empty chunk.
+///
+pub(crate) fn encode(buf: &[u8], out: &mut Vec<u8>) {
+    for chunk in buf.chunks(MAX_CHUNK_SIZE) {
+        out.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&[0, 0]);
+}
+
 pub struct ChunkStream<T: Read + Write> {
     stream: T,
+    max_message_size: Option<usize>,
 }
 
 impl<T: Read + Write> ChunkStream<T> {
     pub fn new(stream: T) -> Self {
-        ChunkStream { stream }
+        ChunkStream {
+            stream,
+            max_message_size: None,
+        }
+    }
+
+    /// Fails `recv_into` with `InvalidData` once a message grows past `size`
+    /// bytes, before the rest of it is read.
+    ///
+    pub fn set_max_message_size(&mut self, size: Option<usize>) {
+        self.max_message_size = size;
     }
 
     pub fn send(&mut self, buf: &[u8]) -> ChunkResult<()> {
-        for chunk in buf.chunks(MAX_CHUNK_SIZE) {
-            self.stream.write_u16::<BigEndian>(chunk.len() as u16)?;
-            self.stream.write_all(chunk)?;
-        }
-        self.stream.write_u16::<BigEndian>(0)?;
-        Ok(())
+        let mut out = Vec::with_capacity(buf.len() + 4);
+        encode(buf, &mut out);
+        self.stream.write_all(&out)
     }
 
-    pub fn recv(&mut self) -> ChunkResult<Vec<u8>> {
-        let mut ret = Vec::new();
+    /// Reads the next message into `ret`, replacing what it held, so one
+    /// buffer can be reused for every message.
+    ///
+    pub fn recv_into(&mut self, ret: &mut Vec<u8>) -> ChunkResult<()> {
+        ret.clear();
         let mut size = self.stream.read_u16::<BigEndian>()?;
         while size != 0 {
-            (&mut self.stream)
-                .take(u64::from(size))
-                .read_to_end(&mut ret)?;
+            if let Some(max) = self.max_message_size {
+                if ret.len() + usize::from(size) > max {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Message exceeds the maximum size of {} bytes", max),
+                    ));
+                }
+            }
+            (&mut self.stream).take(u64::from(size)).read_to_end(ret)?;
             size = self.stream.read_u16::<BigEndian>()?;
         }
-        Ok(ret)
+        Ok(())
     }
 }
 
@@ -45,26 +73,31 @@ mod tests {
     fn byte_read_test() {
         let buf: &mut [u8] = &mut [0, 5, 0, 1, 2, 3, 4, 0, 0];
         let mut c = ChunkStream::new(::std::io::Cursor::new(buf));
-        let rbuf = c.recv().unwrap();
-        for i in 0..5 {
-            assert_eq!(i as u8, rbuf[i]);
-        }
+        let mut rbuf = vec![9, 9];
+        c.recv_into(&mut rbuf).unwrap();
+        assert_eq!(rbuf, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn max_message_size() {
+        let buf: &mut [u8] = &mut [0, 3, 0, 1, 2, 0, 3, 3, 4, 5, 0, 0];
+        let mut c = ChunkStream::new(::std::io::Cursor::new(buf));
+        c.set_max_message_size(Some(5));
+        let err = c.recv_into(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
     }
 
     #[test]
     fn round_trip() {
         let mut c = ChunkStream::new(::std::io::Cursor::new(Vec::new()));
         for _ in 0..5 {
-            c.send(&((0..100).into_iter().collect::<Vec<u8>>())[..])
-                .unwrap();
+            c.send(&((0..100).collect::<Vec<u8>>())[..]).unwrap();
         }
         c.stream.set_position(0);
+        let mut rbuf = Vec::new();
         for _ in 0..5 {
-            let rbuf = c.recv().unwrap();
-            assert_eq!(rbuf.len(), 100);
-            for i in 0..100 {
-                assert_eq!(i as u8, rbuf[i]);
-            }
+            c.recv_into(&mut rbuf).unwrap();
+            assert_eq!(rbuf, (0..100).collect::<Vec<u8>>());
         }
     }
 }
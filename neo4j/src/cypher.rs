@@ -1,52 +1,136 @@
 use std::collections::HashMap;
 
-use crate::bolt::{BoltStream, BoltSummary};
+use crate::{
+    bolt::{BoltError, BoltStream, BoltSummary, ConnectOptions, ProtocolVersion, ServerError},
+    Neo4jError, NeoResult, TransactionConfig,
+};
 
+#[cfg(feature = "rustls")]
+use crate::tls::TlsConfig;
 use log::info;
-use packstream::{parameters, Data, Value};
+use packstream::{parameters, Data, ListRef, Value};
 
-const USER_AGENT: &str = "rusty-bolt/0.1.0";
+pub const USER_AGENT: &str = "rusty-bolt/0.1.0";
+
+/// How many records each PULL asks for unless changed with
+/// `set_fetch_size`.
+///
+pub const DEFAULT_FETCH_SIZE: i64 = 1000;
+
+/// The host part of a `host:port` address, without IPv6 brackets.
+///
+pub(crate) fn host(address: &str) -> &str {
+    let host = match address.rfind(':') {
+        Some(i) if !address[i..].contains(']') => &address[..i],
+        _ => address,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// The BEGIN metadata carrying `bookmarks` and `config`, or before Bolt 3
+/// the parameters of the BEGIN statement. Servers from before bookmark
+/// lists were understood read only the single `bookmark`.
+///
+pub(crate) fn begin_extra(
+    version: ProtocolVersion,
+    bookmarks: &[String],
+    config: &TransactionConfig,
+) -> crate::bolt::Result<Option<Value>> {
+    let extra = config.extra(version, bookmarks)?;
+    if version.major >= 3 {
+        return Ok(extra);
+    }
+    Ok(bookmarks
+        .last()
+        .map(|last| parameters!("bookmark" => &last[..], "bookmarks" => bookmarks.to_vec()).into()))
+}
 
 pub struct CypherStream {
     bolt: BoltStream,
     server_version: Option<String>,
     bookmark: Option<String>,
+    fetch_size: i64,
 }
 impl CypherStream {
     pub fn connect(address: &str, user: &str, password: &str) -> crate::bolt::Result<CypherStream> {
-        info!("Connecting to bolt://{} as {}", address, user);
-        match BoltStream::connect(address) {
-            Ok(mut bolt) => {
-                bolt.init(USER_AGENT, user, password);
-                let init = bolt.collect_response();
-                bolt.send();
-                let init_summary = bolt.fetch_summary(init);
-                let summary = init_summary.unwrap();
-                bolt.compact_responses();
-
-                let server_version = match summary {
-                    BoltSummary::Success(ref metadata) => match metadata.get("server") {
-                        Some(&Value::String(ref string)) => Some(string.clone()),
-                        _ => None,
-                    },
-                    BoltSummary::Ignored(_) => {
-                        panic!("Protocol violation! INIT should not be IGNORED")
-                    }
-                    BoltSummary::Failure(_) => panic!("INIT returned FAILURE"),
-                };
-
-                info!("Connected to server version {:?}", server_version);
-                Ok(CypherStream {
-                    bolt,
-                    server_version,
-                    bookmark: None,
-                })
-            }
-            Err(e) => Err(e),
+        CypherStream::open(
+            address,
+            user,
+            password,
+            USER_AGENT,
+            &ConnectOptions::default(),
+        )
+    }
+
+    /// Connects over TLS. The host part of `address` is the name the server
+    /// certificate is checked against.
+    ///
+    #[cfg(feature = "rustls")]
+    pub fn connect_tls(
+        address: &str,
+        user: &str,
+        password: &str,
+        config: &TlsConfig,
+    ) -> crate::bolt::Result<CypherStream> {
+        let options = ConnectOptions {
+            tls: Some(config.clone()),
+            ..ConnectOptions::default()
+        };
+        CypherStream::open(address, user, password, USER_AGENT, &options)
+    }
+
+    /// Connects with the given socket settings and user agent. The host part
+    /// of `address` is the name any TLS certificate is checked against.
+    ///
+    pub fn open(
+        address: &str,
+        user: &str,
+        password: &str,
+        user_agent: &str,
+        options: &ConnectOptions,
+    ) -> crate::bolt::Result<CypherStream> {
+        info!("Connecting to {} as {}", address, user);
+        let mut bolt = BoltStream::open(address, host(address), options)?;
+        if bolt.protocol_version().major >= 3 {
+            bolt.hello(user_agent, user, password)?;
+        } else {
+            bolt.init(user_agent, user, password)?;
         }
+        let init = bolt.collect_response();
+        bolt.send()?;
+        let summary = bolt.fetch_summary(init)?;
+        bolt.compact_responses();
+
+        let server_version = match summary {
+            Some(BoltSummary::Success(ref metadata)) => match metadata.get("server") {
+                Some(Value::String(string)) => Some(string.clone()),
+                _ => None,
+            },
+            Some(BoltSummary::Failure(ref metadata)) => {
+                return Err(ServerError::from_metadata(metadata).into())
+            }
+            Some(BoltSummary::Ignored(_)) => {
+                return Err(BoltError::Protocol(String::from(
+                    "INIT/HELLO should not be IGNORED",
+                )))
+            }
+            None => {
+                return Err(BoltError::Protocol(String::from(
+                    "No summary for INIT/HELLO",
+                )))
+            }
+        };
+
+        info!("Connected to server version {:?}", server_version);
+        Ok(CypherStream {
+            bolt,
+            server_version,
+            bookmark: None,
+            fetch_size: DEFAULT_FETCH_SIZE,
+        })
     }
 
-    pub fn protocol_version(&self) -> u32 {
+    pub fn protocol_version(&self) -> ProtocolVersion {
         self.bolt.protocol_version()
     }
 
@@ -69,28 +153,71 @@ impl CypherStream {
         self.bookmark = None;
     }
 
-    pub fn begin_transaction(&mut self, bookmark: Option<&str>) {
-        info!("BEGIN {:?}->|...|", bookmark);
-        self.bolt.run(
-            "BEGIN",
-            bookmark.map(|v| parameters!("bookmark" => v).into()),
-        );
-        self.bolt.discard_all();
-        self.bolt.ignore_response();
-        self.bolt.ignore_response();
+    /// How many records to pull from the server at a time, or -1 for all
+    /// of them at once. Only Bolt v4 and above can pull in batches.
+    ///
+    pub fn set_fetch_size(&mut self, fetch_size: i64) {
+        self.fetch_size = fetch_size;
     }
 
-    pub fn commit_transaction(&mut self) -> Option<BoltSummary> {
-        self.bolt.run("COMMIT", None);
-        self.bolt.discard_all();
-        self.bolt.ignore_response();
+    pub fn fetch_size(&self) -> i64 {
+        self.fetch_size
+    }
+
+    /// Opens a transaction that waits for the server to have caught up
+    /// with every one of `bookmarks`.
+    ///
+    pub fn begin_transaction(&mut self, bookmarks: &[String]) -> NeoResult<()> {
+        self.begin_transaction_with_config(bookmarks, &TransactionConfig::default())
+    }
+
+    /// Like `begin_transaction`, with a timeout, metadata and so on for the
+    /// transaction. Waits for the server to accept the BEGIN, so that a
+    /// setting it refuses fails here with the server's error.
+    ///
+    pub fn begin_transaction_with_config(
+        &mut self,
+        bookmarks: &[String],
+        config: &TransactionConfig,
+    ) -> NeoResult<()> {
+        info!("BEGIN {:?}->|...|", bookmarks);
+        let extra = begin_extra(self.protocol_version(), bookmarks, config)?;
+        if self.protocol_version().major >= 3 {
+            self.bolt.begin(extra)?;
+        } else {
+            self.bolt.run("BEGIN", extra, None)?;
+            self.bolt.discard_all()?;
+        }
+        let begin = self.bolt.collect_response();
+        if self.protocol_version().major < 3 {
+            self.bolt.ignore_response();
+        }
+        self.bolt.send()?;
+        match self.fetch_header(begin)? {
+            Some(BoltSummary::Success(_)) => Ok(()),
+            Some(BoltSummary::Failure(metadata)) => Err(Neo4jError::BeginFailure(
+                ServerError::from_metadata(&metadata),
+            )),
+            Some(BoltSummary::Ignored(_)) => Err(Neo4jError::Ignored),
+            None => Err(BoltError::Protocol(String::from("No summary for BEGIN")).into()),
+        }
+    }
+
+    pub fn commit_transaction(&mut self) -> crate::bolt::Result<Option<BoltSummary>> {
+        if self.protocol_version().major >= 3 {
+            self.bolt.commit()?;
+        } else {
+            self.bolt.run("COMMIT", None, None)?;
+            self.bolt.discard_all()?;
+            self.bolt.ignore_response();
+        }
         let body = self.bolt.collect_response();
-        self.bolt.send();
-        let summary = self.bolt.fetch_summary(body);
+        self.bolt.send()?;
+        let summary = self.bolt.fetch_summary(body)?;
 
         let bookmark: Option<String> = match summary {
             Some(BoltSummary::Success(ref metadata)) => match metadata.get("bookmark") {
-                Some(&Value::String(ref bookmark)) => Some(bookmark.clone()),
+                Some(Value::String(bookmark)) => Some(bookmark.clone()),
                 _ => None,
             },
             _ => None,
@@ -108,111 +235,536 @@ impl CypherStream {
 
         info!("COMMIT |...|->{:?}", bookmark);
         self.bookmark = bookmark;
-        ret
+        Ok(ret)
     }
 
-    pub fn rollback_transaction(&mut self) {
-        self.bolt.run("ROLLBACK", None);
-        self.bolt.discard_all();
-        self.bolt.ignore_response();
+    pub fn rollback_transaction(&mut self) -> crate::bolt::Result<()> {
+        if self.protocol_version().major >= 3 {
+            self.bolt.rollback()?;
+        } else {
+            self.bolt.run("ROLLBACK", None, None)?;
+            self.bolt.discard_all()?;
+            self.bolt.ignore_response();
+        }
         let body = self.bolt.collect_response();
-        self.bolt.send();
-        self.bolt.fetch_summary(body);
+        self.bolt.send()?;
+        self.bolt.fetch_summary(body)?;
         self.bolt.compact_responses();
+        Ok(())
     }
 
-    pub fn reset(&mut self) {
-        self.bolt.reset();
+    pub fn reset(&mut self) -> crate::bolt::Result<()> {
+        self.bolt.reset()?;
         let reset = self.bolt.collect_response();
-        self.bolt.send();
-        self.bolt.fetch_summary(reset);
+        self.bolt.send()?;
+        self.bolt.fetch_summary(reset)?;
         self.bolt.compact_responses();
+        Ok(())
+    }
+
+    /// Fetches the routing table for `db`, or for the default database if
+    /// `None`, once the server has caught up with `bookmarks`. Uses the
+    /// ROUTE message where the server supports it and the routing
+    /// procedure otherwise. Returns a map with `ttl` and `servers` entries.
+    ///
+    pub fn route(
+        &mut self,
+        routing_context: &HashMap<String, String>,
+        db: Option<&str>,
+        bookmarks: &[String],
+    ) -> NeoResult<HashMap<String, Value>> {
+        let context: Value = routing_context.clone().into();
+        if self.protocol_version() >= ProtocolVersion::new(4, 3) {
+            self.bolt.route(context, bookmarks.to_vec(), db)?;
+            let response = self.bolt.collect_response();
+            self.send()?;
+            let summary = self.fetch_header(response)?;
+            return match summary {
+                Some(BoltSummary::Success(mut metadata)) => metadata
+                    .remove("rt")
+                    .and_then(Value::into_map)
+                    .ok_or_else(|| {
+                        BoltError::Protocol(String::from("ROUTE returned no routing table")).into()
+                    }),
+                Some(BoltSummary::Failure(metadata)) => Err(Neo4jError::RunFailure(
+                    ServerError::from_metadata(&metadata),
+                )),
+                Some(BoltSummary::Ignored(_)) => Err(Neo4jError::Ignored),
+                None => Err(BoltError::Protocol(String::from("No summary for ROUTE")).into()),
+            };
+        }
+
+        let mut result = if self.protocol_version().major >= 4 {
+            // the procedure only exists in the system database
+            let config = TransactionConfig {
+                database: Some(String::from("system")),
+                ..TransactionConfig::default()
+            };
+            self.run_with_config(
+                "CALL dbms.routing.getRoutingTable($context, $database)",
+                parameters!("context" => context, "database" => db.map_or(Value::Null, Value::from)),
+                bookmarks,
+                &config,
+            )?
+        } else {
+            self.run_with_config(
+                "CALL dbms.cluster.routing.getRoutingTable($context)",
+                parameters!("context" => context),
+                bookmarks,
+                &TransactionConfig::default(),
+            )?
+        };
+        let keys = result.keys().clone().into_vec().unwrap_or_default();
+        let mut table = None;
+        while let Some(Data::Record(values)) = self.fetch(&mut result)? {
+            table = Some(
+                keys.iter()
+                    .cloned()
+                    .filter_map(Value::into_string)
+                    .zip(values)
+                    .collect(),
+            );
+        }
+        match self.fetch_summary(&mut result)? {
+            Some(BoltSummary::Success(_)) => (),
+            Some(BoltSummary::Failure(metadata)) => {
+                return Err(Neo4jError::RunFailure(ServerError::from_metadata(
+                    &metadata,
+                )))
+            }
+            _ => return Err(Neo4jError::Ignored),
+        }
+        table.ok_or_else(|| {
+            BoltError::Protocol(String::from("Routing procedure returned no records")).into()
+        })
     }
 
     pub fn run(
         &mut self,
         statement: &str,
         parameters: HashMap<&str, Value>,
-    ) -> Result<StatementResult, HashMap<String, Value>> {
-        self.bolt.run(statement, Some(parameters.into()));
-        self.bolt.pull_all();
+    ) -> NeoResult<StatementResult> {
+        self.run_with_extra(statement, parameters, None)
+    }
+
+    /// Runs an auto-commit query that waits for `bookmarks`, configured by
+    /// `config`.
+    ///
+    pub fn run_with_config(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+        bookmarks: &[String],
+        config: &TransactionConfig,
+    ) -> NeoResult<StatementResult> {
+        let extra = config.extra(self.protocol_version(), bookmarks)?;
+        self.run_with_extra(statement, parameters, extra)
+    }
+
+    fn run_with_extra(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+        extra: Option<Value>,
+    ) -> NeoResult<StatementResult> {
+        self.bolt.run(statement, Some(parameters.into()), extra)?;
+        if self.protocol_version().major >= 4 {
+            self.bolt.pull(self.fetch_size, -1)?;
+        } else {
+            self.bolt.pull_all()?;
+        }
         let head = self.bolt.collect_response();
         let body = self.bolt.collect_response();
-        self.send();
-        match self.fetch_header(head) {
-            Some(header) => match header {
-                BoltSummary::Success(metadata) => Ok(StatementResult {
-                    header: metadata,
-                    body,
-                }),
-                BoltSummary::Ignored(metadata) => Err(metadata),
-                BoltSummary::Failure(metadata) => Err(metadata),
-            },
-            _ => panic!("Failed! No header summary"),
+        self.send()?;
+        match self.fetch_header(head)? {
+            Some(BoltSummary::Success(metadata)) => Ok(StatementResult {
+                header: metadata,
+                body,
+                done: false,
+                summary: None,
+            }),
+            Some(BoltSummary::Failure(metadata)) => Err(Neo4jError::RunFailure(
+                ServerError::from_metadata(&metadata),
+            )),
+            Some(BoltSummary::Ignored(_)) => Err(Neo4jError::Ignored),
+            None => Err(BoltError::Protocol(String::from("No summary for RUN")).into()),
         }
     }
 
-    pub fn run_unchecked(&mut self, statement: &str, parameters: HashMap<&str, Value>) {
-        self.bolt.run(statement, Some(parameters.into()));
-        self.bolt.discard_all();
+    pub fn run_unchecked(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+    ) -> crate::bolt::Result<()> {
+        self.run_unchecked_with_extra(statement, parameters, None)
+    }
+
+    /// Like `run_with_config`, without waiting for the outcome.
+    ///
+    pub fn run_unchecked_with_config(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+        bookmarks: &[String],
+        config: &TransactionConfig,
+    ) -> crate::bolt::Result<()> {
+        let extra = config.extra(self.protocol_version(), bookmarks)?;
+        self.run_unchecked_with_extra(statement, parameters, extra)
+    }
+
+    fn run_unchecked_with_extra(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+        extra: Option<Value>,
+    ) -> crate::bolt::Result<()> {
+        self.bolt.run(statement, Some(parameters.into()), extra)?;
+        self.bolt.discard_all()?;
         self.bolt.ignore_response();
         self.bolt.ignore_response();
-        self.send();
+        self.send()
     }
 
-    fn send(&mut self) {
-        self.bolt.send();
+    fn send(&mut self) -> crate::bolt::Result<()> {
+        self.bolt.send()
     }
 
     /// Fetch the RUN summary
-    fn fetch_header(&mut self, response_id: usize) -> Option<BoltSummary> {
-        let summary = self.bolt.fetch_summary(response_id);
+    fn fetch_header(&mut self, response_id: usize) -> crate::bolt::Result<Option<BoltSummary>> {
+        let summary = self.bolt.fetch_summary(response_id)?;
         info!("HEADER {:?}", summary);
         self.bolt.compact_responses();
-        match summary {
-            Some(BoltSummary::Ignored(_)) => panic!("RUN was IGNORED"),
-            Some(BoltSummary::Failure(_)) => {
-                self.bolt.ack_failure();
-                self.bolt.ignore_response();
-                self.bolt.send();
+        if let Some(BoltSummary::Failure(_)) = summary {
+            self.bolt.ack_failure()?;
+            self.bolt.ignore_response();
+            self.bolt.send()?;
+        }
+        Ok(summary)
+    }
+
+    /// Fetch the next record of a result, asking the server for another
+    /// batch when the last one runs out. A result should be read to the
+    /// end, or its summary fetched, before the next statement is run.
+    ///
+    pub fn fetch(&mut self, result: &mut StatementResult) -> crate::bolt::Result<Option<Data>> {
+        while !result.done {
+            if let Some(data) = self.bolt.fetch_record(result.body)? {
+                return Ok(Some(data));
             }
-            _ => (),
-        };
-        summary
+            self.end_batch(result, false)?;
+        }
+        Ok(None)
+    }
+
+    /// Like `fetch`, but hands the fields of the next record to `f` as they
+    /// sit in the receive buffer, and returns what `f` returns.
+    ///
+    pub fn fetch_with<T, F>(
+        &mut self,
+        result: &mut StatementResult,
+        mut f: F,
+    ) -> crate::bolt::Result<Option<T>>
+    where
+        F: FnMut(ListRef<'_>) -> T,
+    {
+        while !result.done {
+            if let Some(value) = self.bolt.fetch_record_with(result.body, &mut f)? {
+                return Ok(Some(value));
+            }
+            self.end_batch(result, false)?;
+        }
+        Ok(None)
     }
 
-    /// Fetch the result detail
-    pub fn fetch(&mut self, result: &StatementResult) -> Option<Data> {
-        self.bolt.fetch_record(result.body)
+    /// Fetch the result summary. Records not yet read are skipped, and any
+    /// the server has not sent yet are discarded without being sent.
+    ///
+    pub fn fetch_summary(
+        &mut self,
+        result: &mut StatementResult,
+    ) -> crate::bolt::Result<Option<BoltSummary>> {
+        while !result.done {
+            while self.bolt.fetch_record(result.body)?.is_some() {}
+            self.end_batch(result, true)?;
+        }
+        Ok(result.summary.take())
     }
 
-    /// Fetch the result summary
-    pub fn fetch_summary(&mut self, result: &StatementResult) -> Option<BoltSummary> {
-        let summary = self.bolt.fetch_summary(result.body);
-        info!("SUMMARY {:?}", summary);
+    /// Takes the summary ending the current batch of `result`. If the
+    /// server has more records, the next batch is pulled, or the rest are
+    /// discarded; otherwise the result is done.
+    ///
+    fn end_batch(
+        &mut self,
+        result: &mut StatementResult,
+        discard: bool,
+    ) -> crate::bolt::Result<()> {
+        let summary = self.bolt.fetch_summary(result.body)?;
         self.bolt.compact_responses();
+        if let Some(BoltSummary::Success(ref metadata)) = summary {
+            if metadata.get("has_more") == Some(&Value::Boolean(true)) {
+                let qid = result.qid();
+                if discard {
+                    self.bolt.discard(-1, qid)?;
+                } else {
+                    self.bolt.pull(self.fetch_size, qid)?;
+                }
+                result.body = self.bolt.collect_response();
+                return self.send();
+            }
+        }
+        info!("SUMMARY {:?}", summary);
         if let Some(BoltSummary::Failure(_)) = summary {
-            self.bolt.ack_failure();
+            self.bolt.ack_failure()?;
             self.bolt.ignore_response();
-            self.bolt.send();
+            self.bolt.send()?;
         }
-        summary
+        result.done = true;
+        result.summary = summary;
+        Ok(())
     }
 }
 
-//#[derive(Copy, Clone)]
 pub struct StatementResult {
     header: HashMap<String, Value>,
+    /// The response to the latest PULL or DISCARD.
     body: usize,
+    done: bool,
+    summary: Option<BoltSummary>,
 }
 impl StatementResult {
     pub fn keys(&self) -> &Value {
         &self.header["fields"]
     }
+
+    pub(crate) fn header(&self) -> &HashMap<String, Value> {
+        &self.header
+    }
+
+    /// The statement id within an explicit transaction, or -1 for the last
+    /// statement run.
+    ///
+    fn qid(&self) -> i64 {
+        match self.header.get("qid") {
+            Some(&Value::Integer(qid)) => qid,
+            _ => -1,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    use boltstub::BoltStub;
+
+    use crate::ServerError;
+
     #[test]
     fn it_works() {}
+
+    fn records(cypher: &mut CypherStream, result: &mut StatementResult) -> Vec<Vec<Value>> {
+        let mut records = Vec::new();
+        while let Some(Data::Record(values)) = cypher.fetch(result).unwrap() {
+            records.push(values);
+        }
+        records
+    }
+
+    #[test]
+    fn runs_and_pulls() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO {"user_agent": "rusty-bolt/0.1.0", "scheme": "basic", "principal": "neo4j", "credentials": "password"}
+            S: SUCCESS {"server": "Neo4j/4.4.0", "connection_id": "bolt-1"}
+            C: RUN "UNWIND range(1, $n) AS x RETURN x" {"n": 2} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["x"]}
+               RECORD [1]
+               RECORD [2]
+               SUCCESS {"type": "r"}
+            "#,
+        )
+        .unwrap();
+        let mut cypher = CypherStream::connect(stub.address(), "neo4j", "password").unwrap();
+        assert_eq!(cypher.server_version(), "Neo4j/4.4.0");
+        let mut result = cypher
+            .run("UNWIND range(1, $n) AS x RETURN x", parameters!("n" => 2))
+            .unwrap();
+        assert_eq!(result.keys(), &Value::from(vec!["x"]));
+        assert_eq!(
+            records(&mut cypher, &mut result),
+            vec![vec![1.into()], vec![2.into()]]
+        );
+        assert!(matches!(
+            cypher.fetch_summary(&mut result).unwrap(),
+            Some(BoltSummary::Success(_))
+        ));
+        drop(cypher);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn failed_run_is_reset() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.4.0"}
+            C: RUN "RETRUN 1" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: FAILURE {"code": "Neo.ClientError.Statement.SyntaxError", "message": "Invalid input"}
+               IGNORED
+            C: RESET
+            S: SUCCESS {}
+            C: RUN "RETURN 1" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["1"]}
+               RECORD [1]
+               SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut cypher = CypherStream::connect(stub.address(), "neo4j", "password").unwrap();
+        match cypher.run("RETRUN 1", parameters!()) {
+            Err(Neo4jError::RunFailure(ServerError { ref code, .. })) => {
+                assert_eq!(code, "Neo.ClientError.Statement.SyntaxError")
+            }
+            _ => panic!("RUN should have failed"),
+        }
+        let mut result = cypher.run("RETURN 1", parameters!()).unwrap();
+        assert_eq!(records(&mut cypher, &mut result), vec![vec![1.into()]]);
+        cypher.fetch_summary(&mut result).unwrap();
+        drop(cypher);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn legacy_failure_is_acknowledged() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 1
+            C: INIT "rusty-bolt/0.1.0" {"scheme": "basic", "principal": "neo4j", "credentials": *}
+            S: SUCCESS {"server": "Neo4j/3.4.0"}
+            C: RUN "RETURN 1/0" {}
+               PULL_ALL
+            S: SUCCESS {"fields": ["1/0"]}
+               FAILURE {"code": "Neo.ClientError.Statement.ArithmeticError", "message": "/ by zero"}
+            C: ACK_FAILURE
+            S: SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut cypher = CypherStream::connect(stub.address(), "neo4j", "password").unwrap();
+        assert_eq!(cypher.protocol_version(), ProtocolVersion::new(1, 0));
+        let mut result = cypher.run("RETURN 1/0", parameters!()).unwrap();
+        assert!(records(&mut cypher, &mut result).is_empty());
+        assert!(matches!(
+            cypher.fetch_summary(&mut result).unwrap(),
+            Some(BoltSummary::Failure(_))
+        ));
+        drop(cypher);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn commit_keeps_bookmark() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 3
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/3.5.0"}
+            C: BEGIN {"bookmarks": ["bm:1"]}
+            S: SUCCESS {}
+            C: RUN "CREATE ()" {} {}
+               PULL_ALL
+            S: SUCCESS {"fields": []}
+               SUCCESS {}
+            C: COMMIT
+            S: SUCCESS {"bookmark": "bm:2"}
+            "#,
+        )
+        .unwrap();
+        let mut cypher = CypherStream::connect(stub.address(), "neo4j", "password").unwrap();
+        cypher.begin_transaction(&[String::from("bm:1")]).unwrap();
+        let mut result = cypher.run("CREATE ()", parameters!()).unwrap();
+        assert!(records(&mut cypher, &mut result).is_empty());
+        cypher.fetch_summary(&mut result).unwrap();
+        cypher.commit_transaction().unwrap();
+        assert_eq!(cypher.bookmark().as_deref(), Some("bm:2"));
+        drop(cypher);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn pulls_in_batches_and_discards_the_rest() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.4.0"}
+            C: BEGIN {}
+            S: SUCCESS {}
+            C: RUN "UNWIND range(1, 5) AS x RETURN x" {} {}
+               PULL {"n": 2, "qid": -1}
+            S: SUCCESS {"fields": ["x"], "qid": 0}
+               RECORD [1]
+               RECORD [2]
+               SUCCESS {"has_more": true}
+            C: PULL {"n": 2, "qid": 0}
+            S: RECORD [3]
+               RECORD [4]
+               SUCCESS {"has_more": true}
+            C: DISCARD {"n": -1, "qid": 0}
+            S: SUCCESS {"type": "r"}
+            "#,
+        )
+        .unwrap();
+        let mut cypher = CypherStream::connect(stub.address(), "neo4j", "password").unwrap();
+        cypher.set_fetch_size(2);
+        cypher.begin_transaction(&[]).unwrap();
+        let mut result = cypher
+            .run("UNWIND range(1, 5) AS x RETURN x", parameters!())
+            .unwrap();
+        for x in 1..=3 {
+            match cypher.fetch(&mut result).unwrap() {
+                Some(Data::Record(values)) => assert_eq!(values, vec![Value::from(x)]),
+                None => panic!("Expected record {}", x),
+            }
+        }
+        match cypher.fetch_summary(&mut result).unwrap() {
+            Some(BoltSummary::Success(metadata)) => assert_eq!(metadata["type"], "r".into()),
+            other => panic!("Expected a summary, not {:?}", other),
+        }
+        assert!(cypher.fetch(&mut result).unwrap().is_none());
+        drop(cypher);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn begin_carries_bookmarks() {
+        let bookmarks = [String::from("bm:1"), String::from("bm:2")];
+        let config = TransactionConfig::default();
+        assert_eq!(
+            begin_extra(ProtocolVersion::new(4, 4), &[], &config).unwrap(),
+            None
+        );
+        assert_eq!(
+            begin_extra(ProtocolVersion::new(4, 4), &bookmarks, &config).unwrap(),
+            Some(parameters!("bookmarks" => vec!["bm:1", "bm:2"]).into())
+        );
+        assert_eq!(
+            begin_extra(ProtocolVersion::new(1, 0), &bookmarks, &config).unwrap(),
+            Some(parameters!("bookmark" => "bm:2", "bookmarks" => vec!["bm:1", "bm:2"]).into())
+        );
+    }
+
+    #[test]
+    fn host_of_address() {
+        assert_eq!(host("db.example.com:7687"), "db.example.com");
+        assert_eq!(host("db.example.com"), "db.example.com");
+        assert_eq!(host("[::1]:7687"), "::1");
+        assert_eq!(host("[::1]"), "::1");
+    }
 }
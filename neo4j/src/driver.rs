@@ -0,0 +1,426 @@
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use crate::{
+    bolt::ConnectOptions,
+    cypher::{CypherStream, DEFAULT_FETCH_SIZE, USER_AGENT},
+    retry::DEFAULT_MAX_RETRY_TIME,
+    Neo4jDB, Neo4jError, NeoResult,
+};
+
+#[cfg(feature = "rustls")]
+use crate::tls::TlsConfig;
+
+const DEFAULT_PORT: u16 = 7687;
+
+/// A parsed connection URI such as `neo4j+s://db.example.com:7687?region=eu`.
+/// Query parameters form the routing context and are only accepted by the
+/// routing `neo4j` schemes.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Uri {
+    pub scheme: String,
+    pub host: String,
+    pub port: u16,
+    pub routing_context: HashMap<String, String>,
+}
+
+impl Uri {
+    pub fn parse(uri: &str) -> NeoResult<Uri> {
+        let (scheme, rest) = split_once(uri, "://")
+            .ok_or_else(|| config_error(format!("URI {:?} has no scheme", uri)))?;
+        let scheme = scheme.to_ascii_lowercase();
+        match &scheme[..] {
+            "bolt" | "bolt+s" | "bolt+ssc" | "neo4j" | "neo4j+s" | "neo4j+ssc" => (),
+            _ => return Err(config_error(format!("Unsupported URI scheme {:?}", scheme))),
+        }
+        let (authority, query) = match split_once(rest, "?") {
+            Some((authority, query)) => (authority, Some(query)),
+            None => (rest, None),
+        };
+        let authority = authority.trim_end_matches('/');
+        if authority.contains('/') || authority.contains('@') {
+            return Err(config_error(format!(
+                "URI {:?} may only contain a host and port",
+                uri
+            )));
+        }
+        let (host, port) = split_host_port(authority)?;
+        if host.is_empty() {
+            return Err(config_error(format!("URI {:?} has no host", uri)));
+        }
+
+        let mut routing_context = HashMap::new();
+        for pair in query.unwrap_or("").split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = split_once(pair, "=").unwrap_or((pair, ""));
+            let key = percent_decode(key)?;
+            if key == "address" {
+                return Err(config_error(String::from(
+                    "The routing context may not contain \"address\"",
+                )));
+            }
+            routing_context.insert(key, percent_decode(value)?);
+        }
+        let uri = Uri {
+            scheme,
+            host: String::from(host),
+            port: port.unwrap_or(DEFAULT_PORT),
+            routing_context,
+        };
+        if !uri.is_routing() && !uri.routing_context.is_empty() {
+            return Err(config_error(format!(
+                "Scheme {:?} does not accept a routing context",
+                uri.scheme
+            )));
+        }
+        Ok(uri)
+    }
+
+    /// Whether the scheme asks for a routing driver (`neo4j`, `neo4j+s`,
+    /// `neo4j+ssc`) rather than a direct connection.
+    ///
+    pub fn is_routing(&self) -> bool {
+        self.scheme.starts_with("neo4j")
+    }
+
+    /// The `host:port` address to connect to.
+    ///
+    pub fn address(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}://{}", self.scheme, self.address())?;
+        let mut keys: Vec<_> = self.routing_context.keys().collect();
+        keys.sort();
+        for (i, key) in keys.into_iter().enumerate() {
+            let sep = if i == 0 { '?' } else { '&' };
+            write!(f, "{}{}={}", sep, key, self.routing_context[key])?;
+        }
+        Ok(())
+    }
+}
+
+fn config_error(message: String) -> Neo4jError {
+    Neo4jError::Config(message)
+}
+
+fn split_once<'a>(s: &'a str, sep: &str) -> Option<(&'a str, &'a str)> {
+    let i = s.find(sep)?;
+    Some((&s[..i], &s[i + sep.len()..]))
+}
+
+fn split_host_port(authority: &str) -> NeoResult<(&str, Option<u16>)> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority
+            .find(']')
+            .ok_or_else(|| config_error(format!("Unclosed IPv6 address in {:?}", authority)))?;
+        let port = &authority[end + 1..];
+        if !port.is_empty() && !port.starts_with(':') {
+            return Err(config_error(format!("Invalid address {:?}", authority)));
+        }
+        (&authority[1..end], port.get(1..))
+    } else {
+        match split_once(authority, ":") {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    let port = match port {
+        Some(port) => Some(
+            port.parse()
+                .map_err(|_| config_error(format!("Invalid port {:?}", port)))?,
+        ),
+        None => None,
+    };
+    Ok((host, port))
+}
+
+fn percent_decode(s: &str) -> NeoResult<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = s
+                    .get(i + 1..i + 3)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| config_error(format!("Invalid escape in {:?}", s)))?;
+                out.push(hex);
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).map_err(|_| config_error(format!("Invalid UTF-8 in {:?}", s)))
+}
+
+#[derive(Clone)]
+struct Config {
+    uri: Uri,
+    user: String,
+    password: String,
+    user_agent: String,
+    options: ConnectOptions,
+    max_retry_time: Duration,
+    fetch_size: i64,
+}
+
+/// Holds everything needed to open connections to a server. Cheap to
+/// clone; every clone shares the same configuration.
+///
+#[derive(Clone)]
+pub struct Driver {
+    config: Arc<Config>,
+}
+
+impl Driver {
+    pub fn builder(uri: &str) -> DriverBuilder {
+        DriverBuilder {
+            uri: String::from(uri),
+            user: String::new(),
+            password: String::new(),
+            user_agent: String::from(USER_AGENT),
+            options: ConnectOptions::default(),
+            max_retry_time: DEFAULT_MAX_RETRY_TIME,
+            fetch_size: DEFAULT_FETCH_SIZE,
+        }
+    }
+
+    pub fn uri(&self) -> &Uri {
+        &self.config.uri
+    }
+
+    pub fn user_agent(&self) -> &str {
+        &self.config.user_agent
+    }
+
+    pub fn max_retry_time(&self) -> Duration {
+        self.config.max_retry_time
+    }
+
+    /// Opens a new connection to the server named in the URI.
+    ///
+    pub fn connect(&self) -> NeoResult<Neo4jDB> {
+        self.connect_to(&self.config.uri.address())
+    }
+
+    /// Opens a new connection to `address` with this driver's settings.
+    ///
+    pub(crate) fn connect_to(&self, address: &str) -> NeoResult<Neo4jDB> {
+        let config = &self.config;
+        CypherStream::open(
+            address,
+            &config.user,
+            &config.password,
+            &config.user_agent,
+            &config.options,
+        )
+        .map(|conn| {
+            let mut db = Neo4jDB::new(conn);
+            db.set_max_retry_time(config.max_retry_time);
+            db.set_fetch_size(config.fetch_size);
+            db.origin = Some((self.clone(), String::from(address)));
+            db
+        })
+        .map_err(Neo4jError::ConnectFailure)
+    }
+}
+
+pub struct DriverBuilder {
+    uri: String,
+    user: String,
+    password: String,
+    user_agent: String,
+    options: ConnectOptions,
+    max_retry_time: Duration,
+    fetch_size: i64,
+}
+
+impl DriverBuilder {
+    pub fn auth(mut self, user: &str, password: &str) -> Self {
+        self.user = String::from(user);
+        self.password = String::from(password);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = String::from(user_agent);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long to wait for the server to send anything before failing
+    /// with a socket error.
+    ///
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.options.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Refuses any single message from the server larger than `size` bytes.
+    ///
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.options.max_message_size = Some(size);
+        self
+    }
+
+    /// How long managed transactions keep retrying transient failures
+    /// before giving up.
+    ///
+    pub fn max_retry_time(mut self, max_retry_time: Duration) -> Self {
+        self.max_retry_time = max_retry_time;
+        self
+    }
+
+    /// How many records each result pulls from the server at a time; see
+    /// `Neo4jDB::set_fetch_size`.
+    ///
+    pub fn fetch_size(mut self, fetch_size: i64) -> Self {
+        self.fetch_size = fetch_size;
+        self
+    }
+
+    /// Encrypts connections with these settings. Only valid with the plain
+    /// `bolt` and `neo4j` schemes; the `+s` and `+ssc` schemes choose their
+    /// own.
+    ///
+    #[cfg(feature = "rustls")]
+    pub fn tls(mut self, config: TlsConfig) -> Self {
+        self.options.tls = Some(config);
+        self
+    }
+
+    pub fn build(self) -> NeoResult<Driver> {
+        let uri = Uri::parse(&self.uri)?;
+        let options = if uri.scheme.contains('+') {
+            encrypted(&uri.scheme, self.options)?
+        } else {
+            self.options
+        };
+        Ok(Driver {
+            config: Arc::new(Config {
+                uri,
+                user: self.user,
+                password: self.password,
+                user_agent: self.user_agent,
+                options,
+                max_retry_time: self.max_retry_time,
+                fetch_size: self.fetch_size,
+            }),
+        })
+    }
+}
+
+#[cfg(feature = "rustls")]
+fn encrypted(scheme: &str, mut options: ConnectOptions) -> NeoResult<ConnectOptions> {
+    if options.tls.is_some() {
+        return Err(config_error(format!(
+            "Scheme {:?} already configures encryption",
+            scheme
+        )));
+    }
+    options.tls = TlsConfig::from_scheme(scheme);
+    Ok(options)
+}
+
+#[cfg(not(feature = "rustls"))]
+fn encrypted(scheme: &str, _options: ConnectOptions) -> NeoResult<ConnectOptions> {
+    Err(config_error(format!(
+        "Scheme {:?} requires the rustls feature",
+        scheme
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_uri() {
+        let uri = Uri::parse("neo4j://db.example.com:7688?region=eu&policy=my%20policy").unwrap();
+        assert_eq!(uri.scheme, "neo4j");
+        assert_eq!(uri.host, "db.example.com");
+        assert_eq!(uri.port, 7688);
+        assert_eq!(uri.routing_context["region"], "eu");
+        assert_eq!(uri.routing_context["policy"], "my policy");
+        assert!(uri.is_routing());
+
+        let uri = Uri::parse("bolt://[::1]").unwrap();
+        assert_eq!(uri.host, "::1");
+        assert_eq!(uri.port, 7687);
+        assert_eq!(uri.address(), "[::1]:7687");
+        assert!(!uri.is_routing());
+        assert_eq!(uri.to_string(), "bolt://[::1]:7687");
+    }
+
+    #[test]
+    fn reject_bad_uris() {
+        for uri in &[
+            "localhost:7687",
+            "http://localhost",
+            "bolt://",
+            "bolt://localhost:port",
+            "bolt://localhost?region=eu",
+            "neo4j://localhost?address=x",
+            "neo4j://user@localhost",
+            "bolt://[::1:7687",
+        ] {
+            match Uri::parse(uri) {
+                Err(Neo4jError::Config(_)) => (),
+                other => panic!("{} gave {:?}", uri, other),
+            }
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let driver = Driver::builder("bolt://localhost")
+            .auth("neo4j", "secret")
+            .user_agent("my-app/1.0")
+            .connect_timeout(Duration::from_secs(5))
+            .max_message_size(1 << 20)
+            .build()
+            .unwrap();
+        assert_eq!(driver.uri().address(), "localhost:7687");
+        assert_eq!(driver.user_agent(), "my-app/1.0");
+        assert_eq!(
+            driver.config.options.connect_timeout,
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[cfg(feature = "rustls")]
+    #[test]
+    fn encryption_from_scheme() {
+        let driver = Driver::builder("neo4j+ssc://localhost").build().unwrap();
+        assert!(driver.config.options.tls.is_some());
+        assert!(Driver::builder("bolt+s://localhost")
+            .tls(TlsConfig::new())
+            .build()
+            .is_err());
+    }
+
+    #[cfg(not(feature = "rustls"))]
+    #[test]
+    fn encryption_needs_rustls() {
+        assert!(Driver::builder("bolt+s://localhost").build().is_err());
+    }
+}
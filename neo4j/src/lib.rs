@@ -1,144 +1,578 @@
+#[cfg(feature = "tokio")]
+pub mod aio;
 pub mod bolt;
+mod bookmarks;
 mod chunk;
 pub mod cypher;
+mod driver;
+mod pool;
+mod record;
+mod retry;
+mod routing;
+mod summary;
+#[cfg(feature = "rustls")]
+pub mod tls;
+mod transaction;
+mod transport;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, error::Error, fmt, sync::Arc, time};
 
-use bolt::{BoltError, BoltSummary};
+use log::info;
+
+pub use bolt::{BoltError, Classification, ServerError};
+pub use bookmarks::BookmarkManager;
+pub use driver::{Driver, DriverBuilder, Uri};
+pub use pool::{ConnectionPool, PoolConfig, PooledSession};
+
+use bolt::{BoltSummary, ProtocolVersion};
 use cypher::{CypherStream, StatementResult};
-pub use packstream::{Data, Value};
+#[cfg(feature = "serde")]
+pub use packstream::{from_value, to_value, SerdeError};
+pub use packstream::{
+    Data, Date, DateTime, DateTimeZoneId, Duration, ListRef, LocalDateTime, LocalTime, MapRef,
+    Point2D, Point3D, Time, Value, ValueRef,
+};
+pub use record::{FromValue, Record, RecordError};
+use retry::{is_transient, is_unavailable, Backoff};
+pub use routing::{AccessMode, RoutingDriver, RoutingTable};
+pub use summary::{
+    Counters, InputPosition, Notification, Plan, ProfiledPlan, QueryType, ResultSummary,
+};
+pub use transaction::TransactionConfig;
 
 pub enum Neo4jError {
     ConnectFailure(BoltError),
-    CommitFailure(HashMap<String, Value>),
+    Bolt(BoltError),
+    BeginFailure(ServerError),
+    CommitFailure(ServerError),
     CommitNoSummary,
-    RunFailure(HashMap<String, Value>),
+    RunFailure(ServerError),
+    Ignored,
     ClosedTransaction,
+    Config(String),
+    PoolTimeout,
+    ServiceUnavailable(String),
+}
+
+impl Neo4jError {
+    /// The error reported by the server, if this failure came from one.
+    ///
+    pub fn server_error(&self) -> Option<&ServerError> {
+        match *self {
+            Neo4jError::ConnectFailure(BoltError::Failure(ref e))
+            | Neo4jError::Bolt(BoltError::Failure(ref e))
+            | Neo4jError::BeginFailure(ref e)
+            | Neo4jError::CommitFailure(ref e)
+            | Neo4jError::RunFailure(ref e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
-impl ::std::fmt::Debug for Neo4jError {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+impl fmt::Debug for Neo4jError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Neo4jError::ConnectFailure(ref e) => writeln!(f, "Failure to connect: {:?}", e),
+            Neo4jError::Bolt(ref e) => writeln!(f, "Bolt error: {:?}", e),
+            Neo4jError::BeginFailure(ref e) => writeln!(f, "Failure to begin: {:?}", e),
             Neo4jError::CommitFailure(ref e) => writeln!(f, "Failure to commit: {:?}", e),
             Neo4jError::CommitNoSummary => writeln!(f, "Commit returned no summary"),
             Neo4jError::RunFailure(ref e) => writeln!(f, "Failed to RUN: {:?}", e),
+            Neo4jError::Ignored => writeln!(f, "Request was ignored after an earlier failure"),
             Neo4jError::ClosedTransaction => writeln!(f, "Tried to operate on closed transaction"),
+            Neo4jError::Config(ref e) => writeln!(f, "Invalid configuration: {}", e),
+            Neo4jError::PoolTimeout => writeln!(f, "Timed out waiting for a pooled connection"),
+            Neo4jError::ServiceUnavailable(ref e) => writeln!(f, "Service unavailable: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for Neo4jError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Neo4jError::ConnectFailure(ref e) => write!(f, "Failure to connect: {}", e),
+            Neo4jError::Bolt(ref e) => write!(f, "Bolt error: {}", e),
+            Neo4jError::BeginFailure(ref e) => write!(f, "Failure to begin: {}", e),
+            Neo4jError::CommitFailure(ref e) => write!(f, "Failure to commit: {}", e),
+            Neo4jError::CommitNoSummary => write!(f, "Commit returned no summary"),
+            Neo4jError::RunFailure(ref e) => write!(f, "Failed to RUN: {}", e),
+            Neo4jError::Ignored => write!(f, "Request was ignored after an earlier failure"),
+            Neo4jError::ClosedTransaction => write!(f, "Tried to operate on closed transaction"),
+            Neo4jError::Config(ref e) => write!(f, "Invalid configuration: {}", e),
+            Neo4jError::PoolTimeout => write!(f, "Timed out waiting for a pooled connection"),
+            Neo4jError::ServiceUnavailable(ref e) => write!(f, "Service unavailable: {}", e),
         }
     }
 }
 
+impl Error for Neo4jError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            Neo4jError::ConnectFailure(ref e) | Neo4jError::Bolt(ref e) => Some(e),
+            Neo4jError::BeginFailure(ref e)
+            | Neo4jError::CommitFailure(ref e)
+            | Neo4jError::RunFailure(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BoltError> for Neo4jError {
+    fn from(val: BoltError) -> Self {
+        Neo4jError::Bolt(val)
+    }
+}
+
 pub trait Neo4jOperations {
-    fn run(&mut self, statement: &str, parameters: HashMap<&str, Value>) -> NeoResult<QueryResult>;
-    fn run_unchecked(&mut self, statement: &str, parameters: HashMap<&str, Value>);
+    fn run(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+    ) -> NeoResult<QueryResult<'_>>;
+    fn run_unchecked(&mut self, statement: &str, parameters: HashMap<&str, Value>)
+        -> NeoResult<()>;
 }
 
 pub type NeoResult<T> = Result<T, Neo4jError>;
 
-pub struct Neo4jTransaction<'a>(&'a mut Neo4jDB, bool);
+/// A transaction open on a `Neo4jDB`.
+///
+pub struct Neo4jTransaction<'a> {
+    db: &'a mut Neo4jDB,
+    closed: bool,
+    config: TransactionConfig,
+    /// The bookmarks the transaction was begun with.
+    bookmarks: Vec<String>,
+}
 
 impl<'a> Neo4jTransaction<'a> {
-    fn new(neo: &'a mut Neo4jDB) -> Self {
-        let mut s = Neo4jTransaction(neo, false);
-        s._start();
-        s
+    fn new(neo: &'a mut Neo4jDB, config: TransactionConfig) -> NeoResult<Self> {
+        let mut s = Neo4jTransaction {
+            db: neo,
+            closed: false,
+            config,
+            bookmarks: Vec::new(),
+        };
+        s._start()?;
+        Ok(s)
     }
 
-    fn _start(&mut self) {
-        self.0.conn.begin_transaction(None);
+    fn _start(&mut self) -> NeoResult<()> {
+        self.bookmarks = self.db.begin_bookmarks();
+        let begun = self
+            .db
+            .conn
+            .begin_transaction_with_config(&self.bookmarks, &self.config);
+        // there is nothing to roll back if BEGIN was refused
+        self.closed = begun.is_err();
+        begun
     }
 
     fn _commit(&mut self) -> NeoResult<HashMap<String, Value>> {
-        match self.0.conn.commit_transaction() {
+        let metadata = match self.db.conn.commit_transaction()? {
             Some(s) => match s {
-                BoltSummary::Failure(m) => Err(Neo4jError::CommitFailure(m)),
-                BoltSummary::Ignored(_) => unreachable!(),
-                BoltSummary::Success(m) => Ok(m),
+                BoltSummary::Failure(m) => {
+                    return Err(Neo4jError::CommitFailure(ServerError::from_metadata(&m)))
+                }
+                BoltSummary::Ignored(_) => return Err(Neo4jError::Ignored),
+                BoltSummary::Success(m) => m,
             },
-            None => Err(Neo4jError::CommitNoSummary),
+            None => return Err(Neo4jError::CommitNoSummary),
+        };
+        if let Some(bookmark) = self.db.conn.bookmark().clone() {
+            if let Some(ref manager) = self.db.bookmark_manager {
+                manager.update(&self.bookmarks, &bookmark);
+            }
+            self.db.bookmarks = vec![bookmark];
         }
+        Ok(metadata)
     }
 
-    fn _rollback(&mut self) {
-        self.0.conn.rollback_transaction();
+    fn _rollback(&mut self) -> NeoResult<()> {
+        Ok(self.db.conn.rollback_transaction()?)
     }
 
     pub fn commit_and_refresh(&mut self) -> NeoResult<HashMap<String, Value>> {
-        let ret = self._commit();
-        if ret.is_ok() {
-            self._start();
-        }
-        ret
+        let ret = self._commit()?;
+        self._start()?;
+        Ok(ret)
     }
 
     pub fn commit(mut self) -> NeoResult<HashMap<String, Value>> {
-        self.1 = true;
+        self.closed = true;
         self._commit()
     }
 
-    pub fn rollback(mut self) {
-        self.1 = true;
-        self._rollback();
+    pub fn rollback(mut self) -> NeoResult<()> {
+        self.closed = true;
+        self._rollback()
     }
 }
 
 impl<'a> Neo4jOperations for Neo4jTransaction<'a> {
-    fn run(&mut self, statement: &str, parameters: HashMap<&str, Value>) -> NeoResult<QueryResult> {
-        self.0.run(statement, parameters)
+    fn run(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+    ) -> NeoResult<QueryResult<'_>> {
+        let result = self.db.conn.run(statement, parameters)?;
+        Ok(QueryResult::new(result, &mut self.db.conn))
     }
 
-    fn run_unchecked(&mut self, statement: &str, parameters: HashMap<&str, Value>) {
-        self.0.run_unchecked(statement, parameters)
+    fn run_unchecked(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+    ) -> NeoResult<()> {
+        Ok(self.db.conn.run_unchecked(statement, parameters)?)
     }
 }
 
 impl<'a> Drop for Neo4jTransaction<'a> {
     fn drop(&mut self) {
-        if !self.1 {
-            self._rollback()
+        if !self.closed {
+            let _ = self._rollback();
         }
     }
 }
 
 pub struct Neo4jDB {
     conn: CypherStream,
+    retry: Backoff,
+    bookmarks: Vec<String>,
+    bookmark_manager: Option<BookmarkManager>,
+    database: Option<String>,
+    /// The driver and address this session was opened with, if any, used
+    /// to replace a lost connection.
+    origin: Option<(Driver, String)>,
 }
 
 impl Neo4jDB {
+    fn new(conn: CypherStream) -> Self {
+        Neo4jDB {
+            conn,
+            retry: Backoff::default(),
+            bookmarks: Vec::new(),
+            bookmark_manager: None,
+            database: None,
+            origin: None,
+        }
+    }
+
     pub fn connect(addr: &str, user: &str, pass: &str) -> NeoResult<Self> {
         match CypherStream::connect(addr, user, pass) {
-            Ok(s) => Ok(Neo4jDB { conn: s }),
+            Ok(s) => Ok(Neo4jDB::new(s)),
             Err(e) => Err(Neo4jError::ConnectFailure(e)),
         }
     }
 
-    pub fn transaction(&mut self) -> Neo4jTransaction {
-        Neo4jTransaction::new(self)
+    #[cfg(feature = "rustls")]
+    pub fn connect_tls(
+        addr: &str,
+        user: &str,
+        pass: &str,
+        config: &tls::TlsConfig,
+    ) -> NeoResult<Self> {
+        match CypherStream::connect_tls(addr, user, pass, config) {
+            Ok(s) => Ok(Neo4jDB::new(s)),
+            Err(e) => Err(Neo4jError::ConnectFailure(e)),
+        }
     }
-}
 
-impl Neo4jOperations for Neo4jDB {
-    fn run(&mut self, statement: &str, parameters: HashMap<&str, Value>) -> NeoResult<QueryResult> {
+    pub fn transaction(&mut self) -> NeoResult<Neo4jTransaction<'_>> {
+        self.transaction_with_config(TransactionConfig::default())
+    }
+
+    /// Begins a transaction with a timeout, metadata and so on. The same
+    /// settings are used again by `commit_and_refresh`.
+    ///
+    pub fn transaction_with_config(
+        &mut self,
+        config: TransactionConfig,
+    ) -> NeoResult<Neo4jTransaction<'_>> {
+        let config = self.session_config(config);
+        Neo4jTransaction::new(self, config)
+    }
+
+    /// Runs an auto-commit query with a timeout, metadata and so on. Like
+    /// a transaction, it waits for this session's bookmarks.
+    ///
+    pub fn run_with_config(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+        config: &TransactionConfig,
+    ) -> NeoResult<QueryResult<'_>> {
+        let bookmarks = self.begin_bookmarks();
+        let config = self.session_config(config.clone());
         let result = self
             .conn
-            .run(statement, parameters)
-            .map_err(Neo4jError::RunFailure)?;
+            .run_with_config(statement, parameters, &bookmarks, &config)?;
         Ok(QueryResult::new(result, &mut self.conn))
     }
 
-    fn run_unchecked(&mut self, statement: &str, parameters: HashMap<&str, Value>) {
-        self.conn.run_unchecked(statement, parameters)
+    /// The database this session runs against, or `None` for the user's
+    /// home database.
+    ///
+    pub fn database(&self) -> Option<&str> {
+        self.database.as_deref()
+    }
+
+    /// Runs every later transaction and query of this session against
+    /// `database`, unless its `TransactionConfig` names another. Fails if
+    /// the server is older than Neo4j 4.0.
+    ///
+    pub fn set_database(&mut self, database: Option<String>) -> NeoResult<()> {
+        if database.is_some() {
+            transaction::require_databases(self.conn.protocol_version())?;
+        }
+        self.database = database;
+        Ok(())
+    }
+
+    /// Asks the server for the name of the user's home database: the one
+    /// used when no other is chosen. Bolt 4.4 reports it in the routing
+    /// table. Older servers cannot name it, so this asks `db.info()` for
+    /// the database a query lands in when none is chosen. On Neo4j 4.3
+    /// that is the home database too, but before 4.3 it is the server's
+    /// default database.
+    ///
+    pub fn home_database(&mut self) -> NeoResult<String> {
+        let version = self.conn.protocol_version();
+        transaction::require_databases(version)?;
+        if version >= ProtocolVersion::new(4, 4) {
+            let bookmarks = self.begin_bookmarks();
+            let mut table = self.conn.route(&HashMap::new(), None, &bookmarks)?;
+            return table
+                .remove("db")
+                .and_then(Value::into_string)
+                .ok_or_else(|| {
+                    BoltError::Protocol(String::from("ROUTE returned no database")).into()
+                });
+        }
+        let bookmarks = self.begin_bookmarks();
+        let result = self.conn.run_with_config(
+            "CALL db.info() YIELD name RETURN name",
+            HashMap::new(),
+            &bookmarks,
+            &TransactionConfig::default(),
+        )?;
+        let mut result = QueryResult::new(result, &mut self.conn);
+        let name = result
+            .next()
+            .and_then(|Data::Record(mut values)| values.pop())
+            .and_then(Value::into_string);
+        result.consume()?;
+        name.ok_or_else(|| BoltError::Protocol(String::from("db.info() returned no name")).into())
+    }
+
+    /// `config` with this session's database filled in if it names none.
+    ///
+    fn session_config(&self, mut config: TransactionConfig) -> TransactionConfig {
+        if config.database.is_none() {
+            config.database = self.database.clone();
+        }
+        config
+    }
+
+    /// The bookmarks the next transaction waits for. Each commit replaces
+    /// them with the bookmark it leaves.
+    ///
+    pub fn bookmarks(&self) -> &[String] {
+        &self.bookmarks
+    }
+
+    /// Makes the next transaction wait for `bookmarks`, such as the
+    /// `last_bookmark` of another session, so that it sees what they wrote.
+    ///
+    pub fn set_bookmarks(&mut self, bookmarks: Vec<String>) {
+        self.bookmarks = bookmarks;
+    }
+
+    /// The bookmark left by the last transaction committed here.
+    ///
+    pub fn last_bookmark(&self) -> Option<&str> {
+        self.conn.bookmark().as_deref()
+    }
+
+    /// Shares bookmarks with every other session using `manager`: each
+    /// transaction also waits for the manager's bookmarks and leaves its
+    /// own there on commit.
+    ///
+    pub fn set_bookmark_manager(&mut self, manager: BookmarkManager) {
+        self.bookmark_manager = Some(manager);
+    }
+
+    /// Forgets the bookmarks, bookmark manager and database, as for a new
+    /// session.
+    ///
+    pub(crate) fn clear_session(&mut self) {
+        self.bookmarks.clear();
+        self.bookmark_manager = None;
+        self.database = None;
+        self.conn.clear_bookmark();
+    }
+
+    fn begin_bookmarks(&self) -> Vec<String> {
+        let mut bookmarks = self.bookmarks.clone();
+        if let Some(ref manager) = self.bookmark_manager {
+            bookmarks.extend(manager.bookmarks());
+        }
+        bookmarks.sort();
+        bookmarks.dedup();
+        bookmarks
+    }
+
+    /// How many records a result pulls from the server at a time. More
+    /// are only asked for once those have been read, and any left unread
+    /// when the result is dropped are discarded. Use -1 to pull every
+    /// record at once. Bolt v3 and older always pull everything.
+    ///
+    pub fn set_fetch_size(&mut self, fetch_size: i64) {
+        self.conn.set_fetch_size(fetch_size);
+    }
+
+    /// How long `read_transaction` and `write_transaction` keep retrying
+    /// before giving up. Defaults to 30 seconds.
+    ///
+    pub fn set_max_retry_time(&mut self, max_retry_time: time::Duration) {
+        self.retry.max_retry_time = max_retry_time;
+    }
+
+    /// Runs `work` in a read transaction and commits it if `work`
+    /// succeeds. After a transient failure, such as a deadlock, the whole
+    /// transaction is run again with exponential backoff until the maximum
+    /// retry time is reached. Client errors are never retried.
+    ///
+    /// If the connection is lost, a session opened through a `Driver` or
+    /// `ConnectionPool` connects to the same server again and retries. One
+    /// opened with `Neo4jDB::connect` cannot reconnect and returns the
+    /// error.
+    ///
+    pub fn read_transaction<T, F>(&mut self, work: F) -> NeoResult<T>
+    where
+        F: FnMut(&mut Neo4jTransaction<'_>) -> NeoResult<T>,
+    {
+        self.retry_transaction(AccessMode::Read, work)
+    }
+
+    /// Like `read_transaction`, for work that writes.
+    ///
+    pub fn write_transaction<T, F>(&mut self, work: F) -> NeoResult<T>
+    where
+        F: FnMut(&mut Neo4jTransaction<'_>) -> NeoResult<T>,
+    {
+        self.retry_transaction(AccessMode::Write, work)
+    }
+
+    fn retry_transaction<T, F>(&mut self, mode: AccessMode, mut work: F) -> NeoResult<T>
+    where
+        F: FnMut(&mut Neo4jTransaction<'_>) -> NeoResult<T>,
+    {
+        let retry = self.retry.clone();
+        let retryable: fn(&Neo4jError) -> bool = match self.origin {
+            Some(_) => |e| is_transient(e) || is_unavailable(e),
+            None => is_transient,
+        };
+        let mut first = true;
+        let mut lost = false;
+        retry.run(retryable, || {
+            let result = if lost {
+                self.reconnect()
+            } else if !std::mem::replace(&mut first, false) {
+                // A failed attempt can leave the connection needing a RESET.
+                self.conn.reset().map_err(Neo4jError::from)
+            } else {
+                Ok(())
+            }
+            .and_then(|()| transact(self, mode, &mut work));
+            lost = matches!(result, Err(ref e) if is_unavailable(e));
+            result
+        })
+    }
+
+    /// Replaces a lost connection with a new one to the same server. The
+    /// session keeps its bookmarks, database and fetch size.
+    ///
+    fn reconnect(&mut self) -> NeoResult<()> {
+        let (driver, address) = self.origin.as_ref().ok_or_else(|| {
+            Neo4jError::ServiceUnavailable(String::from(
+                "Connection lost and no driver to reopen it",
+            ))
+        })?;
+        info!("Reconnecting to {}", address);
+        let fetch_size = self.conn.fetch_size();
+        self.conn = driver.connect_to(address)?.conn;
+        self.conn.set_fetch_size(fetch_size);
+        Ok(())
+    }
+}
+
+/// Runs `work` in a new transaction, committing if it succeeds and rolling
+/// back otherwise. The access mode is sent with BEGIN where the protocol
+/// allows it; Bolt 1 and 2 have no way to say.
+///
+pub(crate) fn transact<T, F>(db: &mut Neo4jDB, mode: AccessMode, work: &mut F) -> NeoResult<T>
+where
+    F: FnMut(&mut Neo4jTransaction<'_>) -> NeoResult<T>,
+{
+    let mut config = TransactionConfig::default();
+    if db.conn.protocol_version().major >= 3 {
+        config.mode = mode;
+    }
+    let mut tx = db.transaction_with_config(config)?;
+    let value = work(&mut tx)?;
+    tx.commit()?;
+    Ok(value)
+}
+
+impl Neo4jOperations for Neo4jDB {
+    fn run(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+    ) -> NeoResult<QueryResult<'_>> {
+        self.run_with_config(statement, parameters, &TransactionConfig::default())
+    }
+
+    fn run_unchecked(
+        &mut self,
+        statement: &str,
+        parameters: HashMap<&str, Value>,
+    ) -> NeoResult<()> {
+        let bookmarks = self.begin_bookmarks();
+        let config = self.session_config(TransactionConfig::default());
+        Ok(self
+            .conn
+            .run_unchecked_with_config(statement, parameters, &bookmarks, &config)?)
     }
 }
 
 pub struct QueryResult<'a> {
     src: StatementResult,
     conn: &'a mut CypherStream,
+    keys: Arc<[String]>,
+    consumed: bool,
+    error: Option<Neo4jError>,
 }
 
 impl<'a> QueryResult<'a> {
     fn new(src: StatementResult, conn: &'a mut CypherStream) -> Self {
-        QueryResult { src, conn }
+        let keys = src
+            .keys()
+            .clone()
+            .into_vec()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(Value::into_string)
+            .collect();
+        QueryResult {
+            src,
+            conn,
+            keys,
+            consumed: false,
+            error: None,
+        }
     }
 
     pub fn first(self) -> Neo4jSingleIter<'a> {
@@ -146,19 +580,78 @@ impl<'a> QueryResult<'a> {
     }
 
     pub fn maps(self) -> Neo4jMapIter<'a> {
-        let keys = self.keys();
-        Neo4jMapIter { inner: self, keys }
+        Neo4jMapIter { inner: self }
+    }
+
+    /// Iterates over the remaining rows as `Record`s, which share this
+    /// result's field names.
+    ///
+    pub fn records(self) -> Records<'a> {
+        Records { inner: self }
     }
 
     pub fn keys(&self) -> Vec<String> {
-        self.src
-            .keys()
-            .clone()
-            .into_vec()
-            .unwrap()
-            .into_iter()
-            .map(|v| v.into_string().unwrap())
-            .collect()
+        self.keys.to_vec()
+    }
+
+    /// Reads the next record without building `Value`s, handing its
+    /// fields to `f` still packed. The record is checked once as it is
+    /// read, but only what `f` looks at is decoded, so a field can be
+    /// picked out of a wide record without copying the rest. Records that
+    /// arrived early, while another response was read, are kept packed
+    /// until then. Returns `None` at the end of the result or on failure,
+    /// as `next` does.
+    ///
+    pub fn next_with<T, F>(&mut self, f: F) -> Option<T>
+    where
+        F: FnMut(ListRef<'_>) -> T,
+    {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.conn.fetch_with(&mut self.src, f) {
+            Ok(value) => value,
+            Err(e) => {
+                self.error = Some(e.into());
+                None
+            }
+        }
+    }
+
+    /// The error that ended iteration early, if any. Iteration stops at
+    /// the first failure to read a record; the error is also returned by
+    /// `consume`.
+    ///
+    pub fn error(&self) -> Option<&Neo4jError> {
+        self.error.as_ref()
+    }
+
+    /// Discards any remaining records and returns the summary the server
+    /// sent at the end of the result.
+    ///
+    pub fn consume(mut self) -> NeoResult<ResultSummary> {
+        let summary = self.finish();
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        match summary? {
+            Some(BoltSummary::Success(metadata)) => {
+                Ok(ResultSummary::new(self.src.header(), &metadata))
+            }
+            Some(BoltSummary::Failure(metadata)) => Err(Neo4jError::RunFailure(
+                ServerError::from_metadata(&metadata),
+            )),
+            Some(BoltSummary::Ignored(_)) => Err(Neo4jError::Ignored),
+            None => Err(BoltError::Protocol(String::from("No summary for PULL")).into()),
+        }
+    }
+
+    fn finish(&mut self) -> NeoResult<Option<BoltSummary>> {
+        self.consumed = true;
+        if self.error.is_some() {
+            return Ok(None);
+        }
+        Ok(self.conn.fetch_summary(&mut self.src)?)
     }
 }
 
@@ -168,21 +661,34 @@ pub struct Neo4jSingleIter<'a> {
 
 pub struct Neo4jMapIter<'a> {
     inner: QueryResult<'a>,
-    keys: Vec<String>,
+}
+
+pub struct Records<'a> {
+    inner: QueryResult<'a>,
 }
 
 impl<'a> Iterator for QueryResult<'a> {
     type Item = Data;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.conn.fetch(&self.src)
+        if self.error.is_some() {
+            return None;
+        }
+        match self.conn.fetch(&mut self.src) {
+            Ok(data) => data,
+            Err(e) => {
+                self.error = Some(e.into());
+                None
+            }
+        }
     }
 }
 
 impl<'a> Drop for QueryResult<'a> {
     fn drop(&mut self) {
-        while self.conn.fetch(&self.src).is_some() {}
-        self.conn.fetch_summary(&self.src);
+        if !self.consumed {
+            let _ = self.finish();
+        }
     }
 }
 
@@ -201,12 +707,21 @@ impl<'a> Iterator for Neo4jMapIter<'a> {
     type Item = HashMap<String, Value>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner
-            .next()
-            .map(|Data::Record(v)| self.keys.clone().into_iter().zip(v).collect())
+        let Data::Record(values) = self.inner.next()?;
+        Some(self.inner.keys.iter().cloned().zip(values).collect())
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Data::Record(values) = self.inner.next()?;
+        Some(Record::new(self.inner.keys.clone(), values))
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct Node {
     pub id: u64,
     pub labs: Vec<String>,
@@ -248,6 +763,7 @@ impl Node {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct Rel {
     pub id: u64,
     pub src: u64,
@@ -301,3 +817,462 @@ impl Rel {
         }
     }
 }
+
+/// A relationship as it appears inside a path, without its end nodes.
+///
+#[derive(Clone, Debug)]
+pub struct UnboundRel {
+    pub id: u64,
+    pub label: String,
+    pub props: HashMap<String, Value>,
+}
+
+impl UnboundRel {
+    pub fn from_value(val: Value) -> Result<UnboundRel, &'static str> {
+        match val {
+            Value::Structure {
+                signature,
+                mut fields,
+            } => {
+                if signature != 0x72 {
+                    return Err("Structure has incorrect signature");
+                }
+                if fields.len() != 3 {
+                    return Err("UnboundRel structure has incorrect number of fields");
+                }
+                let id = fields
+                    .remove(0)
+                    .into_int()
+                    .ok_or("ID field is not an Integer")?;
+                let label = fields
+                    .remove(0)
+                    .into_string()
+                    .ok_or("Label field is not a String")?;
+                let props = fields
+                    .remove(0)
+                    .into_map()
+                    .ok_or("Props field is not a Map")?;
+                Ok(UnboundRel { id, label, props })
+            }
+            _ => Err("Value is not an unbound relationship"),
+        }
+    }
+
+    /// Binds the relationship to its start and end nodes.
+    ///
+    pub fn bind(self, src: u64, dst: u64) -> Rel {
+        Rel {
+            id: self.id,
+            src,
+            dst,
+            label: self.label,
+            props: self.props,
+        }
+    }
+}
+
+/// An alternating sequence of nodes and relationships. A path of length
+/// `n` holds `n` relationships and `n + 1` nodes, where a node appears once
+/// for every time the path visits it.
+///
+#[derive(Clone, Debug)]
+pub struct Path {
+    nodes: Vec<Node>,
+    rels: Vec<Rel>,
+}
+
+/// A single step along a path, with the relationship oriented as stored:
+/// `rel.src` and `rel.dst` may point either way relative to the traversal.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Segment<'a> {
+    pub start: &'a Node,
+    pub rel: &'a Rel,
+    pub end: &'a Node,
+}
+
+impl Path {
+    /// Rebuilds a path from the compact form sent by the server: the
+    /// distinct nodes, the distinct relationships and a list of
+    /// (relationship index, node index) pairs. Relationship indices are
+    /// one-based and negative when the relationship is traversed against
+    /// its direction.
+    ///
+    pub fn from_value(val: Value) -> Result<Path, &'static str> {
+        match val {
+            Value::Structure {
+                signature,
+                mut fields,
+            } => {
+                if signature != 0x50 {
+                    return Err("Structure has incorrect signature");
+                }
+                if fields.len() != 3 {
+                    return Err("Path structure has incorrect number of fields");
+                }
+                let distinct_nodes = fields
+                    .remove(0)
+                    .into_vec()
+                    .ok_or("Nodes field is not a List")?
+                    .into_iter()
+                    .map(Node::from_value)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let distinct_rels = fields
+                    .remove(0)
+                    .into_vec()
+                    .ok_or("Rels field is not a List")?
+                    .into_iter()
+                    .map(UnboundRel::from_value)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let indices = fields
+                    .remove(0)
+                    .into_vec()
+                    .ok_or("Indices field is not a List")?
+                    .into_iter()
+                    .map(|i| i.into_int::<i64>().ok_or("Index is not an Integer"))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if indices.len() % 2 != 0 {
+                    return Err("Indices field has an odd number of entries");
+                }
+
+                let mut last = distinct_nodes.first().ok_or("Path has no start node")?;
+                let mut nodes = vec![last.clone()];
+                let mut rels = Vec::with_capacity(indices.len() / 2);
+                for pair in indices.chunks(2) {
+                    let rel = distinct_rels
+                        .get((pair[0].unsigned_abs() as usize).wrapping_sub(1))
+                        .ok_or("Relationship index is out of range")?;
+                    let next = distinct_nodes
+                        .get(pair[1] as usize)
+                        .filter(|_| pair[1] >= 0)
+                        .ok_or("Node index is out of range")?;
+                    rels.push(if pair[0] > 0 {
+                        rel.clone().bind(last.id, next.id)
+                    } else {
+                        rel.clone().bind(next.id, last.id)
+                    });
+                    nodes.push(next.clone());
+                    last = next;
+                }
+                Ok(Path { nodes, rels })
+            }
+            _ => Err("Value is not a path"),
+        }
+    }
+
+    pub fn start(&self) -> &Node {
+        &self.nodes[0]
+    }
+
+    pub fn end(&self) -> &Node {
+        &self.nodes[self.nodes.len() - 1]
+    }
+
+    /// The number of relationships in the path.
+    ///
+    pub fn len(&self) -> usize {
+        self.rels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rels.is_empty()
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn rels(&self) -> &[Rel] {
+        &self.rels
+    }
+
+    pub fn segments(&self) -> Segments<'_> {
+        Segments {
+            path: self,
+            index: 0,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Path {
+    type Item = Segment<'a>;
+    type IntoIter = Segments<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.segments()
+    }
+}
+
+pub struct Segments<'a> {
+    path: &'a Path,
+    index: usize,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rel = self.path.rels.get(self.index)?;
+        let segment = Segment {
+            start: &self.path.nodes[self.index],
+            rel,
+            end: &self.path.nodes[self.index + 1],
+        };
+        self.index += 1;
+        Some(segment)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.path.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for Segments<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use boltstub::BoltStub;
+    use packstream::parameters;
+
+    #[test]
+    fn dropped_result_discards_unread_records() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.4.0"}
+            C: RUN "UNWIND range(1, 100) AS x RETURN x" {} {}
+               PULL {"n": 10, "qid": -1}
+            S: SUCCESS {"fields": ["x"]}
+               RECORD [1]
+               RECORD [2]
+               SUCCESS {"has_more": true}
+            C: DISCARD {"n": -1, "qid": -1}
+            S: SUCCESS {}
+            C: RUN "RETURN 1" {} {}
+               PULL {"n": 10, "qid": -1}
+            S: SUCCESS {"fields": ["1"]}
+               RECORD [1]
+               SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        db.set_fetch_size(10);
+        let first = db
+            .run("UNWIND range(1, 100) AS x RETURN x", parameters!())
+            .unwrap()
+            .first()
+            .next();
+        assert_eq!(first, Some(Value::Integer(1)));
+        let one: Vec<Value> = db.run("RETURN 1", parameters!()).unwrap().first().collect();
+        assert_eq!(one, vec![Value::Integer(1)]);
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn next_with_projects_fields_from_the_buffer() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.4.0"}
+            C: RUN "MATCH (p) RETURN p.bio, p.name" {} {}
+               PULL {"n": 2, "qid": -1}
+            S: SUCCESS {"fields": ["p.bio", "p.name"]}
+               RECORD ["long text", "Ann"]
+               RECORD ["more text", "Bob"]
+               SUCCESS {"has_more": true}
+            C: PULL {"n": 2, "qid": -1}
+            S: RECORD [null, "Cy"]
+               SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        db.set_fetch_size(2);
+        let mut result = db
+            .run("MATCH (p) RETURN p.bio, p.name", parameters!())
+            .unwrap();
+        let mut names = Vec::new();
+        while let Some(name) =
+            result.next_with(|fields| String::from(fields.get(1).and_then(|v| v.as_str()).unwrap()))
+        {
+            names.push(name);
+        }
+        assert_eq!(names, vec!["Ann", "Bob", "Cy"]);
+        assert!(result.error().is_none());
+        result.consume().unwrap();
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn session_database_is_sent_unless_overridden() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.4.0"}
+            C: RUN "RETURN 1" {} {"db": "sales"}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["1"]}
+               SUCCESS {}
+            C: BEGIN {"db": "sales"}
+            S: SUCCESS {}
+            C: RUN "RETURN 1" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["1"]}
+               SUCCESS {}
+            C: COMMIT
+            S: SUCCESS {}
+            C: BEGIN {"db": "system"}
+            S: SUCCESS {}
+            C: ROLLBACK
+            S: SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        db.set_database(Some(String::from("sales"))).unwrap();
+        assert_eq!(db.database(), Some("sales"));
+        db.run("RETURN 1", parameters!())
+            .unwrap()
+            .consume()
+            .unwrap();
+        let mut tx = db.transaction().unwrap();
+        tx.run("RETURN 1", parameters!())
+            .unwrap()
+            .consume()
+            .unwrap();
+        tx.commit().unwrap();
+        let system = TransactionConfig {
+            database: Some(String::from("system")),
+            ..TransactionConfig::default()
+        };
+        db.transaction_with_config(system)
+            .unwrap()
+            .rollback()
+            .unwrap();
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn resolves_home_database() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.3
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.3.0"}
+            C: RUN "CALL db.info() YIELD name RETURN name" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["name"]}
+               RECORD ["movies"]
+               SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        assert_eq!(db.home_database().unwrap(), "movies");
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn routes_for_home_database() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.4.0"}
+            C: ROUTE {} [] {}
+            S: SUCCESS {"rt": {"ttl": 300, "db": "movies", "servers": []}}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        assert_eq!(db.home_database().unwrap(), "movies");
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn databases_need_bolt_4() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 3
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/3.5.0"}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        match db.set_database(Some(String::from("sales"))) {
+            Err(Neo4jError::Bolt(BoltError::Protocol(ref message))) => {
+                assert!(message.contains("3.0"))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(db.home_database().is_err());
+        assert_eq!(db.database(), None);
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    fn node(id: i64) -> Value {
+        Value::Structure {
+            signature: 0x4E,
+            fields: vec![id.into(), vec!["Person"].into(), Value::Map(HashMap::new())],
+        }
+    }
+
+    fn unbound_rel(id: i64) -> Value {
+        Value::Structure {
+            signature: 0x72,
+            fields: vec![id.into(), "KNOWS".into(), Value::Map(HashMap::new())],
+        }
+    }
+
+    #[test]
+    fn path_from_value() {
+        // (1)-[10]->(2)<-[11]-(3)-[11]->(2) walks relationship 11 both ways
+        let path = Path::from_value(Value::Structure {
+            signature: 0x50,
+            fields: vec![
+                vec![node(1), node(2), node(3)].into(),
+                vec![unbound_rel(10), unbound_rel(11)].into(),
+                vec![1, 1, -2, 2, 2, 1].into(),
+            ],
+        })
+        .unwrap();
+        assert_eq!(path.len(), 3);
+        assert_eq!(path.start().id, 1);
+        assert_eq!(path.end().id, 2);
+        let steps: Vec<_> = path
+            .segments()
+            .map(|s| (s.start.id, s.rel.id, s.rel.src, s.rel.dst, s.end.id))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![(1, 10, 1, 2, 2), (2, 11, 3, 2, 3), (3, 11, 3, 2, 2)]
+        );
+    }
+
+    #[test]
+    fn path_with_bad_index() {
+        let path = Path::from_value(Value::Structure {
+            signature: 0x50,
+            fields: vec![
+                vec![node(1)].into(),
+                vec![unbound_rel(10)].into(),
+                vec![2, 0].into(),
+            ],
+        });
+        assert!(path.is_err());
+    }
+}
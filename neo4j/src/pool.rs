@@ -0,0 +1,340 @@
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::{Driver, Neo4jDB, Neo4jError, NeoResult};
+
+/// Limits for a `ConnectionPool`.
+///
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// The most connections open at once, both idle and in use.
+    pub max_size: usize,
+    /// How long `acquire` waits for a connection to come free.
+    pub acquisition_timeout: Duration,
+    /// Idle connections older than this are closed instead of reused.
+    pub max_idle_time: Option<Duration>,
+    /// Connections older than this are closed instead of reused.
+    pub max_lifetime: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 100,
+            acquisition_timeout: Duration::from_secs(60),
+            max_idle_time: None,
+            max_lifetime: Some(Duration::from_secs(3600)),
+        }
+    }
+}
+
+struct Idle {
+    db: Neo4jDB,
+    created: Instant,
+    returned: Instant,
+}
+
+struct State {
+    idle: VecDeque<Idle>,
+    open: usize,
+}
+
+struct Inner {
+    driver: Driver,
+    address: String,
+    config: PoolConfig,
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn expired(&self, created: Instant, now: Instant) -> bool {
+        match self.config.max_lifetime {
+            Some(lifetime) => now.duration_since(created) >= lifetime,
+            None => false,
+        }
+    }
+
+    /// Forgets a connection that has been closed, making room for another.
+    ///
+    fn discard(&self, state: &mut State) {
+        state.open -= 1;
+        self.released.notify_one();
+    }
+}
+
+/// A thread-safe pool of connections opened through a `Driver`. Sessions
+/// taken from the pool go back to it when dropped, and are checked with a
+/// RESET before they are handed out again.
+///
+#[derive(Clone)]
+pub struct ConnectionPool {
+    inner: Arc<Inner>,
+}
+
+impl ConnectionPool {
+    pub fn new(driver: Driver, config: PoolConfig) -> Self {
+        let address = driver.uri().address();
+        ConnectionPool::with_address(driver, address, config)
+    }
+
+    /// A pool of connections to `address` rather than the server named in
+    /// the driver's URI, used for the members of a cluster.
+    ///
+    pub(crate) fn with_address(driver: Driver, address: String, config: PoolConfig) -> Self {
+        ConnectionPool {
+            inner: Arc::new(Inner {
+                driver,
+                address,
+                config,
+                state: Mutex::new(State {
+                    idle: VecDeque::new(),
+                    open: 0,
+                }),
+                released: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Takes an idle connection, opens a new one if the pool has room, or
+    /// waits up to the acquisition timeout for one to be released.
+    ///
+    pub fn acquire(&self) -> NeoResult<PooledSession> {
+        let inner = &self.inner;
+        let deadline = Instant::now() + inner.config.acquisition_timeout;
+        let mut state = inner.lock();
+        loop {
+            let now = Instant::now();
+            while let Some(idle) = state.idle.pop_back() {
+                let stale = match inner.config.max_idle_time {
+                    Some(max_idle) => now.duration_since(idle.returned) >= max_idle,
+                    None => false,
+                };
+                if stale || inner.expired(idle.created, now) {
+                    debug!("Closing expired pooled connection");
+                    inner.discard(&mut state);
+                    continue;
+                }
+                drop(state);
+                let mut db = idle.db;
+                if db.conn.reset().is_ok() {
+                    return Ok(PooledSession {
+                        db: Some(db),
+                        created: idle.created,
+                        pool: inner.clone(),
+                    });
+                }
+                debug!("Closing pooled connection that failed RESET");
+                state = inner.lock();
+                inner.discard(&mut state);
+            }
+            if state.open < inner.config.max_size {
+                state.open += 1;
+                drop(state);
+                return match inner.driver.connect_to(&inner.address) {
+                    Ok(db) => Ok(PooledSession {
+                        db: Some(db),
+                        created: Instant::now(),
+                        pool: inner.clone(),
+                    }),
+                    Err(e) => {
+                        inner.discard(&mut inner.lock());
+                        Err(e)
+                    }
+                };
+            }
+            if now >= deadline {
+                return Err(Neo4jError::PoolTimeout);
+            }
+            state = inner
+                .released
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// The number of connections currently open, idle or in use.
+    ///
+    pub fn size(&self) -> usize {
+        self.inner.lock().open
+    }
+
+    pub fn idle(&self) -> usize {
+        self.inner.lock().idle.len()
+    }
+}
+
+/// A connection on loan from a `ConnectionPool`.
+///
+pub struct PooledSession {
+    db: Option<Neo4jDB>,
+    created: Instant,
+    pool: Arc<Inner>,
+}
+
+impl Deref for PooledSession {
+    type Target = Neo4jDB;
+
+    fn deref(&self) -> &Neo4jDB {
+        self.db.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledSession {
+    fn deref_mut(&mut self) -> &mut Neo4jDB {
+        self.db.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledSession {
+    fn drop(&mut self) {
+        let pool = &self.pool;
+        let mut state = pool.lock();
+        let now = Instant::now();
+        match self.db.take() {
+            Some(mut db) if !pool.expired(self.created, now) => {
+                db.clear_session();
+                state.idle.push_back(Idle {
+                    db,
+                    created: self.created,
+                    returned: now,
+                });
+                pool.released.notify_one();
+            }
+            _ => pool.discard(&mut state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use boltstub::BoltStub;
+
+    /// A server that lets any number of clients log in at once and answers
+    /// every RESET.
+    ///
+    fn server() -> BoltStub {
+        BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            !: ALLOW CONCURRENT
+            !: AUTO HELLO
+            !: AUTO RESET
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn pool(server: &BoltStub, config: PoolConfig) -> ConnectionPool {
+        let driver = Driver::builder(&format!("bolt://{}", server.address()))
+            .build()
+            .unwrap();
+        ConnectionPool::new(driver, config)
+    }
+
+    #[test]
+    fn reuses_released_connections() {
+        let server = server();
+        let pool = pool(&server, PoolConfig::default());
+        for _ in 0..3 {
+            let session = pool.acquire().unwrap();
+            assert_eq!(pool.idle(), 0);
+            drop(session);
+            assert_eq!(pool.idle(), 1);
+        }
+        assert_eq!(server.connections(), 1);
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn times_out_when_full() {
+        let server = server();
+        let pool = pool(
+            &server,
+            PoolConfig {
+                max_size: 1,
+                acquisition_timeout: Duration::from_millis(20),
+                ..PoolConfig::default()
+            },
+        );
+        let session = pool.acquire().unwrap();
+        match pool.acquire() {
+            Err(Neo4jError::PoolTimeout) => (),
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("pool exceeded its maximum size"),
+        }
+        drop(session);
+        assert!(pool.acquire().is_ok());
+    }
+
+    #[test]
+    fn evicts_idle_and_old_connections() {
+        let server = server();
+        let idle = pool(
+            &server,
+            PoolConfig {
+                max_idle_time: Some(Duration::from_millis(0)),
+                ..PoolConfig::default()
+            },
+        );
+        drop(idle.acquire().unwrap());
+        drop(idle.acquire().unwrap());
+        assert_eq!(server.connections(), 2);
+        assert_eq!(idle.size(), 1);
+
+        let old = pool(
+            &server,
+            PoolConfig {
+                max_lifetime: Some(Duration::from_millis(0)),
+                ..PoolConfig::default()
+            },
+        );
+        drop(old.acquire().unwrap());
+        assert_eq!(old.size(), 0);
+        drop(old.acquire().unwrap());
+        assert_eq!(server.connections(), 4);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let server = server();
+        let pool = pool(
+            &server,
+            PoolConfig {
+                max_size: 2,
+                ..PoolConfig::default()
+            },
+        );
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    for _ in 0..5 {
+                        let _session = pool.acquire().unwrap();
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(server.connections() <= 2);
+        assert_eq!(pool.idle(), pool.size());
+    }
+}
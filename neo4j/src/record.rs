@@ -0,0 +1,272 @@
+use std::{any, collections::HashMap, convert::TryFrom, error::Error, fmt, hash::Hash, sync::Arc};
+
+use crate::{
+    Date, DateTime, DateTimeZoneId, Duration, LocalDateTime, LocalTime, Node, Path, Point2D,
+    Point3D, Rel, Time, UnboundRel, Value,
+};
+
+/// Conversion out of a `Value`, used by the typed `Record` accessors.
+/// Returns `None` when the value holds a different type.
+///
+pub trait FromValue: Sized {
+    fn from_value(val: Value) -> Option<Self>;
+}
+
+impl FromValue for Value {
+    fn from_value(val: Value) -> Option<Self> {
+        Some(val)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(val: Value) -> Option<Self> {
+        val.into_bool()
+    }
+}
+
+macro_rules! impl_FromValue_Integer {
+    ($T:ty) => {
+        impl FromValue for $T {
+            fn from_value(val: Value) -> Option<Self> {
+                val.into_int()
+            }
+        }
+    };
+}
+
+impl_FromValue_Integer!(i8);
+impl_FromValue_Integer!(i16);
+impl_FromValue_Integer!(i32);
+impl_FromValue_Integer!(i64);
+impl_FromValue_Integer!(u8);
+impl_FromValue_Integer!(u16);
+impl_FromValue_Integer!(u32);
+impl_FromValue_Integer!(u64);
+impl_FromValue_Integer!(usize);
+
+impl FromValue for f64 {
+    fn from_value(val: Value) -> Option<Self> {
+        match val {
+            Value::Float(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(val: Value) -> Option<Self> {
+        val.into_string()
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(val: Value) -> Option<Self> {
+        match val {
+            Value::Null => Some(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(val: Value) -> Option<Self> {
+        val.into_vec()?.into_iter().map(T::from_value).collect()
+    }
+}
+
+impl<K, T> FromValue for HashMap<K, T>
+where
+    K: From<String> + Eq + Hash,
+    T: FromValue,
+{
+    fn from_value(val: Value) -> Option<Self> {
+        val.into_map()?
+            .into_iter()
+            .map(|(k, v)| T::from_value(v).map(|v| (K::from(k), v)))
+            .collect()
+    }
+}
+
+macro_rules! impl_FromValue_Graph {
+    ($T:ident) => {
+        impl FromValue for $T {
+            fn from_value(val: Value) -> Option<Self> {
+                $T::from_value(val).ok()
+            }
+        }
+    };
+}
+
+impl_FromValue_Graph!(Node);
+impl_FromValue_Graph!(Rel);
+impl_FromValue_Graph!(UnboundRel);
+impl_FromValue_Graph!(Path);
+
+macro_rules! impl_FromValue_TryFrom {
+    ($T:ident) => {
+        impl FromValue for $T {
+            fn from_value(val: Value) -> Option<Self> {
+                $T::try_from(val).ok()
+            }
+        }
+    };
+}
+
+impl_FromValue_TryFrom!(Date);
+impl_FromValue_TryFrom!(Time);
+impl_FromValue_TryFrom!(LocalTime);
+impl_FromValue_TryFrom!(DateTime);
+impl_FromValue_TryFrom!(DateTimeZoneId);
+impl_FromValue_TryFrom!(LocalDateTime);
+impl_FromValue_TryFrom!(Duration);
+impl_FromValue_TryFrom!(Point2D);
+impl_FromValue_TryFrom!(Point3D);
+
+#[derive(Debug)]
+pub enum RecordError {
+    MissingKey(String),
+    IndexOutOfRange {
+        index: usize,
+        len: usize,
+    },
+    TypeMismatch {
+        field: String,
+        expected: &'static str,
+        value: Value,
+    },
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecordError::MissingKey(ref key) => write!(f, "Record has no field {:?}", key),
+            RecordError::IndexOutOfRange { index, len } => write!(
+                f,
+                "Index {} is out of range for a record of {} fields",
+                index, len
+            ),
+            RecordError::TypeMismatch {
+                ref field,
+                expected,
+                ref value,
+            } => write!(
+                f,
+                "Field {} cannot be read as {}: found {:?}",
+                field, expected, value
+            ),
+        }
+    }
+}
+
+impl Error for RecordError {}
+
+/// A single row of a result. The field names are shared with every other
+/// record from the same result rather than copied into each one.
+///
+#[derive(Clone, Debug)]
+pub struct Record {
+    keys: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Record {
+    pub(crate) fn new(keys: Arc<[String]>, values: Vec<Value>) -> Self {
+        Record { keys, values }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn value(&self, key: &str) -> Option<&Value> {
+        let index = self.keys.iter().position(|k| k == key)?;
+        self.values.get(index)
+    }
+
+    /// Reads the field named `key` as a `T`.
+    ///
+    pub fn get<T: FromValue>(&self, key: &str) -> Result<T, RecordError> {
+        let value = self
+            .value(key)
+            .ok_or_else(|| RecordError::MissingKey(String::from(key)))?;
+        convert(key.to_string(), value)
+    }
+
+    /// Reads the field at position `index` as a `T`.
+    ///
+    pub fn get_index<T: FromValue>(&self, index: usize) -> Result<T, RecordError> {
+        let value = self.values.get(index).ok_or(RecordError::IndexOutOfRange {
+            index,
+            len: self.values.len(),
+        })?;
+        convert(index.to_string(), value)
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+
+    pub fn into_map(self) -> HashMap<String, Value> {
+        self.keys.iter().cloned().zip(self.values).collect()
+    }
+}
+
+fn convert<T: FromValue>(field: String, value: &Value) -> Result<T, RecordError> {
+    T::from_value(value.clone()).ok_or_else(|| RecordError::TypeMismatch {
+        field,
+        expected: any::type_name::<T>(),
+        value: value.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> Record {
+        let keys: Arc<[String]> = vec![String::from("name"), String::from("born")].into();
+        Record::new(keys, vec![Value::from("Keanu"), Value::from(1964)])
+    }
+
+    #[test]
+    fn typed_access() {
+        let record = record();
+        assert_eq!(record.get::<String>("name").unwrap(), "Keanu");
+        assert_eq!(record.get::<u16>("born").unwrap(), 1964);
+        assert_eq!(record.get_index::<i64>(1).unwrap(), 1964);
+        assert_eq!(record.get::<Option<i64>>("born").unwrap(), Some(1964));
+    }
+
+    #[test]
+    fn access_errors() {
+        let record = record();
+        match record.get::<String>("age") {
+            Err(RecordError::MissingKey(ref key)) if key == "age" => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match record.get_index::<String>(2) {
+            Err(RecordError::IndexOutOfRange { index: 2, len: 2 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match record.get::<bool>("name") {
+            Err(RecordError::TypeMismatch { expected, .. }) => assert_eq!(expected, "bool"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match record.get::<i8>("born") {
+            Err(RecordError::TypeMismatch { .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Retries for managed transactions. A transaction function is run again
+//! after a transient failure, waiting a little longer each time, until it
+//! succeeds, fails for good, or the retry time runs out.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::{BoltError, Classification, Neo4jError, NeoResult};
+
+/// Transient codes that come from the client itself cancelling the
+/// transaction, and so are not worth retrying.
+///
+const NOT_RETRYABLE: &[&str] = &[
+    "Neo.TransientError.Transaction.Terminated",
+    "Neo.TransientError.Transaction.LockClientStopped",
+];
+
+pub(crate) const DEFAULT_MAX_RETRY_TIME: Duration = Duration::from_secs(30);
+
+/// Exponential backoff with jitter: the first retry waits about
+/// `initial_delay`, and each one after that `multiplier` times as long,
+/// give or take a `jitter` fraction.
+///
+#[derive(Clone, Debug)]
+pub(crate) struct Backoff {
+    pub max_retry_time: Duration,
+    pub initial_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+}
+
+impl Backoff {
+    pub fn new(max_retry_time: Duration) -> Self {
+        Backoff {
+            max_retry_time,
+            initial_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+
+    /// Calls `attempt` until it returns something other than an error for
+    /// which `retryable` holds, or until waiting any longer would run past
+    /// the maximum retry time, in which case the last error is returned.
+    ///
+    pub fn run<T, F>(&self, retryable: fn(&Neo4jError) -> bool, mut attempt: F) -> NeoResult<T>
+    where
+        F: FnMut() -> NeoResult<T>,
+    {
+        let start = Instant::now();
+        let mut delay = self.initial_delay;
+        loop {
+            let error = match attempt() {
+                Err(e) => e,
+                result => return result,
+            };
+            if !retryable(&error) {
+                return Err(error);
+            }
+            let wait = delay.mul_f64(1.0 + self.jitter * (2.0 * random() - 1.0));
+            if start.elapsed() + wait > self.max_retry_time {
+                info!("Giving up on transaction: {}", error);
+                return Err(error);
+            }
+            info!("Retrying transaction in {:?}: {}", wait, error);
+            thread::sleep(wait);
+            delay = delay.mul_f64(self.multiplier);
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(DEFAULT_MAX_RETRY_TIME)
+    }
+}
+
+/// A number in `[0, 1)`, random enough to spread out retries.
+///
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+/// Whether the server reported a failure that may succeed if tried again,
+/// such as a deadlock or a leader switch.
+///
+pub(crate) fn is_transient(error: &Neo4jError) -> bool {
+    match error.server_error() {
+        Some(e) => {
+            e.classification == Classification::TransientError
+                && !NOT_RETRYABLE.contains(&&e.code[..])
+        }
+        None => false,
+    }
+}
+
+/// Whether `error` means the server could not be reached or the connection
+/// to it was lost.
+///
+pub(crate) fn is_unavailable(error: &Neo4jError) -> bool {
+    match *error {
+        Neo4jError::ConnectFailure(BoltError::Failure(_)) => false,
+        Neo4jError::ConnectFailure(_) | Neo4jError::ServiceUnavailable(_) => true,
+        Neo4jError::Bolt(BoltError::Socket(_)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use boltstub::BoltStub;
+    use packstream::parameters;
+
+    use crate::{Driver, Neo4jDB, Neo4jOperations, ServerError, Value};
+
+    const DEADLOCK: &str = "Neo.TransientError.Transaction.DeadlockDetected";
+
+    fn backoff(max_retry_time: Duration) -> Backoff {
+        Backoff {
+            initial_delay: Duration::from_millis(1),
+            ..Backoff::new(max_retry_time)
+        }
+    }
+
+    fn server_error(code: &str) -> Neo4jError {
+        Neo4jError::RunFailure(ServerError::new(code, "failed"))
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let mut attempts = 0;
+        let result = backoff(Duration::from_secs(5)).run(is_transient, || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(server_error(DEADLOCK))
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn never_retries_client_errors() {
+        for code in &[
+            "Neo.ClientError.Statement.SyntaxError",
+            "Neo.TransientError.Transaction.Terminated",
+        ] {
+            let mut attempts = 0;
+            let result: NeoResult<()> = backoff(Duration::from_secs(5)).run(is_transient, || {
+                attempts += 1;
+                Err(server_error(code))
+            });
+            assert!(result.is_err());
+            assert_eq!(attempts, 1);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_retry_time() {
+        let start = Instant::now();
+        let mut attempts = 0;
+        let result: NeoResult<()> = backoff(Duration::from_millis(50)).run(is_transient, || {
+            attempts += 1;
+            Err(server_error(DEADLOCK))
+        });
+        assert_eq!(result.unwrap_err().server_error().unwrap().code, DEADLOCK);
+        assert!(attempts > 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn connection_loss_is_retryable() {
+        assert!(is_unavailable(&Neo4jError::ServiceUnavailable(
+            String::new()
+        )));
+        assert!(!is_unavailable(&server_error(DEADLOCK)));
+        assert!(!is_transient(&Neo4jError::PoolTimeout));
+    }
+
+    #[test]
+    fn write_transaction_reruns_after_deadlock() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            !: AUTO HELLO
+            !: AUTO RESET
+            C: BEGIN {}
+            S: SUCCESS {}
+            C: RUN "RETURN 1 AS n" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["n"]}
+               RECORD [1]
+               SUCCESS {}
+            C: COMMIT
+            S: FAILURE {"code": "Neo.TransientError.Transaction.DeadlockDetected", "message": "Deadlock detected"}
+            C: BEGIN {}
+            S: SUCCESS {}
+            C: RUN "RETURN 1 AS n" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["n"]}
+               RECORD [1]
+               SUCCESS {}
+            C: COMMIT
+            S: SUCCESS {"bookmark": "bm"}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        db.retry.initial_delay = Duration::from_millis(1);
+        let mut runs = 0;
+        let n = db
+            .write_transaction(|tx| {
+                runs += 1;
+                let value = tx.run("RETURN 1 AS n", parameters!())?.first().next();
+                Ok(value.and_then(Value::into_int::<i64>))
+            })
+            .unwrap();
+        assert_eq!(n, Some(1));
+        assert_eq!(runs, 2);
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn read_transaction_reconnects_after_lost_connection() {
+        let stub = BoltStub::start_all(&[
+            r#"
+            !: BOLT 4.4
+            !: AUTO HELLO
+            C: BEGIN {"mode": "r"}
+            S: <EXIT>
+            "#,
+            r#"
+            !: BOLT 4.4
+            !: AUTO HELLO
+            C: BEGIN {"mode": "r"}
+            S: SUCCESS {}
+            C: RUN "RETURN 1 AS n" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["n"]}
+               RECORD [1]
+               SUCCESS {}
+            C: COMMIT
+            S: SUCCESS {"bookmark": "bm"}
+            "#,
+        ])
+        .unwrap();
+        let driver = Driver::builder(&format!("bolt://{}", stub.address()))
+            .build()
+            .unwrap();
+        let mut db = driver.connect().unwrap();
+        db.retry.initial_delay = Duration::from_millis(1);
+        let n = db
+            .read_transaction(|tx| {
+                let value = tx.run("RETURN 1 AS n", parameters!())?.first().next();
+                Ok(value.and_then(Value::into_int::<i64>))
+            })
+            .unwrap();
+        assert_eq!(n, Some(1));
+        assert_eq!(db.last_bookmark(), Some("bm"));
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn lost_connection_is_final_without_a_driver() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            !: AUTO HELLO
+            C: BEGIN {}
+            S: SUCCESS {}
+            C: RUN "RETURN 1 AS n" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: <EXIT>
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        db.retry.initial_delay = Duration::from_millis(1);
+        let mut runs = 0;
+        let result = db.write_transaction(|tx| {
+            runs += 1;
+            tx.run("RETURN 1 AS n", parameters!())?.consume()
+        });
+        assert!(is_unavailable(&result.unwrap_err()));
+        assert_eq!(runs, 1);
+        drop(db);
+        stub.finish().unwrap();
+    }
+}
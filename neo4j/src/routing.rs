@@ -0,0 +1,741 @@
+//! Client-side routing for `neo4j://` URIs. The routing table is fetched
+//! from one of the cluster's routers and tells the driver which members
+//! accept reads and which accept writes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+
+use crate::{
+    retry::{is_transient, is_unavailable, Backoff},
+    transact, BoltError, BookmarkManager, ConnectionPool, Driver, Neo4jDB, Neo4jError,
+    Neo4jTransaction, NeoResult, PoolConfig, PooledSession, Value,
+};
+
+const NOT_A_LEADER: &str = "Neo.ClientError.Cluster.NotALeader";
+const FORBIDDEN_ON_READ_ONLY: &str = "Neo.ClientError.General.ForbiddenOnReadOnlyDatabase";
+
+/// Whether a unit of work only reads, and so may run on any member, or
+/// also writes and must run on the leader.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AccessMode {
+    Read,
+    #[default]
+    Write,
+}
+
+/// The cluster members able to serve each role, as last reported by a
+/// router, and how long that report may be trusted.
+///
+#[derive(Clone, Debug)]
+pub struct RoutingTable {
+    pub routers: Vec<String>,
+    pub readers: Vec<String>,
+    pub writers: Vec<String>,
+    pub ttl: Duration,
+    fetched: Instant,
+}
+
+impl RoutingTable {
+    fn empty() -> Self {
+        RoutingTable {
+            routers: Vec::new(),
+            readers: Vec::new(),
+            writers: Vec::new(),
+            ttl: Duration::from_secs(0),
+            fetched: Instant::now(),
+        }
+    }
+
+    /// Parses the `{ttl, servers}` map returned by ROUTE or the routing
+    /// procedure.
+    ///
+    pub(crate) fn from_map(table: &HashMap<String, Value>) -> NeoResult<Self> {
+        let invalid =
+            || Neo4jError::Bolt(BoltError::Protocol(String::from("Malformed routing table")));
+        let ttl: u64 = table
+            .get("ttl")
+            .and_then(|v| v.clone().into_int())
+            .ok_or_else(invalid)?;
+        let servers = table
+            .get("servers")
+            .and_then(|v| v.clone().into_vec())
+            .ok_or_else(invalid)?;
+        let mut routing = RoutingTable {
+            ttl: Duration::from_secs(ttl),
+            ..RoutingTable::empty()
+        };
+        for server in servers {
+            let mut server = server.into_map().ok_or_else(invalid)?;
+            let addresses = server
+                .remove("addresses")
+                .and_then(Value::into_vec)
+                .ok_or_else(invalid)?
+                .into_iter()
+                .filter_map(Value::into_string);
+            let list = match server.remove("role").and_then(Value::into_string) {
+                Some(ref role) if role == "ROUTE" => &mut routing.routers,
+                Some(ref role) if role == "READ" => &mut routing.readers,
+                Some(ref role) if role == "WRITE" => &mut routing.writers,
+                _ => continue,
+            };
+            list.extend(addresses);
+        }
+        Ok(routing)
+    }
+
+    pub fn servers(&self, mode: AccessMode) -> &[String] {
+        match mode {
+            AccessMode::Read => &self.readers,
+            AccessMode::Write => &self.writers,
+        }
+    }
+
+    /// Whether the table must be fetched again before serving `mode`:
+    /// its TTL has passed, or it has no routers or no servers for `mode`.
+    ///
+    pub fn is_stale(&self, mode: AccessMode) -> bool {
+        self.fetched.elapsed() >= self.ttl
+            || self.routers.is_empty()
+            || self.servers(mode).is_empty()
+    }
+
+    fn remove(&mut self, address: &str) {
+        self.routers.retain(|a| a != address);
+        self.readers.retain(|a| a != address);
+        self.writers.retain(|a| a != address);
+    }
+}
+
+struct Inner {
+    driver: Driver,
+    pool_config: PoolConfig,
+    context: HashMap<String, String>,
+    tables: Mutex<HashMap<Option<String>, RoutingTable>>,
+    pools: Mutex<HashMap<String, ConnectionPool>>,
+    next: AtomicUsize,
+}
+
+/// Spreads work over the members of a cluster named by a `neo4j://` URI.
+/// Reads go to the readers and writes to the leader in turn, with a pool of
+/// connections kept for each member. Cheap to clone; clones share the
+/// routing tables and the pools.
+///
+#[derive(Clone)]
+pub struct RoutingDriver {
+    inner: Arc<Inner>,
+    database: Option<String>,
+    bookmark_manager: Option<BookmarkManager>,
+}
+
+impl RoutingDriver {
+    pub fn new(driver: Driver) -> NeoResult<Self> {
+        RoutingDriver::with_pool_config(driver, PoolConfig::default())
+    }
+
+    /// Like `new`, but with limits for the pool kept for each member.
+    ///
+    pub fn with_pool_config(driver: Driver, pool_config: PoolConfig) -> NeoResult<Self> {
+        let uri = driver.uri();
+        if !uri.is_routing() {
+            return Err(Neo4jError::Config(format!(
+                "Scheme {:?} does not support routing",
+                uri.scheme
+            )));
+        }
+        let mut context = uri.routing_context.clone();
+        context.insert(String::from("address"), uri.address());
+        Ok(RoutingDriver {
+            inner: Arc::new(Inner {
+                driver,
+                pool_config,
+                context,
+                tables: Mutex::new(HashMap::new()),
+                pools: Mutex::new(HashMap::new()),
+                next: AtomicUsize::new(0),
+            }),
+            database: None,
+            bookmark_manager: None,
+        })
+    }
+
+    /// A driver for the same cluster, sharing this one's pools, whose
+    /// sessions run against `database`, or the user's home database for
+    /// `None`. Each database is routed with its own table.
+    ///
+    pub fn with_database(&self, database: Option<String>) -> RoutingDriver {
+        RoutingDriver {
+            database,
+            ..self.clone()
+        }
+    }
+
+    /// A driver for the same cluster whose sessions share bookmarks
+    /// through `manager`, so that each transaction sees what the ones
+    /// before it wrote. Routing tables are fetched with the same bookmarks.
+    ///
+    pub fn with_bookmark_manager(&self, manager: BookmarkManager) -> RoutingDriver {
+        RoutingDriver {
+            bookmark_manager: Some(manager),
+            ..self.clone()
+        }
+    }
+
+    /// The database this driver's sessions run against, or `None` for the
+    /// user's home database.
+    ///
+    pub fn database(&self) -> Option<&str> {
+        self.database.as_deref()
+    }
+
+    /// A copy of the current routing table for this driver's database.
+    ///
+    pub fn routing_table(&self) -> RoutingTable {
+        self.with_table(|table| table.clone())
+    }
+
+    /// Takes a connection to a member that can serve `mode`, fetching a
+    /// fresh routing table first if needed. Members that cannot be reached
+    /// are dropped from the table and the next one is tried.
+    ///
+    pub fn session(&self, mode: AccessMode) -> NeoResult<PooledSession> {
+        self.acquire(mode, &mut HashSet::new())
+            .map(|(_, session)| session)
+    }
+
+    /// Runs `work` in a transaction on a reader, committing if it succeeds.
+    /// The whole transaction is retried with backoff after a transient
+    /// failure or when no reader can be reached, for up to the driver's
+    /// maximum retry time.
+    ///
+    pub fn read_transaction<T, F>(&self, work: F) -> NeoResult<T>
+    where
+        F: FnMut(&mut Neo4jTransaction<'_>) -> NeoResult<T>,
+    {
+        self.transaction(AccessMode::Read, work)
+    }
+
+    /// Like `read_transaction`, but runs on the leader.
+    ///
+    pub fn write_transaction<T, F>(&self, work: F) -> NeoResult<T>
+    where
+        F: FnMut(&mut Neo4jTransaction<'_>) -> NeoResult<T>,
+    {
+        self.transaction(AccessMode::Write, work)
+    }
+
+    fn transaction<T, F>(&self, mode: AccessMode, mut work: F) -> NeoResult<T>
+    where
+        F: FnMut(&mut Neo4jTransaction<'_>) -> NeoResult<T>,
+    {
+        let backoff = Backoff::new(self.inner.driver.max_retry_time());
+        backoff.run(
+            |e| is_transient(e) || is_unavailable(e),
+            || self.execute(mode, |db| transact(db, mode, &mut work)),
+        )
+    }
+
+    /// Runs `work` on a member that can serve `mode`. If the member goes
+    /// away, or a writer turns out not to be the leader, it is dropped
+    /// from the table and `work` is run again on another member.
+    ///
+    pub fn execute<T, F>(&self, mode: AccessMode, mut work: F) -> NeoResult<T>
+    where
+        F: FnMut(&mut Neo4jDB) -> NeoResult<T>,
+    {
+        let mut tried = HashSet::new();
+        loop {
+            let (address, mut session) = self.acquire(mode, &mut tried)?;
+            match work(&mut session) {
+                Err(ref e) if is_unavailable(e) => {
+                    info!("Lost connection to {}: {}", address, e);
+                    drop(session);
+                    self.forget(&address);
+                }
+                Err(ref e) if mode == AccessMode::Write && is_not_leader(e) => {
+                    info!("{} is no longer the leader", address);
+                    self.with_table(|table| table.writers.retain(|a| *a != address));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn with_table<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut RoutingTable) -> T,
+    {
+        let mut tables = self.inner.tables.lock().unwrap_or_else(|e| e.into_inner());
+        f(tables
+            .entry(self.database.clone())
+            .or_insert_with(RoutingTable::empty))
+    }
+
+    fn pool(&self, address: &str) -> ConnectionPool {
+        let inner = &self.inner;
+        let mut pools = inner.pools.lock().unwrap_or_else(|e| e.into_inner());
+        pools
+            .entry(String::from(address))
+            .or_insert_with(|| {
+                ConnectionPool::with_address(
+                    inner.driver.clone(),
+                    String::from(address),
+                    inner.pool_config.clone(),
+                )
+            })
+            .clone()
+    }
+
+    /// Drops an unreachable member from every routing table along with its
+    /// pool.
+    ///
+    fn forget(&self, address: &str) {
+        let mut tables = self.inner.tables.lock().unwrap_or_else(|e| e.into_inner());
+        for table in tables.values_mut() {
+            table.remove(address);
+        }
+        drop(tables);
+        let mut pools = self.inner.pools.lock().unwrap_or_else(|e| e.into_inner());
+        pools.remove(address);
+    }
+
+    fn acquire(
+        &self,
+        mode: AccessMode,
+        tried: &mut HashSet<String>,
+    ) -> NeoResult<(String, PooledSession)> {
+        loop {
+            if self.with_table(|table| table.is_stale(mode)) {
+                self.refresh()?;
+            }
+            let address = self.with_table(|table| {
+                let servers: Vec<_> = table
+                    .servers(mode)
+                    .iter()
+                    .filter(|a| !tried.contains(*a))
+                    .collect();
+                if servers.is_empty() {
+                    return None;
+                }
+                let next = self.inner.next.fetch_add(1, Ordering::Relaxed);
+                Some(servers[next % servers.len()].clone())
+            });
+            let address = address.ok_or_else(|| {
+                Neo4jError::ServiceUnavailable(format!("No {:?} servers available", mode))
+            })?;
+            tried.insert(address.clone());
+            match self.pool(&address).acquire() {
+                Ok(mut session) => {
+                    session.set_database(self.database.clone())?;
+                    if let Some(ref manager) = self.bookmark_manager {
+                        session.set_bookmark_manager(manager.clone());
+                    }
+                    return Ok((address, session));
+                }
+                Err(ref e) if is_unavailable(e) => {
+                    info!("Unable to connect to {}: {}", address, e);
+                    self.forget(&address);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Fetches a new routing table for this driver's database from the
+    /// known routers, falling back to the address in the URI.
+    ///
+    fn refresh(&self) -> NeoResult<()> {
+        let mut routers = self.with_table(|table| table.routers.clone());
+        let initial = self.inner.driver.uri().address();
+        if !routers.contains(&initial) {
+            routers.push(initial);
+        }
+        for router in routers {
+            debug!("Fetching routing table from {}", router);
+            let table = self.pool(&router).acquire().and_then(|mut session| {
+                let bookmarks = match self.bookmark_manager {
+                    Some(ref manager) => manager.bookmarks(),
+                    None => Vec::new(),
+                };
+                let table = session.conn.route(
+                    &self.inner.context,
+                    self.database.as_deref(),
+                    &bookmarks,
+                )?;
+                RoutingTable::from_map(&table)
+            });
+            match table {
+                Ok(table) if !table.routers.is_empty() && !table.readers.is_empty() => {
+                    info!("Routing table from {}: {:?}", router, table);
+                    self.with_table(|current| *current = table);
+                    return Ok(());
+                }
+                Ok(_) => debug!("Routing table from {} is incomplete", router),
+                Err(ref e) if is_unavailable(e) => {
+                    info!("Unable to route with {}: {}", router, e);
+                    self.forget(&router);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Neo4jError::ServiceUnavailable(String::from(
+            "Unable to retrieve a routing table",
+        )))
+    }
+}
+
+fn is_not_leader(error: &Neo4jError) -> bool {
+    match error.server_error() {
+        Some(e) => e.code == NOT_A_LEADER || e.code == FORBIDDEN_ON_READ_ONLY,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    use boltstub::BoltStub;
+    use packstream::parameters;
+
+    use crate::Neo4jOperations;
+
+    const HEADER: &str = "!: BOLT 4.4\n!: AUTO HELLO\n!: AUTO RESET\n";
+
+    /// The `servers` list of a routing table, written as in a script.
+    ///
+    fn servers(routers: &[&str], readers: &[&str], writers: &[&str]) -> String {
+        let server = |role: &str, addresses: &[&str]| {
+            let addresses: Vec<_> = addresses.iter().map(|a| format!("{:?}", a)).collect();
+            format!(
+                r#"{{"role": "{}", "addresses": [{}]}}"#,
+                role,
+                addresses.join(", ")
+            )
+        };
+        format!(
+            "[{}, {}, {}]",
+            server("ROUTE", routers),
+            server("READ", readers),
+            server("WRITE", writers)
+        )
+    }
+
+    fn table(ttl: i64, routers: &[&str], readers: &[&str], writers: &[&str]) -> String {
+        format!(
+            r#"{{"ttl": {}, "servers": {}}}"#,
+            ttl,
+            servers(routers, readers, writers)
+        )
+    }
+
+    /// A ROUTE asking `router` for the table of the database in `extra`,
+    /// with the routing context given by `driver`.
+    ///
+    fn route(router: &str, extra: &str) -> String {
+        format!(
+            "C: ROUTE {{\"region\": \"eu\", \"address\": \"{}\"}} [] {}\n",
+            router, extra
+        )
+    }
+
+    /// A cluster member that answers `RETURN $name` with its own name,
+    /// `runs` times over one connection.
+    ///
+    fn member(name: &str, runs: usize) -> BoltStub {
+        let mut script = String::from(HEADER);
+        for _ in 0..runs {
+            script.push_str(&format!(
+                "C: RUN \"RETURN $name\" {{}} {{}}\n   PULL {{\"n\": 1000, \"qid\": -1}}\n\
+                 S: SUCCESS {{\"fields\": [\"name\"]}}\n   RECORD [\"{}\"]\n   SUCCESS {{}}\n",
+                name
+            ));
+        }
+        BoltStub::start(&script).unwrap()
+    }
+
+    /// A server that hangs up on every client straight after the handshake.
+    ///
+    fn dead_server() -> BoltStub {
+        BoltStub::start("!: BOLT 4.4\n!: ALLOW RESTART\nS: <EXIT>").unwrap()
+    }
+
+    /// Starts a router whose script is written by `script`, which is given
+    /// the router's own address to list in the tables it returns.
+    ///
+    fn router_with<F>(script: F) -> BoltStub
+    where
+        F: FnOnce(&str) -> String,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let script = script(&address).parse().unwrap();
+        BoltStub::serve_on(listener, vec![script]).unwrap()
+    }
+
+    fn router(readers: &[&str], writers: &[&str]) -> BoltStub {
+        router_with(|address| {
+            format!(
+                "{}{}S: SUCCESS {{\"rt\": {}}}\n",
+                HEADER,
+                route(address, "{}"),
+                table(300, &[address], readers, writers)
+            )
+        })
+    }
+
+    fn driver(router: &BoltStub) -> RoutingDriver {
+        let driver = Driver::builder(&format!("neo4j://{}?region=eu", router.address()))
+            .build()
+            .unwrap();
+        RoutingDriver::new(driver).unwrap()
+    }
+
+    fn name(db: &mut Neo4jDB) -> NeoResult<String> {
+        let mut result = db.run("RETURN $name", parameters!())?.first();
+        Ok(result.next().unwrap().into_string().unwrap())
+    }
+
+    #[test]
+    fn parse_routing_table() {
+        let server = |role: &str, addresses: &[&str]| -> Value {
+            parameters!("role" => role, "addresses" => addresses.to_vec()).into()
+        };
+        let map = parameters!(
+            "ttl" => 300,
+            "servers" => vec![
+                server("ROUTE", &["a:1", "b:1"]),
+                server("READ", &["b:1"]),
+                server("WRITE", &["a:1"])
+            ]
+        )
+        .into_iter()
+        .map(|(k, v)| (String::from(k), v))
+        .collect();
+        let table = RoutingTable::from_map(&map).unwrap();
+        assert_eq!(table.routers, ["a:1", "b:1"]);
+        assert_eq!(table.readers, ["b:1"]);
+        assert_eq!(table.writers, ["a:1"]);
+        assert_eq!(table.ttl, Duration::from_secs(300));
+        assert!(!table.is_stale(AccessMode::Write));
+        assert!(RoutingTable::from_map(&HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn routes_by_access_mode() {
+        let reader = member("reader", 2);
+        let writer = member("writer", 2);
+        let router = router(&[reader.address()], &[writer.address()]);
+        let driver = driver(&router);
+        for _ in 0..2 {
+            assert_eq!(driver.execute(AccessMode::Read, name).unwrap(), "reader");
+            assert_eq!(driver.execute(AccessMode::Write, name).unwrap(), "writer");
+        }
+        assert_eq!(reader.connections(), 1);
+        assert_eq!(writer.connections(), 1);
+        assert_eq!(router.connections(), 1);
+    }
+
+    #[test]
+    fn sends_routing_context() {
+        // the router only answers a ROUTE carrying the URI's query string
+        // and the address the driver was given
+        let reader = member("reader", 0);
+        let router = router(&[reader.address()], &[]);
+        driver(&router).session(AccessMode::Read).unwrap();
+    }
+
+    #[test]
+    fn routes_the_session_database() {
+        let reader = BoltStub::start(&format!(
+            "{}{}",
+            HEADER,
+            r#"
+            C: RUN "RETURN $name" {} {"db": "sales"}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["name"]}
+               RECORD ["sales"]
+               SUCCESS {}
+            "#
+        ))
+        .unwrap();
+        let router = router_with(|address| {
+            format!(
+                "{}{}S: SUCCESS {{\"rt\": {}}}\n",
+                HEADER,
+                route(address, r#"{"db": "sales"}"#),
+                table(300, &[address], &[reader.address()], &[])
+            )
+        });
+        let driver = driver(&router).with_database(Some(String::from("sales")));
+        assert_eq!(driver.database(), Some("sales"));
+        assert_eq!(driver.execute(AccessMode::Read, name).unwrap(), "sales");
+        assert!(!driver.routing_table().is_stale(AccessMode::Read));
+        assert!(driver
+            .with_database(None)
+            .routing_table()
+            .is_stale(AccessMode::Read));
+    }
+
+    #[test]
+    fn routes_with_shared_bookmarks() {
+        let reader = BoltStub::start(&format!(
+            "{}{}",
+            HEADER,
+            r#"
+            C: RUN "RETURN $name" {} {"bookmarks": ["bm:1"]}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["name"]}
+               RECORD ["reader"]
+               SUCCESS {}
+            "#
+        ))
+        .unwrap();
+        let router = router_with(|address| {
+            format!(
+                "{}C: ROUTE {{\"region\": \"eu\", \"address\": \"{}\"}} [\"bm:1\"] {{}}\n\
+                 S: SUCCESS {{\"rt\": {}}}\n",
+                HEADER,
+                address,
+                table(300, &[address], &[reader.address()], &[])
+            )
+        });
+        let driver =
+            driver(&router).with_bookmark_manager(BookmarkManager::with_bookmarks(vec!["bm:1"]));
+        assert_eq!(driver.execute(AccessMode::Read, name).unwrap(), "reader");
+    }
+
+    #[test]
+    fn drops_unavailable_servers() {
+        let dead = dead_server();
+        let writer = member("writer", 3);
+        let router = router(&[writer.address()], &[dead.address(), writer.address()]);
+        let driver = driver(&router);
+        for _ in 0..3 {
+            assert_eq!(driver.execute(AccessMode::Write, name).unwrap(), "writer");
+        }
+        assert_eq!(driver.routing_table().writers, [writer.address()]);
+    }
+
+    #[test]
+    fn write_transaction_skips_lost_writer() {
+        let dead = dead_server();
+        let writer = BoltStub::start(&format!(
+            "{}{}",
+            HEADER,
+            r#"
+            C: BEGIN {}
+            S: SUCCESS {}
+            C: RUN "RETURN $name" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["name"]}
+               RECORD ["writer"]
+               SUCCESS {}
+            C: COMMIT
+            S: SUCCESS {"bookmark": "bm:1"}
+            "#
+        ))
+        .unwrap();
+        let router = router(&[writer.address()], &[dead.address(), writer.address()]);
+        let name = driver(&router)
+            .write_transaction(|tx| {
+                let mut result = tx.run("RETURN $name", parameters!())?.first();
+                Ok(result.next().and_then(Value::into_string))
+            })
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("writer"));
+    }
+
+    #[test]
+    fn retries_when_not_leader() {
+        let follower = BoltStub::start(&format!(
+            "{}{}",
+            HEADER,
+            r#"
+            C: RUN "RETURN $name" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: FAILURE {"code": "Neo.ClientError.Cluster.NotALeader", "message": "No write operations are allowed"}
+               IGNORED
+            "#
+        ))
+        .unwrap();
+        let leader = member("leader", 2);
+        let router = router(
+            &[follower.address()],
+            &[follower.address(), leader.address()],
+        );
+        let driver = driver(&router);
+        for _ in 0..2 {
+            assert_eq!(driver.execute(AccessMode::Write, name).unwrap(), "leader");
+        }
+        let table = driver.routing_table();
+        assert_eq!(table.writers, [leader.address()]);
+        assert_eq!(table.readers, [follower.address()]);
+    }
+
+    #[test]
+    fn legacy_routing_procedure() {
+        let reader = member("reader", 1);
+        let router = router_with(|address| {
+            format!(
+                r#"
+                !: BOLT 4.0
+                !: AUTO HELLO
+                !: AUTO RESET
+                C: RUN "CALL dbms.routing.getRoutingTable($context, $database)" {{"context": {{"region": "eu", "address": "{}"}}, "database": null}} {{"db": "system"}}
+                   PULL {{"n": 1000, "qid": -1}}
+                S: SUCCESS {{"fields": ["ttl", "servers"]}}
+                   RECORD [300, {}]
+                   SUCCESS {{}}
+                "#,
+                address,
+                servers(&[address], &[reader.address()], &[])
+            )
+        });
+        let driver = driver(&router);
+        assert_eq!(driver.execute(AccessMode::Read, name).unwrap(), "reader");
+    }
+
+    #[test]
+    fn refreshes_expired_table() {
+        let reader = member("reader", 0);
+        let router = router_with(|address| {
+            let fetch = format!(
+                "{}S: SUCCESS {{\"rt\": {}}}\n",
+                route(address, "{}"),
+                table(0, &[address], &[reader.address()], &[])
+            );
+            format!("{}{}{}", HEADER, fetch, fetch)
+        });
+        let driver = driver(&router);
+        driver.session(AccessMode::Read).unwrap();
+        driver.session(AccessMode::Read).unwrap();
+    }
+
+    #[test]
+    fn no_router_available() {
+        let dead = dead_server();
+        let driver = Driver::builder(&format!("neo4j://{}", dead.address()))
+            .build()
+            .unwrap();
+        match RoutingDriver::new(driver)
+            .unwrap()
+            .session(AccessMode::Read)
+        {
+            Err(Neo4jError::ServiceUnavailable(_)) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        let direct = Driver::builder("bolt://localhost").build().unwrap();
+        assert!(RoutingDriver::new(direct).is_err());
+    }
+}
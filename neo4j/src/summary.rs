@@ -0,0 +1,352 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::Value;
+
+/// Metadata collected once a result has been fully consumed, drawn from
+/// the SUCCESS summaries of both the RUN and the PULL.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ResultSummary {
+    pub query_type: Option<QueryType>,
+    pub counters: Counters,
+    pub result_available_after: Option<Duration>,
+    pub result_consumed_after: Option<Duration>,
+    pub plan: Option<Plan>,
+    pub profile: Option<ProfiledPlan>,
+    pub notifications: Vec<Notification>,
+    pub database: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryType {
+    ReadOnly,
+    ReadWrite,
+    WriteOnly,
+    SchemaWrite,
+}
+
+/// Update statistics reported by the server under `stats`.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub nodes_created: u64,
+    pub nodes_deleted: u64,
+    pub relationships_created: u64,
+    pub relationships_deleted: u64,
+    pub properties_set: u64,
+    pub labels_added: u64,
+    pub labels_removed: u64,
+    pub indexes_added: u64,
+    pub indexes_removed: u64,
+    pub constraints_added: u64,
+    pub constraints_removed: u64,
+    pub system_updates: u64,
+}
+
+/// A node in the execution plan returned for an `EXPLAIN` query.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+    pub operator_type: String,
+    pub identifiers: Vec<String>,
+    pub arguments: HashMap<String, Value>,
+    pub children: Vec<Plan>,
+}
+
+/// A node in the execution plan returned for a `PROFILE` query, along
+/// with what the operator actually cost.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfiledPlan {
+    pub operator_type: String,
+    pub identifiers: Vec<String>,
+    pub arguments: HashMap<String, Value>,
+    pub db_hits: u64,
+    pub rows: u64,
+    pub page_cache_hits: u64,
+    pub page_cache_misses: u64,
+    pub time: u64,
+    pub children: Vec<ProfiledPlan>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub code: String,
+    pub title: String,
+    pub description: String,
+    pub severity: String,
+    pub position: Option<InputPosition>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputPosition {
+    pub offset: u64,
+    pub line: u64,
+    pub column: u64,
+}
+
+fn int(map: &HashMap<String, Value>, key: &str) -> Option<u64> {
+    map.get(key).and_then(|v| v.clone().into_int())
+}
+
+fn string(map: &HashMap<String, Value>, key: &str) -> Option<String> {
+    map.get(key).and_then(|v| v.clone().into_string())
+}
+
+fn map(value: &Value) -> Option<&HashMap<String, Value>> {
+    match *value {
+        Value::Map(ref map) => Some(map),
+        _ => None,
+    }
+}
+
+fn list<'a>(map: &'a HashMap<String, Value>, key: &str) -> &'a [Value] {
+    match map.get(key) {
+        Some(Value::List(items)) => items,
+        _ => &[],
+    }
+}
+
+fn millis(map: &HashMap<String, Value>, key: &str, legacy_key: &str) -> Option<Duration> {
+    int(map, key)
+        .or_else(|| int(map, legacy_key))
+        .map(Duration::from_millis)
+}
+
+impl ResultSummary {
+    /// Combines the RUN metadata (`header`) with the final PULL metadata
+    /// (`footer`).
+    ///
+    pub(crate) fn new(header: &HashMap<String, Value>, footer: &HashMap<String, Value>) -> Self {
+        ResultSummary {
+            query_type: footer
+                .get("type")
+                .and_then(|v| QueryType::from_value(v.clone())),
+            counters: footer
+                .get("stats")
+                .and_then(map)
+                .map(Counters::from_map)
+                .unwrap_or_default(),
+            result_available_after: millis(header, "t_first", "result_available_after"),
+            result_consumed_after: millis(footer, "t_last", "result_consumed_after"),
+            plan: footer.get("plan").and_then(map).map(Plan::from_map),
+            profile: footer
+                .get("profile")
+                .and_then(map)
+                .map(ProfiledPlan::from_map),
+            notifications: list(footer, "notifications")
+                .iter()
+                .filter_map(map)
+                .map(Notification::from_map)
+                .collect(),
+            database: string(footer, "db"),
+        }
+    }
+}
+
+impl QueryType {
+    fn from_value(val: Value) -> Option<Self> {
+        match &val.into_string()?[..] {
+            "r" => Some(QueryType::ReadOnly),
+            "rw" => Some(QueryType::ReadWrite),
+            "w" => Some(QueryType::WriteOnly),
+            "s" => Some(QueryType::SchemaWrite),
+            _ => None,
+        }
+    }
+}
+
+impl Counters {
+    fn from_map(stats: &HashMap<String, Value>) -> Self {
+        let count = |key| int(stats, key).unwrap_or(0);
+        Counters {
+            nodes_created: count("nodes-created"),
+            nodes_deleted: count("nodes-deleted"),
+            relationships_created: count("relationships-created"),
+            relationships_deleted: count("relationships-deleted"),
+            properties_set: count("properties-set"),
+            labels_added: count("labels-added"),
+            labels_removed: count("labels-removed"),
+            indexes_added: count("indexes-added"),
+            indexes_removed: count("indexes-removed"),
+            constraints_added: count("constraints-added"),
+            constraints_removed: count("constraints-removed"),
+            system_updates: count("system-updates"),
+        }
+    }
+
+    /// Whether the query changed any data or schema, not counting system
+    /// updates.
+    ///
+    pub fn contains_updates(&self) -> bool {
+        self.nodes_created
+            + self.nodes_deleted
+            + self.relationships_created
+            + self.relationships_deleted
+            + self.properties_set
+            + self.labels_added
+            + self.labels_removed
+            + self.indexes_added
+            + self.indexes_removed
+            + self.constraints_added
+            + self.constraints_removed
+            > 0
+    }
+
+    pub fn contains_system_updates(&self) -> bool {
+        self.system_updates > 0
+    }
+}
+
+fn identifiers(plan: &HashMap<String, Value>) -> Vec<String> {
+    list(plan, "identifiers")
+        .iter()
+        .filter_map(|v| v.clone().into_string())
+        .collect()
+}
+
+fn arguments(plan: &HashMap<String, Value>) -> HashMap<String, Value> {
+    plan.get("args").and_then(map).cloned().unwrap_or_default()
+}
+
+impl Plan {
+    fn from_map(plan: &HashMap<String, Value>) -> Self {
+        Plan {
+            operator_type: string(plan, "operatorType").unwrap_or_default(),
+            identifiers: identifiers(plan),
+            arguments: arguments(plan),
+            children: list(plan, "children")
+                .iter()
+                .filter_map(map)
+                .map(Plan::from_map)
+                .collect(),
+        }
+    }
+}
+
+impl ProfiledPlan {
+    fn from_map(plan: &HashMap<String, Value>) -> Self {
+        ProfiledPlan {
+            operator_type: string(plan, "operatorType").unwrap_or_default(),
+            identifiers: identifiers(plan),
+            arguments: arguments(plan),
+            db_hits: int(plan, "dbHits").unwrap_or(0),
+            rows: int(plan, "rows").unwrap_or(0),
+            page_cache_hits: int(plan, "pageCacheHits").unwrap_or(0),
+            page_cache_misses: int(plan, "pageCacheMisses").unwrap_or(0),
+            time: int(plan, "time").unwrap_or(0),
+            children: list(plan, "children")
+                .iter()
+                .filter_map(map)
+                .map(ProfiledPlan::from_map)
+                .collect(),
+        }
+    }
+}
+
+impl Notification {
+    fn from_map(notification: &HashMap<String, Value>) -> Self {
+        Notification {
+            code: string(notification, "code").unwrap_or_default(),
+            title: string(notification, "title").unwrap_or_default(),
+            description: string(notification, "description").unwrap_or_default(),
+            severity: string(notification, "severity").unwrap_or_default(),
+            position: notification
+                .get("position")
+                .and_then(map)
+                .map(|p| InputPosition {
+                    offset: int(p, "offset").unwrap_or(0),
+                    line: int(p, "line").unwrap_or(0),
+                    column: int(p, "column").unwrap_or(0),
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use packstream::parameters;
+
+    fn to_map(value: Value) -> HashMap<String, Value> {
+        value.into_map().unwrap()
+    }
+
+    #[test]
+    fn parse_write_summary() {
+        let header = to_map(parameters!("fields" => vec!["n"], "t_first" => 3).into());
+        let footer = to_map(
+            parameters!(
+                "type" => "rw",
+                "t_last" => 12,
+                "db" => "neo4j",
+                "stats" => parameters!("nodes-created" => 2, "properties-set" => 4),
+                "notifications" => vec![parameters!(
+                    "code" => "Neo.ClientNotification.Statement.CartesianProduct",
+                    "title" => "Cartesian product",
+                    "description" => "Builds a cartesian product",
+                    "severity" => "WARNING",
+                    "position" => parameters!("offset" => 0, "line" => 1, "column" => 1)
+                )]
+            )
+            .into(),
+        );
+        let summary = ResultSummary::new(&header, &footer);
+        assert_eq!(summary.query_type, Some(QueryType::ReadWrite));
+        assert_eq!(
+            summary.result_available_after,
+            Some(Duration::from_millis(3))
+        );
+        assert_eq!(
+            summary.result_consumed_after,
+            Some(Duration::from_millis(12))
+        );
+        assert_eq!(summary.database.as_deref(), Some("neo4j"));
+        assert_eq!(summary.counters.nodes_created, 2);
+        assert_eq!(summary.counters.properties_set, 4);
+        assert!(summary.counters.contains_updates());
+        assert!(!summary.counters.contains_system_updates());
+        assert_eq!(summary.notifications.len(), 1);
+        assert_eq!(summary.notifications[0].severity, "WARNING");
+        assert_eq!(
+            summary.notifications[0].position,
+            Some(InputPosition {
+                offset: 0,
+                line: 1,
+                column: 1
+            })
+        );
+    }
+
+    #[test]
+    fn parse_profile() {
+        let leaf = parameters!(
+            "operatorType" => "AllNodesScan",
+            "identifiers" => vec!["n"],
+            "args" => parameters!("EstimatedRows" => 10.0),
+            "dbHits" => 11,
+            "rows" => 10,
+            "children" => Vec::<Value>::new()
+        );
+        let root = parameters!(
+            "operatorType" => "ProduceResults",
+            "identifiers" => vec!["n"],
+            "dbHits" => 0,
+            "rows" => 10,
+            "children" => vec![leaf]
+        );
+        let footer = to_map(parameters!("type" => "r", "profile" => root).into());
+        let summary = ResultSummary::new(&HashMap::new(), &footer);
+        let profile = summary.profile.unwrap();
+        assert!(summary.plan.is_none());
+        assert_eq!(profile.operator_type, "ProduceResults");
+        assert_eq!(profile.children.len(), 1);
+        assert_eq!(profile.children[0].db_hits, 11);
+        assert_eq!(
+            profile.children[0].arguments["EstimatedRows"],
+            Value::Float(10.0)
+        );
+        assert!(!summary.counters.contains_updates());
+    }
+}
@@ -0,0 +1,525 @@
+//! TLS settings for encrypted Bolt connections.
+//!
+//! `TlsConfig::new` matches the `bolt+s` / `neo4j+s` schemes: the server
+//! certificate must chain to a trusted root and match the host name.
+//! `TlsConfig::self_signed` matches `bolt+ssc` / `neo4j+ssc` and accepts
+//! any certificate.
+
+use std::{convert::TryFrom, fmt, iter::FromIterator, net::TcpStream, sync::Arc};
+
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::{self, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore,
+    SignatureScheme, StreamOwned,
+};
+
+use crate::bolt::{BoltError, Result};
+
+#[derive(Clone, Debug)]
+enum Roots {
+    WebPki,
+    Custom(Vec<CertificateDer<'static>>),
+    TrustAll,
+}
+
+pub struct TlsConfig {
+    roots: Roots,
+    client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    verify_hostname: bool,
+}
+
+impl TlsConfig {
+    /// Verifies the server against the bundled Mozilla root certificates.
+    ///
+    pub fn new() -> Self {
+        TlsConfig {
+            roots: Roots::WebPki,
+            client_auth: None,
+            verify_hostname: true,
+        }
+    }
+
+    /// Encrypts the connection without verifying the server certificate.
+    ///
+    pub fn self_signed() -> Self {
+        TlsConfig {
+            roots: Roots::TrustAll,
+            ..TlsConfig::new()
+        }
+    }
+
+    /// Picks the settings implied by a URI scheme such as `bolt+s`, or
+    /// `None` if the scheme does not ask for encryption.
+    ///
+    pub fn from_scheme(scheme: &str) -> Option<Self> {
+        if scheme.ends_with("+s") {
+            Some(TlsConfig::new())
+        } else if scheme.ends_with("+ssc") {
+            Some(TlsConfig::self_signed())
+        } else {
+            None
+        }
+    }
+
+    /// Trusts only the given DER-encoded root certificate, plus any added
+    /// by later calls.
+    ///
+    pub fn with_root_certificate(mut self, der: Vec<u8>) -> Self {
+        let cert = CertificateDer::from(der);
+        match self.roots {
+            Roots::Custom(ref mut roots) => roots.push(cert),
+            _ => self.roots = Roots::Custom(vec![cert]),
+        }
+        self
+    }
+
+    /// Trusts only the root certificates in a PEM bundle, plus any added by
+    /// later calls.
+    ///
+    pub fn with_root_certificates_pem(mut self, pem: &[u8]) -> Result<Self> {
+        for cert in CertificateDer::pem_slice_iter(pem) {
+            self = self.with_root_certificate(cert.map_err(tls_error)?.to_vec());
+        }
+        Ok(self)
+    }
+
+    /// Presents a client certificate chain and its private key, both PEM
+    /// encoded, to servers that ask for one.
+    ///
+    pub fn with_client_certificate_pem(mut self, chain: &[u8], key: &[u8]) -> Result<Self> {
+        let chain = CertificateDer::pem_slice_iter(chain)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(tls_error)?;
+        let key = PrivateKeyDer::from_pem_slice(key).map_err(tls_error)?;
+        self.client_auth = Some((chain, key));
+        Ok(self)
+    }
+
+    /// Whether the certificate must be issued for the host being connected
+    /// to. Has no effect on a self-signed configuration.
+    ///
+    pub fn verify_hostname(mut self, verify: bool) -> Self {
+        self.verify_hostname = verify;
+        self
+    }
+
+    fn client_config(&self) -> Result<ClientConfig> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let roots = match self.roots {
+            Roots::WebPki => {
+                RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+            }
+            Roots::Custom(ref certs) => {
+                let mut roots = RootCertStore::empty();
+                for cert in certs {
+                    roots.add(cert.clone()).map_err(tls_error)?;
+                }
+                roots
+            }
+            Roots::TrustAll => RootCertStore::empty(),
+        };
+        let verifier: Arc<dyn ServerCertVerifier> = match self.roots {
+            Roots::TrustAll => Arc::new(TrustAll(provider)),
+            _ => {
+                let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .build()
+                    .map_err(tls_error)?;
+                if self.verify_hostname {
+                    webpki
+                } else {
+                    Arc::new(IgnoreHostname(webpki))
+                }
+            }
+        };
+        let builder = builder
+            .dangerous()
+            .with_custom_certificate_verifier(verifier);
+        match self.client_auth {
+            Some((ref chain, ref key)) => builder
+                .with_client_auth_cert(chain.clone(), key.clone_key())
+                .map_err(tls_error),
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+
+    /// Wraps a connected socket in a TLS session and completes the TLS
+    /// handshake, so that certificate problems are reported here rather
+    /// than on the first Bolt message.
+    ///
+    pub(crate) fn connect(
+        &self,
+        host: &str,
+        mut socket: TcpStream,
+    ) -> Result<StreamOwned<ClientConnection, TcpStream>> {
+        let name = ServerName::try_from(host.to_string()).map_err(tls_error)?;
+        let mut conn =
+            ClientConnection::new(Arc::new(self.client_config()?), name).map_err(tls_error)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut socket).map_err(tls_error)?;
+        }
+        Ok(StreamOwned::new(conn, socket))
+    }
+
+    /// Like `connect`, for the async client.
+    ///
+    #[cfg(feature = "tokio-rustls")]
+    pub(crate) async fn connect_async(
+        &self,
+        host: &str,
+        socket: tokio::net::TcpStream,
+    ) -> Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>> {
+        let name = ServerName::try_from(host.to_string()).map_err(tls_error)?;
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(self.client_config()?));
+        connector.connect(name, socket).await.map_err(tls_error)
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig::new()
+    }
+}
+
+impl Clone for TlsConfig {
+    fn clone(&self) -> Self {
+        TlsConfig {
+            roots: self.roots.clone(),
+            client_auth: self
+                .client_auth
+                .as_ref()
+                .map(|(chain, key)| (chain.clone(), key.clone_key())),
+            verify_hostname: self.verify_hostname,
+        }
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("roots", &self.roots)
+            .field("client_auth", &self.client_auth.is_some())
+            .field("verify_hostname", &self.verify_hostname)
+            .finish()
+    }
+}
+
+fn tls_error<E: fmt::Display>(err: E) -> BoltError {
+    BoltError::Tls(err.to_string())
+}
+
+/// Accepts any server certificate, but still checks that the handshake
+/// is signed by the key in it.
+///
+#[derive(Debug)]
+struct TrustAll(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for TrustAll {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Verifies the certificate chain as usual but accepts a certificate
+/// issued for a different host name.
+///
+#[derive(Debug)]
+struct IgnoreHostname(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for IgnoreHostname {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+            | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext {
+                ..
+            })) => Ok(ServerCertVerified::assertion()),
+            other => other,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::{
+        pki_types::PrivatePkcs8KeyDer, server::WebPkiClientVerifier, ServerConfig, ServerConnection,
+    };
+
+    use crate::bolt::{BoltStream, ProtocolVersion};
+
+    struct Pki {
+        ca: Vec<u8>,
+        server_cert: CertificateDer<'static>,
+        server_key: Vec<u8>,
+        client_cert: String,
+        client_key: String,
+    }
+
+    fn pki() -> Pki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec![String::from("localhost")])
+            .unwrap()
+            .signed_by(&server_key, &ca, &ca_key)
+            .unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec![String::from("client")])
+            .unwrap()
+            .signed_by(&client_key, &ca, &ca_key)
+            .unwrap();
+        Pki {
+            ca: ca.der().to_vec(),
+            server_cert: server_cert.der().clone(),
+            server_key: server_key.serialize_der(),
+            client_cert: client_cert.pem(),
+            client_key: client_key.serialize_pem(),
+        }
+    }
+
+    /// Stands in for a TLS-terminating server: completes the TLS and Bolt
+    /// handshakes, agreeing to Bolt 4.4, then hangs up.
+    ///
+    fn serve(pki: &Pki, require_client_auth: bool) -> (u16, thread::JoinHandle<bool>) {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = if require_client_auth {
+            let mut roots = RootCertStore::empty();
+            roots.add(CertificateDer::from(pki.ca.clone())).unwrap();
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder
+            .with_single_cert(
+                vec![pki.server_cert.clone()],
+                PrivatePkcs8KeyDer::from(pki.server_key.clone()).into(),
+            )
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut stream = StreamOwned::new(conn, socket);
+            let mut handshake = [0; 20];
+            if stream.read_exact(&mut handshake).is_err() {
+                return false;
+            }
+            stream.write_all(&[0x00, 0x00, 0x04, 0x04]).unwrap();
+            stream.flush().unwrap();
+            true
+        });
+        (port, server)
+    }
+
+    fn connect(host: &str, port: u16, config: &TlsConfig) -> Result<BoltStream> {
+        BoltStream::connect_tls(("127.0.0.1", port), host, config)
+    }
+
+    #[test]
+    fn custom_root() {
+        let pki = pki();
+        let (port, server) = serve(&pki, false);
+        let config = TlsConfig::new().with_root_certificate(pki.ca.clone());
+        let bolt = connect("localhost", port, &config).unwrap();
+        assert_eq!(bolt.protocol_version(), ProtocolVersion::new(4, 4));
+        assert!(server.join().unwrap());
+    }
+
+    #[test]
+    fn unknown_issuer_is_rejected() {
+        let pki = pki();
+        let (port, server) = serve(&pki, false);
+        match connect("localhost", port, &TlsConfig::new()) {
+            Err(BoltError::Tls(_)) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        assert!(!server.join().unwrap());
+    }
+
+    #[test]
+    fn self_signed_accepts_any_certificate() {
+        let pki = pki();
+        let (port, server) = serve(&pki, false);
+        let config = TlsConfig::from_scheme("bolt+ssc").unwrap();
+        assert!(connect("localhost", port, &config).is_ok());
+        assert!(server.join().unwrap());
+    }
+
+    #[test]
+    fn hostname_verification() {
+        let pki = pki();
+        let config = TlsConfig::new().with_root_certificate(pki.ca.clone());
+
+        let (port, server) = serve(&pki, false);
+        match connect("neo4j.example.com", port, &config) {
+            Err(BoltError::Tls(_)) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        assert!(!server.join().unwrap());
+
+        let (port, server) = serve(&pki, false);
+        let config = config.verify_hostname(false);
+        assert!(connect("neo4j.example.com", port, &config).is_ok());
+        assert!(server.join().unwrap());
+    }
+
+    #[test]
+    fn client_certificate() {
+        let pki = pki();
+        let (port, server) = serve(&pki, true);
+        let config = TlsConfig::new()
+            .with_root_certificate(pki.ca.clone())
+            .with_client_certificate_pem(pki.client_cert.as_bytes(), pki.client_key.as_bytes())
+            .unwrap();
+        assert!(connect("localhost", port, &config).is_ok());
+        assert!(server.join().unwrap());
+    }
+
+    #[cfg(feature = "tokio-rustls")]
+    #[tokio::test]
+    async fn async_client() {
+        use crate::{aio::AsyncBoltStream, bolt::ConnectOptions};
+
+        let pki = pki();
+        let (port, server) = serve(&pki, false);
+        let options = ConnectOptions {
+            tls: Some(TlsConfig::new().with_root_certificate(pki.ca.clone())),
+            ..ConnectOptions::default()
+        };
+        let bolt = AsyncBoltStream::open(("127.0.0.1", port), "localhost", &options)
+            .await
+            .unwrap();
+        assert_eq!(bolt.protocol_version(), ProtocolVersion::new(4, 4));
+        assert!(server.join().unwrap());
+
+        let (port, server) = serve(&pki, false);
+        let options = ConnectOptions {
+            tls: Some(TlsConfig::new()),
+            ..ConnectOptions::default()
+        };
+        match AsyncBoltStream::open(("127.0.0.1", port), "localhost", &options).await {
+            Err(BoltError::Tls(_)) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        assert!(!server.join().unwrap());
+    }
+
+    #[test]
+    fn from_scheme() {
+        assert!(TlsConfig::from_scheme("bolt").is_none());
+        assert!(TlsConfig::from_scheme("neo4j").is_none());
+        assert!(TlsConfig::from_scheme("neo4j+s").is_some());
+    }
+
+    #[cfg(all(feature = "tokio", not(feature = "tokio-rustls")))]
+    #[tokio::test]
+    async fn async_client_needs_tokio_rustls() {
+        use crate::{aio::AsyncBoltStream, bolt::ConnectOptions};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = ConnectOptions {
+            tls: Some(TlsConfig::new()),
+            ..ConnectOptions::default()
+        };
+        let address = listener.local_addr().unwrap();
+        match AsyncBoltStream::open(address, "localhost", &options).await {
+            Err(BoltError::Tls(_)) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::{collections::HashMap, convert::TryFrom, time::Duration};
+
+use crate::{bolt::ProtocolVersion, AccessMode, BoltError, Value};
+
+/// Settings for one transaction, sent to the server when it begins, or
+/// with the RUN of an auto-commit query. They need Bolt 3 or later, and
+/// `database` needs Bolt 4.
+///
+#[derive(Clone, Debug, Default)]
+pub struct TransactionConfig {
+    /// The server terminates the transaction if it runs for longer.
+    pub timeout: Option<Duration>,
+    /// Shown against the transaction by `dbms.listTransactions`.
+    pub metadata: HashMap<String, Value>,
+    /// Whether the transaction only reads.
+    pub mode: AccessMode,
+    /// The database to run in, if not the default one.
+    pub database: Option<String>,
+}
+
+impl TransactionConfig {
+    pub fn is_empty(&self) -> bool {
+        self.timeout.is_none()
+            && self.metadata.is_empty()
+            && self.mode == AccessMode::Write
+            && self.database.is_none()
+    }
+
+    /// The `extra` dictionary for BEGIN or an auto-commit RUN, or `None`
+    /// if it would be empty.
+    ///
+    pub(crate) fn extra(
+        &self,
+        version: ProtocolVersion,
+        bookmarks: &[String],
+    ) -> crate::bolt::Result<Option<Value>> {
+        if version.major < 3 && !self.is_empty() {
+            return Err(BoltError::Protocol(format!(
+                "Transaction configuration needs Bolt 3 or later, not {}",
+                version
+            )));
+        }
+        let mut extra = HashMap::new();
+        if !bookmarks.is_empty() {
+            extra.insert(String::from("bookmarks"), bookmarks.to_vec().into());
+        }
+        if let Some(timeout) = self.timeout {
+            extra.insert(String::from("tx_timeout"), Value::Integer(millis(timeout)));
+        }
+        if !self.metadata.is_empty() {
+            extra.insert(
+                String::from("tx_metadata"),
+                Value::Map(self.metadata.clone()),
+            );
+        }
+        if self.mode == AccessMode::Read {
+            extra.insert(String::from("mode"), "r".into());
+        }
+        if let Some(ref database) = self.database {
+            require_databases(version)?;
+            extra.insert(String::from("db"), database.as_str().into());
+        }
+        Ok(if extra.is_empty() {
+            None
+        } else {
+            Some(Value::Map(extra))
+        })
+    }
+}
+
+/// Fails unless `version` lets a client choose the database.
+///
+pub(crate) fn require_databases(version: ProtocolVersion) -> crate::bolt::Result<()> {
+    if version.major < 4 {
+        return Err(BoltError::Protocol(format!(
+            "Choosing a database needs Bolt 4 or later, not {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+/// `timeout` in whole milliseconds. A timeout under a millisecond is
+/// rounded up rather than sent as 0, which the server takes to mean none.
+///
+fn millis(timeout: Duration) -> i64 {
+    if timeout > Duration::from_millis(0) && timeout < Duration::from_millis(1) {
+        return 1;
+    }
+    i64::try_from(timeout.as_millis()).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use boltstub::BoltStub;
+    use packstream::parameters;
+
+    use crate::{Neo4jDB, Neo4jError, Neo4jOperations};
+
+    fn config() -> TransactionConfig {
+        let mut metadata = HashMap::new();
+        metadata.insert(String::from("app"), Value::from("billing"));
+        TransactionConfig {
+            timeout: Some(Duration::from_millis(1500)),
+            metadata,
+            mode: AccessMode::Read,
+            database: Some(String::from("sales")),
+        }
+    }
+
+    #[test]
+    fn encodes_extra() {
+        let v44 = ProtocolVersion::new(4, 4);
+        assert_eq!(TransactionConfig::default().extra(v44, &[]).unwrap(), None);
+        assert_eq!(
+            config().extra(v44, &[String::from("bm:1")]).unwrap(),
+            Some(
+                parameters!(
+                    "bookmarks" => vec!["bm:1"],
+                    "tx_timeout" => 1500,
+                    "tx_metadata" => parameters!("app" => "billing"),
+                    "mode" => "r",
+                    "db" => "sales"
+                )
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn rounds_timeouts_to_millis() {
+        assert_eq!(millis(Duration::from_millis(0)), 0);
+        assert_eq!(millis(Duration::from_micros(1)), 1);
+        assert_eq!(millis(Duration::from_micros(1500)), 1);
+        assert_eq!(millis(Duration::from_secs(2)), 2000);
+        assert_eq!(millis(Duration::MAX), i64::MAX);
+    }
+
+    #[test]
+    fn needs_a_recent_protocol() {
+        let config = config();
+        assert!(config.extra(ProtocolVersion::new(3, 0), &[]).is_err());
+        assert!(config.extra(ProtocolVersion::new(1, 0), &[]).is_err());
+        let database = TransactionConfig {
+            database: None,
+            ..config.clone()
+        };
+        assert!(database.extra(ProtocolVersion::new(3, 0), &[]).is_ok());
+    }
+
+    #[test]
+    fn sent_on_begin_and_auto_commit_run() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.4.0"}
+            C: BEGIN {"tx_timeout": 1500, "tx_metadata": {"app": "billing"}, "mode": "r", "db": "sales"}
+            S: SUCCESS {}
+            C: RUN "MATCH (n) RETURN count(n)" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["count(n)"]}
+               RECORD [0]
+               SUCCESS {}
+            C: COMMIT
+            S: SUCCESS {"bookmark": "bm:1"}
+            C: RUN "CALL db.labels()" {} {"bookmarks": ["bm:1"], "tx_timeout": 1500, "tx_metadata": {"app": "billing"}, "mode": "r", "db": "sales"}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["label"]}
+               SUCCESS {}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        let mut tx = db.transaction_with_config(config()).unwrap();
+        let count = tx
+            .run("MATCH (n) RETURN count(n)", parameters!())
+            .unwrap()
+            .first()
+            .next();
+        assert_eq!(count, Some(Value::Integer(0)));
+        tx.commit().unwrap();
+        let labels = db
+            .run_with_config("CALL db.labels()", parameters!(), &config())
+            .unwrap();
+        assert_eq!(labels.count(), 0);
+        drop(db);
+        stub.finish().unwrap();
+    }
+
+    #[test]
+    fn refused_begin_reports_the_server_error() {
+        let stub = BoltStub::start(
+            r#"
+            !: BOLT 4.4
+            C: HELLO *
+            S: SUCCESS {"server": "Neo4j/4.4.0"}
+            C: BEGIN {"db": "nowhere"}
+            S: FAILURE {"code": "Neo.ClientError.Database.DatabaseNotFound", "message": "Database does not exist"}
+            C: RESET
+            S: SUCCESS {}
+            C: BEGIN {}
+            S: SUCCESS {}
+            C: RUN "RETURN 1" {} {}
+               PULL {"n": 1000, "qid": -1}
+            S: SUCCESS {"fields": ["1"]}
+               RECORD [1]
+               SUCCESS {}
+            C: COMMIT
+            S: SUCCESS {"bookmark": "bm:1"}
+            "#,
+        )
+        .unwrap();
+        let mut db = Neo4jDB::connect(stub.address(), "neo4j", "password").unwrap();
+        let config = TransactionConfig {
+            database: Some(String::from("nowhere")),
+            ..TransactionConfig::default()
+        };
+        match db.transaction_with_config(config) {
+            Err(Neo4jError::BeginFailure(ref e)) => {
+                assert_eq!(e.code, "Neo.ClientError.Database.DatabaseNotFound")
+            }
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("BEGIN succeeded"),
+        }
+        let mut tx = db.transaction().unwrap();
+        let one = tx.run("RETURN 1", parameters!()).unwrap().first().next();
+        assert_eq!(one, Some(Value::Integer(1)));
+        tx.commit().unwrap();
+        drop(db);
+        stub.finish().unwrap();
+    }
+}
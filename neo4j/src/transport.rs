@@ -0,0 +1,44 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+#[cfg(feature = "rustls")]
+use rustls::{ClientConnection, StreamOwned};
+
+/// The byte stream underneath a `BoltStream`: either a bare TCP socket or
+/// one wrapped in a TLS session.
+///
+pub enum Transport {
+    Plain(TcpStream),
+    #[cfg(feature = "rustls")]
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut stream) => stream.read(buf),
+            #[cfg(feature = "rustls")]
+            Transport::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut stream) => stream.write(buf),
+            #[cfg(feature = "rustls")]
+            Transport::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Transport::Plain(ref mut stream) => stream.flush(),
+            #[cfg(feature = "rustls")]
+            Transport::Tls(ref mut stream) => stream.flush(),
+        }
+    }
+}
@@ -7,8 +7,45 @@ use std::{
     iter::FromIterator,
 };
 
+/// Implements `From<$T> for Value` and `TryFrom<Value> for $T` for a type
+/// with its own `Value` variant of the same name. A failed conversion hands
+/// back the original value.
+macro_rules! impl_Value_conversions {
+    ($T:ident) => {
+        impl From<$T> for Value {
+            fn from(val: $T) -> Self {
+                Value::$T(val)
+            }
+        }
+
+        impl TryFrom<Value> for $T {
+            type Error = Value;
+
+            fn try_from(val: Value) -> Result<Self, Self::Error> {
+                match val {
+                    Value::$T(v) => Ok(v),
+                    _ => Err(val),
+                }
+            }
+        }
+    };
+}
+
 mod pack;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod spatial;
+mod temporal;
 mod unpack;
+mod value_ref;
+
+pub use pack::PackError;
+#[cfg(feature = "serde")]
+pub use serde_impl::{from_value, to_value, to_vec, to_writer, SerdeError};
+pub use spatial::{Point2D, Point3D};
+pub use temporal::{Date, DateTime, DateTimeZoneId, Duration, LocalDateTime, LocalTime, Time};
+pub use unpack::UnpackError;
+pub use value_ref::{ListIter, ListRef, MapIter, MapRef, ValueRef};
 
 #[derive(Clone, PartialEq)]
 pub enum Value {
@@ -20,23 +57,77 @@ pub enum Value {
     List(Vec<Value>),
     Map(HashMap<String, Value>),
     Structure { signature: u8, fields: Vec<Value> },
+    Date(Date),
+    Time(Time),
+    LocalTime(LocalTime),
+    DateTime(DateTime),
+    DateTimeZoneId(DateTimeZoneId),
+    LocalDateTime(LocalDateTime),
+    Duration(Duration),
+    Point2D(Point2D),
+    Point3D(Point3D),
+}
+
+/// A value type that travels as a PackStream structure with a fixed
+/// signature.
+///
+pub(crate) trait StructType: Sized {
+    const SIGNATURE: u8;
+
+    fn into_fields(self) -> Vec<Value>;
+
+    /// Returns `None` if the fields do not have the expected arity or types.
+    fn from_fields(fields: &[Value]) -> Option<Self>;
 }
 
 impl Value {
+    /// Builds the value for a structure read off the wire, decoding those
+    /// signatures that have a dedicated variant.
+    ///
+    pub(crate) fn from_structure(signature: u8, fields: Vec<Value>) -> Value {
+        fn decode<T: StructType>(fields: &[Value], f: fn(T) -> Value) -> Option<Value> {
+            T::from_fields(fields).map(f)
+        }
+
+        let value = match signature {
+            Date::SIGNATURE => decode(&fields, Value::Date),
+            Time::SIGNATURE => decode(&fields, Value::Time),
+            LocalTime::SIGNATURE => decode(&fields, Value::LocalTime),
+            DateTime::SIGNATURE => decode(&fields, Value::DateTime),
+            DateTimeZoneId::SIGNATURE => decode(&fields, Value::DateTimeZoneId),
+            LocalDateTime::SIGNATURE => decode(&fields, Value::LocalDateTime),
+            Duration::SIGNATURE => decode(&fields, Value::Duration),
+            Point2D::SIGNATURE => decode(&fields, Value::Point2D),
+            Point3D::SIGNATURE => decode(&fields, Value::Point3D),
+            _ => None,
+        };
+        value.unwrap_or(Value::Structure { signature, fields })
+    }
+
     pub fn pack(self, out: &mut dyn Write) -> pack::PackResult {
         pack::pack(self, out)
     }
 
-    pub fn pack_into(self) -> Result<Vec<u8>, std::io::Error> {
+    pub fn pack_into(self) -> Result<Vec<u8>, PackError> {
         let mut buf = Vec::new();
         self.pack(&mut buf)?;
         Ok(buf)
     }
 
+    /// Decodes one value. Lists, maps and structures nested more than 256
+    /// levels deep fail with `UnpackError::TooDeep`.
+    ///
     pub fn unpack(stream: &mut dyn Read) -> unpack::UnpackResult {
         unpack::unpack(stream)
     }
 
+    /// Like `unpack`, but fails with `UnpackError::SizeLimitExceeded` on
+    /// any string, list, map or structure declaring more than `limit` bytes
+    /// or items.
+    pub fn unpack_with_limit(stream: &mut dyn Read, limit: usize) -> unpack::UnpackResult {
+        unpack::unpack_with_limit(stream, limit)
+    }
+
     pub fn into_bool(self) -> Option<bool> {
         match self {
             Value::Boolean(v) => Some(v),
@@ -94,6 +185,17 @@ impl fmt::Debug for Value {
                 signature,
                 ref fields,
             } => write!(f, "#{:02X} {:?}", signature, fields),
+            Value::Date(ref value) => write!(f, "date({:?})", value.to_string()),
+            Value::Time(ref value) => write!(f, "time({:?})", value.to_string()),
+            Value::LocalTime(ref value) => write!(f, "localtime({:?})", value.to_string()),
+            Value::DateTime(ref value) => write!(f, "datetime({:?})", value.to_string()),
+            Value::DateTimeZoneId(ref value) => write!(f, "datetime({:?})", value.to_string()),
+            Value::LocalDateTime(ref value) => {
+                write!(f, "localdatetime({:?})", value.to_string())
+            }
+            Value::Duration(ref value) => write!(f, "duration({:?})", value.to_string()),
+            Value::Point2D(ref value) => <Point2D as fmt::Display>::fmt(value, f),
+            Value::Point3D(ref value) => <Point3D as fmt::Display>::fmt(value, f),
         }
     }
 }
@@ -107,6 +209,15 @@ impl fmt::Display for Value {
             Value::Float(ref value) => <f64 as fmt::Display>::fmt(value, f),
             Value::String(ref value) => <String as fmt::Display>::fmt(value, f),
             Value::List(ref values) => write_tsv(f, values),
+            Value::Date(ref value) => <Date as fmt::Display>::fmt(value, f),
+            Value::Time(ref value) => <Time as fmt::Display>::fmt(value, f),
+            Value::LocalTime(ref value) => <LocalTime as fmt::Display>::fmt(value, f),
+            Value::DateTime(ref value) => <DateTime as fmt::Display>::fmt(value, f),
+            Value::DateTimeZoneId(ref value) => <DateTimeZoneId as fmt::Display>::fmt(value, f),
+            Value::LocalDateTime(ref value) => <LocalDateTime as fmt::Display>::fmt(value, f),
+            Value::Duration(ref value) => <Duration as fmt::Display>::fmt(value, f),
+            Value::Point2D(ref value) => <Point2D as fmt::Display>::fmt(value, f),
+            Value::Point3D(ref value) => <Point3D as fmt::Display>::fmt(value, f),
             _ => write!(f, "{:?}", self),
         }
     }
@@ -266,7 +377,7 @@ impl ValueMatch for Value {
     }
 
     fn is_structure(&self) -> bool {
-        pat_cond!(Value::Structure{ .. } = *self)
+        pat_cond!(Value::Structure { .. } = *self)
     }
 }
 
@@ -308,7 +419,7 @@ macro_rules! parameters(
             let mut map : HashMap<&str, Value> = HashMap::new();
             $(
                 map.insert($key, $value.into());
-            )+;
+            )+
 
             map
         }
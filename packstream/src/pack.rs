@@ -1,13 +1,61 @@
 use std::{
     collections::HashMap,
+    error::Error,
+    fmt,
     io::{self, Write},
 };
 
 use byteorder::{BigEndian, WriteBytesExt};
 
-use crate::Value;
+use crate::{StructType, Value};
 
-pub type PackResult = Result<(), io::Error>;
+pub type PackResult = Result<(), PackError>;
+
+/// Failure to pack a value, either because the output could not be written
+/// or because a string, list, map or structure exceeds what PackStream can
+/// represent.
+///
+#[derive(Debug)]
+pub enum PackError {
+    Io(io::Error),
+    TooLarge {
+        kind: &'static str,
+        size: usize,
+        limit: usize,
+    },
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PackError::Io(ref err) => write!(f, "Write error: {}", err),
+            PackError::TooLarge { kind, size, limit } => write!(
+                f,
+                "{} of size {} is too large to pack (limit {})",
+                kind, size, limit
+            ),
+        }
+    }
+}
+
+impl Error for PackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            PackError::Io(ref err) => Some(err),
+            PackError::TooLarge { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for PackError {
+    fn from(val: io::Error) -> Self {
+        PackError::Io(val)
+    }
+}
+
+fn too_large(kind: &'static str, size: usize, limit: usize) -> PackResult {
+    Err(PackError::TooLarge { kind, size, limit })
+}
 
 pub fn pack(value: Value, out: &mut dyn Write) -> PackResult {
     match value {
@@ -19,46 +67,56 @@ pub fn pack(value: Value, out: &mut dyn Write) -> PackResult {
         Value::List(items) => pack_list(items, out),
         Value::Map(items) => pack_map(items, out),
         Value::Structure { signature, fields } => pack_structure(signature, fields, out),
+        Value::Date(x) => pack_struct_type(x, out),
+        Value::Time(x) => pack_struct_type(x, out),
+        Value::LocalTime(x) => pack_struct_type(x, out),
+        Value::DateTime(x) => pack_struct_type(x, out),
+        Value::DateTimeZoneId(x) => pack_struct_type(x, out),
+        Value::LocalDateTime(x) => pack_struct_type(x, out),
+        Value::Duration(x) => pack_struct_type(x, out),
+        Value::Point2D(x) => pack_struct_type(x, out),
+        Value::Point3D(x) => pack_struct_type(x, out),
     }
 }
 
-fn pack_null(out: &mut dyn Write) -> PackResult {
-    out.write_u8(0xC0)
+pub(crate) fn pack_null(out: &mut dyn Write) -> PackResult {
+    Ok(out.write_u8(0xC0)?)
 }
 
-fn pack_boolean(value: bool, out: &mut dyn Write) -> PackResult {
-    out.write_u8(if value { 0xC3 } else { 0xC2 })
+pub(crate) fn pack_boolean(value: bool, out: &mut dyn Write) -> PackResult {
+    Ok(out.write_u8(if value { 0xC3 } else { 0xC2 })?)
 }
 
-fn pack_integer(value: i64, out: &mut dyn Write) -> PackResult {
-    if -0x10 <= value && value < 0x80 {
+pub(crate) fn pack_integer(value: i64, out: &mut dyn Write) -> PackResult {
+    if (-0x10..0x80).contains(&value) {
         // TINY_INT
-        out.write_i8(value as i8)
-    } else if -0x80 <= value && value < 0x80 {
+        out.write_i8(value as i8)?;
+    } else if (-0x80..0x80).contains(&value) {
         // INT_8
         out.write_u8(0xC8)?;
-        out.write_i8(value as i8)
-    } else if -0x8000 <= value && value < 0x8000 {
+        out.write_i8(value as i8)?;
+    } else if (-0x8000..0x8000).contains(&value) {
         // INT_16
         out.write_u8(0xC9)?;
-        out.write_i16::<BigEndian>(value as i16)
-    } else if -0x8000_0000 <= value && value < 0x8000_0000 {
+        out.write_i16::<BigEndian>(value as i16)?;
+    } else if (-0x8000_0000..0x8000_0000).contains(&value) {
         // INT_32
         out.write_u8(0xCA)?;
-        out.write_i32::<BigEndian>(value as i32)
+        out.write_i32::<BigEndian>(value as i32)?;
     } else {
         // INT_64
         out.write_u8(0xCB)?;
-        out.write_i64::<BigEndian>(value)
+        out.write_i64::<BigEndian>(value)?;
     }
+    Ok(())
 }
 
-fn pack_float(value: f64, out: &mut dyn Write) -> PackResult {
+pub(crate) fn pack_float(value: f64, out: &mut dyn Write) -> PackResult {
     out.write_u8(0xC1)?;
-    out.write_f64::<BigEndian>(value)
+    Ok(out.write_f64::<BigEndian>(value)?)
 }
 
-fn pack_string(value: &str, out: &mut dyn Write) -> PackResult {
+pub(crate) fn pack_string(value: &str, out: &mut dyn Write) -> PackResult {
     let size: usize = value.len();
     if size < 0x10 {
         out.write_u8(0x80 + size as u8)?;
@@ -72,35 +130,47 @@ fn pack_string(value: &str, out: &mut dyn Write) -> PackResult {
         out.write_u8(0xD2)?;
         out.write_u32::<BigEndian>(size as u32)?;
     } else {
-        panic!("String too long to pack");
+        return too_large("String", size, 0xFFFF_FFFF);
     }
-    out.write_all(value.as_bytes())
+    Ok(out.write_all(value.as_bytes())?)
 }
 
 fn pack_list(value: Vec<Value>, out: &mut dyn Write) -> PackResult {
-    let size = value.len();
+    pack_list_header(value.len(), out)?;
+    for val in value {
+        val.pack(out)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn pack_list_header(size: usize, out: &mut dyn Write) -> PackResult {
     if size < 0x10 {
         out.write_u8(0x90 + size as u8)?;
     } else if size < 0x100 {
-        out.write_u8(0xD4).unwrap();
+        out.write_u8(0xD4)?;
         out.write_u8(size as u8)?;
     } else if size < 0x10000 {
-        out.write_u8(0xD5).unwrap();
+        out.write_u8(0xD5)?;
         out.write_u16::<BigEndian>(size as u16)?;
     } else if size < 0x1_0000_0000 {
-        out.write_u8(0xD6).unwrap();
+        out.write_u8(0xD6)?;
         out.write_u32::<BigEndian>(size as u32)?;
     } else {
-        panic!("List too big to pack");
+        return too_large("List", size, 0xFFFF_FFFF);
     }
-    for val in value {
+    Ok(())
+}
+
+fn pack_map(value: HashMap<String, Value>, out: &mut dyn Write) -> PackResult {
+    pack_map_header(value.len(), out)?;
+    for (key, val) in value {
+        pack_string(&key[..], out)?;
         val.pack(out)?;
     }
     Ok(())
 }
 
-fn pack_map(value: HashMap<String, Value>, out: &mut dyn Write) -> PackResult {
-    let size = value.len();
+pub(crate) fn pack_map_header(size: usize, out: &mut dyn Write) -> PackResult {
     if size < 0x10 {
         out.write_u8(0xA0 + size as u8)?;
     } else if size < 0x100 {
@@ -113,11 +183,7 @@ fn pack_map(value: HashMap<String, Value>, out: &mut dyn Write) -> PackResult {
         out.write_u8(0xDA)?;
         out.write_u32::<BigEndian>(size as u32)?;
     } else {
-        panic!("Map too big to pack");
-    }
-    for (key, val) in value {
-        pack_string(&key[..], out)?;
-        val.pack(out)?;
+        return too_large("Map", size, 0xFFFF_FFFF);
     }
     Ok(())
 }
@@ -133,7 +199,7 @@ fn pack_structure(signature: u8, fields: Vec<Value>, out: &mut dyn Write) -> Pac
         out.write_u8(0xDD)?;
         out.write_u16::<BigEndian>(size as u16)?;
     } else {
-        panic!("Structure too big to pack");
+        return too_large("Structure", size, 0xFFFF);
     }
     out.write_u8(signature)?;
     for val in fields {
@@ -141,3 +207,34 @@ fn pack_structure(signature: u8, fields: Vec<Value>, out: &mut dyn Write) -> Pac
     }
     Ok(())
 }
+
+fn pack_struct_type<T: StructType>(value: T, out: &mut dyn Write) -> PackResult {
+    pack_structure(T::SIGNATURE, value.into_fields(), out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structure_too_large() {
+        let fields = vec![Value::Null; 0x10000];
+        match pack_structure(0x01, fields, &mut Vec::new()) {
+            Err(PackError::TooLarge {
+                kind: "Structure",
+                size: 0x10000,
+                limit: 0xFFFF,
+            }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_failure() {
+        let mut out = [0u8; 2];
+        match pack(Value::from("hello"), &mut &mut out[..]) {
+            Err(PackError::Io(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,1477 @@
+//! Serde support: conversion between `Serialize`/`Deserialize` types and
+//! `Value`, plus a serializer that writes PackStream bytes directly.
+//!
+//! Nodes and relationships deserialize through their properties, so a
+//! struct can be filled straight from a returned node. Points deserialize
+//! as `{srid, x, y[, z]}`. Temporal values pass through `to_value` and
+//! `from_value` unchanged and are packed as their structures; read into a
+//! string they give their ISO 8601 form.
+
+use std::{collections::HashMap, convert::TryFrom, error::Error, fmt, io::Write, iter};
+
+use serde::{
+    de::{
+        self,
+        value::{MapDeserializer, SeqDeserializer},
+        DeserializeOwned, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any,
+    ser::{self, Impossible},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    pack::{
+        pack_boolean, pack_float, pack_integer, pack_list_header, pack_map_header, pack_null,
+        pack_string,
+    },
+    Date, DateTime, DateTimeZoneId, Duration, LocalDateTime, LocalTime, PackError, StructType,
+    Time, Value,
+};
+
+const NODE: u8 = 0x4E;
+const REL: u8 = 0x52;
+const UNBOUND_REL: u8 = 0x72;
+
+/// Newtype name under which a structure travels as `[signature, fields..]`,
+/// so that the serializers here can rebuild it instead of seeing a list.
+/// Other formats just see the list.
+const STRUCTURE_TOKEN: &str = "$packstream::private::Structure";
+
+#[derive(Debug)]
+pub enum SerdeError {
+    Message(String),
+    Pack(PackError),
+}
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SerdeError::Message(ref msg) => f.write_str(msg),
+            SerdeError::Pack(ref err) => write!(f, "Pack error: {}", err),
+        }
+    }
+}
+
+impl Error for SerdeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SerdeError::Message(_) => None,
+            SerdeError::Pack(ref err) => Some(err),
+        }
+    }
+}
+
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError::Message(msg.to_string())
+    }
+}
+
+impl de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError::Message(msg.to_string())
+    }
+}
+
+impl From<PackError> for SerdeError {
+    fn from(val: PackError) -> Self {
+        SerdeError::Pack(val)
+    }
+}
+
+type Result<T> = std::result::Result<T, SerdeError>;
+
+/// Converts any serializable type into a `Value`.
+///
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value> {
+    value.serialize(ValueSerializer)
+}
+
+/// Builds a deserializable type from a `Value`.
+///
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T> {
+    T::deserialize(value)
+}
+
+/// Serializes straight into PackStream bytes, without building a `Value`.
+///
+pub fn to_writer<T: Serialize + ?Sized>(out: &mut dyn Write, value: &T) -> Result<()> {
+    value.serialize(PackSerializer { out })
+}
+
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    to_writer(&mut buf, value)?;
+    Ok(buf)
+}
+
+fn u64_to_i64(v: u64) -> Result<i64> {
+    if v > i64::MAX as u64 {
+        Err(SerdeError::Message(format!(
+            "Integer {} is out of range for PackStream",
+            v
+        )))
+    } else {
+        Ok(v as i64)
+    }
+}
+
+fn structure_fields<T: StructType + Clone>(value: &T) -> Value {
+    let mut fields = vec![Value::from(T::SIGNATURE)];
+    fields.extend(value.clone().into_fields());
+    Value::List(fields)
+}
+
+fn serialize_structure<T: StructType + Clone, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_newtype_struct(STRUCTURE_TOKEN, &structure_fields(value))
+}
+
+fn visit_structure<'de, T: StructType + Clone, V: Visitor<'de>>(
+    value: &T,
+    visitor: V,
+) -> Result<V::Value> {
+    let entry = (STRUCTURE_TOKEN, structure_fields(value));
+    visitor.visit_map(MapDeserializer::new(iter::once(entry)))
+}
+
+/// Rebuilds the value carried under `STRUCTURE_TOKEN`.
+///
+fn structure(value: Value) -> Result<Value> {
+    let mut fields = match value {
+        Value::List(fields) if !fields.is_empty() => fields,
+        other => {
+            return Err(SerdeError::Message(format!(
+                "Expected structure fields, found {:?}",
+                other
+            )))
+        }
+    };
+    match fields.remove(0).into_int() {
+        Some(signature) => Ok(Value::from_structure(signature, fields)),
+        None => Err(SerdeError::Message(String::from(
+            "Expected a structure signature",
+        ))),
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use ser::{SerializeMap, SerializeSeq, SerializeStruct};
+
+        match *self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Boolean(v) => serializer.serialize_bool(v),
+            Value::Integer(v) => serializer.serialize_i64(v),
+            Value::Float(v) => serializer.serialize_f64(v),
+            Value::String(ref v) => serializer.serialize_str(v),
+            Value::List(ref values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Value::Map(ref values) => {
+                let mut map = serializer.serialize_map(Some(values.len()))?;
+                for (key, value) in values {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Value::Structure { ref fields, .. } => {
+                let mut seq = serializer.serialize_seq(Some(fields.len()))?;
+                for value in fields {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Value::Point2D(ref p) => {
+                let mut s = serializer.serialize_struct("Point2D", 3)?;
+                s.serialize_field("srid", &p.srid)?;
+                s.serialize_field("x", &p.x)?;
+                s.serialize_field("y", &p.y)?;
+                s.end()
+            }
+            Value::Point3D(ref p) => {
+                let mut s = serializer.serialize_struct("Point3D", 4)?;
+                s.serialize_field("srid", &p.srid)?;
+                s.serialize_field("x", &p.x)?;
+                s.serialize_field("y", &p.y)?;
+                s.serialize_field("z", &p.z)?;
+                s.end()
+            }
+            Value::Date(ref v) => serialize_structure(v, serializer),
+            Value::Time(ref v) => serialize_structure(v, serializer),
+            Value::LocalTime(ref v) => serialize_structure(v, serializer),
+            Value::DateTime(ref v) => serialize_structure(v, serializer),
+            Value::DateTimeZoneId(ref v) => serialize_structure(v, serializer),
+            Value::LocalDateTime(ref v) => serialize_structure(v, serializer),
+            Value::Duration(ref v) => serialize_structure(v, serializer),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a PackStream value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<Value, E> {
+        u64_to_i64(v).map(Value::Integer).map_err(|e| E::custom(e))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Value, E> {
+        Ok(Value::from(v.to_vec()))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Value::List(values))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> std::result::Result<Value, A::Error> {
+        let mut values = HashMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(key) = map.next_key::<String>()? {
+            if key == STRUCTURE_TOKEN && values.is_empty() {
+                return structure(map.next_value()?).map_err(de::Error::custom);
+            }
+            values.insert(key, map.next_value()?);
+        }
+        Ok(Value::Map(values))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(STRUCTURE_TOKEN, ValueVisitor)
+    }
+}
+
+macro_rules! impl_serde_for_structure {
+    ($T:ident, $expected:expr) => {
+        impl Serialize for $T {
+            fn serialize<S: Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                serialize_structure(self, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $T {
+            fn deserialize<D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> std::result::Result<Self, D::Error> {
+                $T::try_from(Value::deserialize(deserializer)?).map_err(|other| {
+                    de::Error::invalid_type(
+                        de::Unexpected::Other(&format!("{:?}", other)),
+                        &$expected,
+                    )
+                })
+            }
+        }
+    };
+}
+
+impl_serde_for_structure!(Date, "a date");
+impl_serde_for_structure!(Time, "a time");
+impl_serde_for_structure!(LocalTime, "a local time");
+impl_serde_for_structure!(DateTime, "a date-time");
+impl_serde_for_structure!(DateTimeZoneId, "a date-time with a zone id");
+impl_serde_for_structure!(LocalDateTime, "a local date-time");
+impl_serde_for_structure!(Duration, "a duration");
+
+impl<'de> IntoDeserializer<'de, SerdeError> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+/// Picks the properties out of a node or relationship structure.
+///
+fn properties(signature: u8, fields: &mut Vec<Value>) -> Option<HashMap<String, Value>> {
+    let index = match (signature, fields.len()) {
+        (NODE, 3) => 2,
+        (REL, 5) => 4,
+        (UNBOUND_REL, 3) => 2,
+        _ => return None,
+    };
+    if !matches!(fields[index], Value::Map(_)) {
+        return None;
+    }
+    match fields.swap_remove(index) {
+        Value::Map(props) => Some(props),
+        _ => None,
+    }
+}
+
+impl<'de> Deserializer<'de> for Value {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Boolean(v) => visitor.visit_bool(v),
+            Value::Integer(v) => visitor.visit_i64(v),
+            Value::Float(v) => visitor.visit_f64(v),
+            Value::String(v) => visitor.visit_string(v),
+            Value::List(values) => visitor.visit_seq(SeqDeserializer::new(values.into_iter())),
+            Value::Map(values) => visitor.visit_map(MapDeserializer::new(values.into_iter())),
+            Value::Structure {
+                signature,
+                mut fields,
+            } => match properties(signature, &mut fields) {
+                Some(props) => visitor.visit_map(MapDeserializer::new(props.into_iter())),
+                None => visitor.visit_seq(SeqDeserializer::new(fields.into_iter())),
+            },
+            Value::Point2D(p) => visitor.visit_map(MapDeserializer::new(
+                vec![
+                    ("srid", Value::from(p.srid)),
+                    ("x", Value::from(p.x)),
+                    ("y", Value::from(p.y)),
+                ]
+                .into_iter(),
+            )),
+            Value::Point3D(p) => visitor.visit_map(MapDeserializer::new(
+                vec![
+                    ("srid", Value::from(p.srid)),
+                    ("x", Value::from(p.x)),
+                    ("y", Value::from(p.y)),
+                    ("z", Value::from(p.z)),
+                ]
+                .into_iter(),
+            )),
+            other => visitor.visit_string(other.to_string()),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        if name != STRUCTURE_TOKEN {
+            return visitor.visit_newtype_struct(self);
+        }
+        match self {
+            Value::Date(ref v) => visit_structure(v, visitor),
+            Value::Time(ref v) => visit_structure(v, visitor),
+            Value::LocalTime(ref v) => visit_structure(v, visitor),
+            Value::DateTime(ref v) => visit_structure(v, visitor),
+            Value::DateTimeZoneId(ref v) => visit_structure(v, visitor),
+            Value::LocalDateTime(ref v) => visit_structure(v, visitor),
+            Value::Duration(ref v) => visit_structure(v, visitor),
+            other => other.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self {
+            Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            Value::Map(map) => {
+                let mut entries = map.into_iter();
+                match (entries.next(), entries.next()) {
+                    (Some((variant, value)), None) => visitor.visit_enum(EnumAccess {
+                        variant,
+                        value: Some(value),
+                    }),
+                    _ => Err(de::Error::invalid_length(
+                        entries.len() + 2,
+                        &"a map with a single key",
+                    )),
+                }
+            }
+            other => Err(de::Error::invalid_type(
+                de::Unexpected::Other(&format!("{:?}", other)),
+                &"a string or a map with a single key",
+            )),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct EnumAccess {
+    variant: String,
+    value: Option<Value>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = SerdeError;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let variant = seed.deserialize(IntoDeserializer::<SerdeError>::into_deserializer(
+            self.variant.clone(),
+        ))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for EnumAccess {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            None | Some(Value::Null) => Ok(()),
+            Some(other) => Deserialize::deserialize(other),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self.value.unwrap_or(Value::Null))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.value.unwrap_or(Value::Null).deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.value.unwrap_or(Value::Null).deserialize_any(visitor)
+    }
+}
+
+/// Serializer producing a `Value` tree.
+///
+struct ValueSerializer;
+
+fn variant_map(variant: &str, value: Value) -> Value {
+    let mut map = HashMap::with_capacity(1);
+    map.insert(variant.to_string(), value);
+    Value::Map(map)
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = SerdeError;
+    type SerializeSeq = ValueSeq;
+    type SerializeTuple = ValueSeq;
+    type SerializeTupleStruct = ValueSeq;
+    type SerializeTupleVariant = ValueSeq;
+    type SerializeMap = ValueMap;
+    type SerializeStruct = ValueMap;
+    type SerializeStructVariant = ValueMap;
+
+    fn serialize_bool(self, v: bool) -> Result<Value> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value> {
+        u64_to_i64(v).map(Value::Integer)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value> {
+        Ok(Value::from(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value> {
+        Ok(Value::from(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value> {
+        if name == STRUCTURE_TOKEN {
+            return structure(value.serialize(self)?);
+        }
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value> {
+        Ok(variant_map(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ValueSeq> {
+        Ok(ValueSeq {
+            variant: None,
+            values: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ValueSeq> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<ValueSeq> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<ValueSeq> {
+        Ok(ValueSeq {
+            variant: Some(variant),
+            values: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<ValueMap> {
+        Ok(ValueMap {
+            variant: None,
+            values: HashMap::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<ValueMap> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<ValueMap> {
+        Ok(ValueMap {
+            variant: Some(variant),
+            values: HashMap::with_capacity(len),
+            key: None,
+        })
+    }
+}
+
+struct ValueSeq {
+    variant: Option<&'static str>,
+    values: Vec<Value>,
+}
+
+impl ValueSeq {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.values.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value> {
+        let list = Value::List(self.values);
+        Ok(match self.variant {
+            Some(variant) => variant_map(variant, list),
+            None => list,
+        })
+    }
+}
+
+impl ser::SerializeSeq for ValueSeq {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for ValueSeq {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for ValueSeq {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for ValueSeq {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+struct ValueMap {
+    variant: Option<&'static str>,
+    values: HashMap<String, Value>,
+    key: Option<String>,
+}
+
+impl ValueMap {
+    fn finish(self) -> Result<Value> {
+        let map = Value::Map(self.values);
+        Ok(match self.variant {
+            Some(variant) => variant_map(variant, map),
+            None => map,
+        })
+    }
+}
+
+impl ser::SerializeMap for ValueMap {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| SerdeError::Message(String::from("Map value without a key")))?;
+        self.values.insert(key, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for ValueMap {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.values
+            .insert(key.to_string(), value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for ValueMap {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+/// Serializer for map keys, which PackStream requires to be strings.
+///
+struct KeySerializer;
+
+fn key_error() -> SerdeError {
+    SerdeError::Message(String::from("Map keys must be strings"))
+}
+
+impl Serializer for KeySerializer {
+    type Ok = String;
+    type Error = SerdeError;
+    type SerializeSeq = Impossible<String, SerdeError>;
+    type SerializeTuple = Impossible<String, SerdeError>;
+    type SerializeTupleStruct = Impossible<String, SerdeError>;
+    type SerializeTupleVariant = Impossible<String, SerdeError>;
+    type SerializeMap = Impossible<String, SerdeError>;
+    type SerializeStruct = Impossible<String, SerdeError>;
+    type SerializeStructVariant = Impossible<String, SerdeError>;
+
+    fn serialize_str(self, v: &str) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<String> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_none(self) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_unit(self) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(key_error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_error())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(key_error())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(key_error())
+    }
+}
+
+/// Serializer writing PackStream bytes. Collections of unknown length are
+/// buffered until their size is known, everything else is written through.
+///
+struct PackSerializer<'a> {
+    out: &'a mut dyn Write,
+}
+
+impl<'a> PackSerializer<'a> {
+    fn variant(self, variant: &str) -> Result<Self> {
+        pack_map_header(1, self.out)?;
+        pack_string(variant, self.out)?;
+        Ok(self)
+    }
+
+    fn compound(self, kind: Kind, len: Option<usize>) -> Result<PackCompound<'a>> {
+        let buffer = match len {
+            Some(size) => {
+                match kind {
+                    Kind::List => pack_list_header(size, self.out)?,
+                    Kind::Map => pack_map_header(size, self.out)?,
+                }
+                None
+            }
+            None => Some(Vec::new()),
+        };
+        Ok(PackCompound {
+            out: self.out,
+            kind,
+            buffer,
+            count: 0,
+        })
+    }
+}
+
+impl<'a> Serializer for PackSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+    type SerializeSeq = PackCompound<'a>;
+    type SerializeTuple = PackCompound<'a>;
+    type SerializeTupleStruct = PackCompound<'a>;
+    type SerializeTupleVariant = PackCompound<'a>;
+    type SerializeMap = PackCompound<'a>;
+    type SerializeStruct = PackCompound<'a>;
+    type SerializeStructVariant = PackCompound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        Ok(pack_boolean(v, self.out)?)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        Ok(pack_integer(v, self.out)?)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.serialize_i64(u64_to_i64(v)?)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        Ok(pack_float(v, self.out)?)
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        Ok(pack_string(v, self.out)?)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        pack_list_header(v.len(), self.out)?;
+        for &b in v {
+            pack_integer(i64::from(b), self.out)?;
+        }
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Ok(pack_null(self.out)?)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(pack_null(self.out)?)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        if name == STRUCTURE_TOKEN {
+            return Ok(structure(value.serialize(ValueSerializer)?)?.pack(self.out)?);
+        }
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self.variant(variant)?)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<PackCompound<'a>> {
+        self.compound(Kind::List, len)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<PackCompound<'a>> {
+        self.compound(Kind::List, Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<PackCompound<'a>> {
+        self.compound(Kind::List, Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<PackCompound<'a>> {
+        self.variant(variant)?.compound(Kind::List, Some(len))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<PackCompound<'a>> {
+        self.compound(Kind::Map, len)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<PackCompound<'a>> {
+        self.compound(Kind::Map, Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<PackCompound<'a>> {
+        self.variant(variant)?.compound(Kind::Map, Some(len))
+    }
+}
+
+enum Kind {
+    List,
+    Map,
+}
+
+struct PackCompound<'a> {
+    out: &'a mut dyn Write,
+    kind: Kind,
+    buffer: Option<Vec<u8>>,
+    count: usize,
+}
+
+impl<'a> PackCompound<'a> {
+    fn target(&mut self) -> PackSerializer<'_> {
+        match self.buffer {
+            Some(ref mut buffer) => PackSerializer { out: buffer },
+            None => PackSerializer { out: self.out },
+        }
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(self.target())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        let key = key.serialize(KeySerializer)?;
+        pack_string(&key, self.target().out)?;
+        Ok(())
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        pack_string(key, self.target().out)?;
+        self.element(value)
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Some(buffer) = self.buffer {
+            match self.kind {
+                Kind::List => pack_list_header(self.count, self.out)?,
+                Kind::Map => pack_map_header(self.count, self.out)?,
+            }
+            self.out
+                .write_all(&buffer)
+                .map_err(|e| SerdeError::Pack(PackError::Io(e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeSeq for PackCompound<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTuple for PackCompound<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for PackCompound<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for PackCompound<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeMap for PackCompound<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStruct for PackCompound<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for PackCompound<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Admin,
+        Guest { expires: i64 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Person {
+        name: String,
+        age: u8,
+        email: Option<String>,
+        tags: Vec<String>,
+        role: Role,
+    }
+
+    fn person() -> Person {
+        Person {
+            name: String::from("Alice"),
+            age: 33,
+            email: None,
+            tags: vec![String::from("a"), String::from("b")],
+            role: Role::Guest { expires: 1000 },
+        }
+    }
+
+    #[test]
+    fn value_round_trip() {
+        let value = to_value(&person()).unwrap();
+        assert_eq!(value.clone().into_map().unwrap()["age"], Value::Integer(33));
+        assert_eq!(from_value::<Person>(value).unwrap(), person());
+        let admin = to_value(&Role::Admin).unwrap();
+        assert_eq!(admin, Value::from("Admin"));
+        assert_eq!(from_value::<Role>(admin).unwrap(), Role::Admin);
+    }
+
+    #[test]
+    fn direct_packing_matches_value() {
+        let bytes = to_vec(&person()).unwrap();
+        let unpacked = Value::unpack(&mut &bytes[..]).unwrap();
+        assert_eq!(unpacked, to_value(&person()).unwrap());
+
+        let unsized_len: Vec<u32> = (0..20).filter(|i| i % 2 == 0).collect();
+        struct Unsized<'a>(&'a [u32]);
+        impl<'a> Serialize for Unsized<'a> {
+            fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+                s.collect_seq(self.0.iter().filter(|_| true))
+            }
+        }
+        let bytes = to_vec(&Unsized(&unsized_len)).unwrap();
+        assert_eq!(
+            Value::unpack(&mut &bytes[..]).unwrap(),
+            Value::from(unsized_len)
+        );
+    }
+
+    #[test]
+    fn temporal_values_round_trip() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Event {
+            on: Date,
+            at: DateTimeZoneId,
+            lasting: Duration,
+            raw: Value,
+        }
+
+        let event = Event {
+            on: Date { days: 19_000 },
+            at: DateTimeZoneId {
+                seconds: 1_700_000_000,
+                nanoseconds: 5,
+                tz_id: String::from("Europe/Stockholm"),
+            },
+            lasting: Duration {
+                months: 1,
+                days: 2,
+                seconds: 3,
+                nanoseconds: 4,
+            },
+            raw: Value::LocalTime(LocalTime { nanoseconds: 42 }),
+        };
+        let value = to_value(&event).unwrap();
+        let map = value.clone().into_map().unwrap();
+        assert_eq!(map["on"], Value::Date(event.on));
+        assert_eq!(map["raw"], event.raw);
+        assert_eq!(from_value::<Event>(value.clone()).unwrap(), event);
+
+        let bytes = to_vec(&event).unwrap();
+        assert_eq!(Value::unpack(&mut &bytes[..]).unwrap(), value);
+
+        let date = Value::Date(event.on);
+        assert_eq!(to_value(&date).unwrap(), date);
+        assert_eq!(from_value::<Value>(date.clone()).unwrap(), date);
+        assert_eq!(
+            from_value::<String>(date.clone()).unwrap(),
+            date.to_string()
+        );
+        assert!(from_value::<Date>(Value::from("2022-01-08")).is_err());
+    }
+
+    #[test]
+    fn node_maps_through_properties() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Movie {
+            title: String,
+            released: i64,
+        }
+
+        let node = Value::Structure {
+            signature: NODE,
+            fields: vec![
+                Value::Integer(7),
+                vec!["Movie"].into(),
+                vec![
+                    ("title", Value::from("Heat")),
+                    ("released", Value::from(1995)),
+                ]
+                .into_iter()
+                .collect(),
+            ],
+        };
+        assert_eq!(
+            from_value::<Movie>(node).unwrap(),
+            Movie {
+                title: String::from("Heat"),
+                released: 1995,
+            }
+        );
+    }
+}
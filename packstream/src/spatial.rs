@@ -0,0 +1,144 @@
+use std::{convert::TryFrom, fmt};
+
+use crate::{StructType, Value};
+
+/// A point in a two-dimensional coordinate reference system, such as
+/// WGS-84 (SRID 4326) or Cartesian (SRID 7203).
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point2D {
+    pub srid: i64,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// A point in a three-dimensional coordinate reference system, such as
+/// WGS-84-3D (SRID 4979) or Cartesian-3D (SRID 9157).
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point3D {
+    pub srid: i64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Point2D {
+    pub fn new(srid: i64, x: f64, y: f64) -> Self {
+        Point2D { srid, x, y }
+    }
+}
+
+impl Point3D {
+    pub fn new(srid: i64, x: f64, y: f64, z: f64) -> Self {
+        Point3D { srid, x, y, z }
+    }
+}
+
+impl fmt::Display for Point2D {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "point({{srid: {}, x: {:?}, y: {:?}}})",
+            self.srid, self.x, self.y
+        )
+    }
+}
+
+impl fmt::Display for Point3D {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "point({{srid: {}, x: {:?}, y: {:?}, z: {:?}}})",
+            self.srid, self.x, self.y, self.z
+        )
+    }
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match *value {
+        Value::Float(v) => Some(v),
+        _ => None,
+    }
+}
+
+impl StructType for Point2D {
+    const SIGNATURE: u8 = b'X';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![self.srid.into(), self.x.into(), self.y.into()]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [Value::Integer(srid), x, y] => Some(Point2D {
+                srid: *srid,
+                x: float(x)?,
+                y: float(y)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl StructType for Point3D {
+    const SIGNATURE: u8 = b'Y';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![
+            self.srid.into(),
+            self.x.into(),
+            self.y.into(),
+            self.z.into(),
+        ]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [Value::Integer(srid), x, y, z] => Some(Point3D {
+                srid: *srid,
+                x: float(x)?,
+                y: float(y)?,
+                z: float(z)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl_Value_conversions!(Point2D);
+impl_Value_conversions!(Point3D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parameters;
+
+    #[test]
+    fn pack_round_trip() {
+        let params = parameters!(
+            "home" => Point2D::new(4326, 12.99, 55.61),
+            "office" => Point3D::new(9157, 1.0, -2.5, 3.0)
+        );
+        let bytes = Value::from(params.clone()).pack_into().unwrap();
+        match Value::unpack(&mut &bytes[..]).unwrap() {
+            Value::Map(map) => {
+                assert_eq!(
+                    Point2D::try_from(map["home"].clone()),
+                    Ok(Point2D::new(4326, 12.99, 55.61))
+                );
+                assert_eq!(map["office"], params["office"]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Value::from(Point3D::new(9157, 1.0, -2.5, 3.0)).to_string(),
+            "point({srid: 9157, x: 1.0, y: -2.5, z: 3.0})"
+        );
+    }
+}
@@ -0,0 +1,881 @@
+//! Temporal values as carried by Bolt structures.
+//!
+//! `DateTime` and `DateTimeZoneId` follow the Bolt v1-v4 encoding, in which
+//! `seconds` counts local (wall clock) time since the epoch rather than UTC.
+
+use std::{
+    convert::TryFrom,
+    fmt,
+    time::{self, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{StructType, Value};
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+const SECONDS_PER_DAY: i64 = 86_400;
+const NANOS_PER_DAY: i64 = SECONDS_PER_DAY * NANOS_PER_SECOND;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub days: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Time {
+    pub nanoseconds: i64,
+    pub tz_offset_seconds: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LocalTime {
+    pub nanoseconds: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DateTime {
+    pub seconds: i64,
+    pub nanoseconds: i64,
+    pub tz_offset_seconds: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DateTimeZoneId {
+    pub seconds: i64,
+    pub nanoseconds: i64,
+    pub tz_id: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LocalDateTime {
+    pub seconds: i64,
+    pub nanoseconds: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Duration {
+    pub months: i64,
+    pub days: i64,
+    pub seconds: i64,
+    pub nanoseconds: i64,
+}
+
+/// Converts a civil date into days since 1970-01-01 (proleptic Gregorian).
+///
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Converts days since 1970-01-01 into a civil (year, month, day).
+///
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn system_time(seconds: i64, nanoseconds: i64) -> SystemTime {
+    let seconds = seconds + nanoseconds.div_euclid(NANOS_PER_SECOND);
+    let nanoseconds = nanoseconds.rem_euclid(NANOS_PER_SECOND) as u64;
+    let epoch = if seconds >= 0 {
+        UNIX_EPOCH + time::Duration::from_secs(seconds as u64)
+    } else {
+        UNIX_EPOCH - time::Duration::from_secs(seconds.unsigned_abs())
+    };
+    epoch + time::Duration::from_nanos(nanoseconds)
+}
+
+fn epoch_seconds(t: SystemTime) -> (i64, i64) {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, i64::from(d.subsec_nanos())),
+        Err(e) => {
+            let d = e.duration();
+            let seconds = -(d.as_secs() as i64);
+            match d.subsec_nanos() {
+                0 => (seconds, 0),
+                n => (seconds - 1, NANOS_PER_SECOND - i64::from(n)),
+            }
+        }
+    }
+}
+
+fn write_time(f: &mut fmt::Formatter, nanoseconds: i64) -> fmt::Result {
+    let seconds = nanoseconds.div_euclid(NANOS_PER_SECOND);
+    let fraction = nanoseconds.rem_euclid(NANOS_PER_SECOND);
+    write!(
+        f,
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )?;
+    if fraction != 0 {
+        write!(f, ".{:09}", fraction)?;
+    }
+    Ok(())
+}
+
+fn write_offset(f: &mut fmt::Formatter, tz_offset_seconds: i64) -> fmt::Result {
+    if tz_offset_seconds == 0 {
+        return write!(f, "Z");
+    }
+    let sign = if tz_offset_seconds < 0 { '-' } else { '+' };
+    let offset = tz_offset_seconds.abs();
+    write!(f, "{}{:02}:{:02}", sign, offset / 3600, offset / 60 % 60)?;
+    if offset % 60 != 0 {
+        write!(f, ":{:02}", offset % 60)?;
+    }
+    Ok(())
+}
+
+fn write_local_date_time(f: &mut fmt::Formatter, seconds: i64, nanoseconds: i64) -> fmt::Result {
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+    let time = seconds.rem_euclid(SECONDS_PER_DAY) * NANOS_PER_SECOND + nanoseconds;
+    write!(f, "{}T", Date { days })?;
+    write_time(f, time)
+}
+
+impl Date {
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Self> {
+        let days = days_from_civil(year, month, day);
+        if (1..=12).contains(&month) && civil_from_days(days) == (year, month, day) {
+            Some(Date { days })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the (year, month, day) of this date.
+    ///
+    pub fn ymd(&self) -> (i64, u32, u32) {
+        civil_from_days(self.days)
+    }
+}
+
+impl LocalTime {
+    pub fn from_hms_nano(hour: u32, minute: u32, second: u32, nano: u32) -> Option<Self> {
+        if hour < 24 && minute < 60 && second < 60 && i64::from(nano) < NANOS_PER_SECOND {
+            let seconds = i64::from(hour * 3600 + minute * 60 + second);
+            Some(LocalTime {
+                nanoseconds: seconds * NANOS_PER_SECOND + i64::from(nano),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Time {
+    pub fn new(time: LocalTime, tz_offset_seconds: i64) -> Self {
+        Time {
+            nanoseconds: time.nanoseconds,
+            tz_offset_seconds,
+        }
+    }
+
+    pub fn local(&self) -> LocalTime {
+        LocalTime {
+            nanoseconds: self.nanoseconds,
+        }
+    }
+}
+
+impl LocalDateTime {
+    pub fn new(date: Date, time: LocalTime) -> Self {
+        LocalDateTime {
+            seconds: date.days * SECONDS_PER_DAY + time.nanoseconds / NANOS_PER_SECOND,
+            nanoseconds: time.nanoseconds % NANOS_PER_SECOND,
+        }
+    }
+
+    pub fn date(&self) -> Date {
+        Date {
+            days: self.seconds.div_euclid(SECONDS_PER_DAY),
+        }
+    }
+
+    pub fn time(&self) -> LocalTime {
+        LocalTime {
+            nanoseconds: self.seconds.rem_euclid(SECONDS_PER_DAY) * NANOS_PER_SECOND
+                + self.nanoseconds,
+        }
+    }
+}
+
+impl DateTime {
+    /// Builds the date-time of the instant `t` as seen at the given offset.
+    ///
+    pub fn from_system_time(t: SystemTime, tz_offset_seconds: i64) -> Self {
+        let (seconds, nanoseconds) = epoch_seconds(t);
+        DateTime {
+            seconds: seconds + tz_offset_seconds,
+            nanoseconds,
+            tz_offset_seconds,
+        }
+    }
+
+    pub fn local(&self) -> LocalDateTime {
+        LocalDateTime {
+            seconds: self.seconds,
+            nanoseconds: self.nanoseconds,
+        }
+    }
+}
+
+impl DateTimeZoneId {
+    pub fn local(&self) -> LocalDateTime {
+        LocalDateTime {
+            seconds: self.seconds,
+            nanoseconds: self.nanoseconds,
+        }
+    }
+}
+
+impl Duration {
+    pub fn new(months: i64, days: i64, seconds: i64, nanoseconds: i64) -> Self {
+        Duration {
+            months,
+            days,
+            seconds,
+            nanoseconds,
+        }
+    }
+
+    /// Total length of the days, seconds and nanoseconds parts.
+    ///
+    fn day_time_nanoseconds(&self) -> i128 {
+        i128::from(self.days) * i128::from(NANOS_PER_DAY)
+            + i128::from(self.seconds) * i128::from(NANOS_PER_SECOND)
+            + i128::from(self.nanoseconds)
+    }
+}
+
+impl From<LocalTime> for time::Duration {
+    fn from(val: LocalTime) -> Self {
+        time::Duration::from_nanos(val.nanoseconds.rem_euclid(NANOS_PER_DAY) as u64)
+    }
+}
+
+impl TryFrom<time::Duration> for LocalTime {
+    type Error = time::Duration;
+
+    /// Interprets the duration as time since midnight, which must be less
+    /// than a day.
+    fn try_from(val: time::Duration) -> Result<Self, Self::Error> {
+        match i64::try_from(val.as_nanos()) {
+            Ok(nanoseconds) if nanoseconds < NANOS_PER_DAY => Ok(LocalTime { nanoseconds }),
+            _ => Err(val),
+        }
+    }
+}
+
+impl From<SystemTime> for DateTime {
+    fn from(val: SystemTime) -> Self {
+        DateTime::from_system_time(val, 0)
+    }
+}
+
+impl From<DateTime> for SystemTime {
+    fn from(val: DateTime) -> Self {
+        system_time(val.seconds - val.tz_offset_seconds, val.nanoseconds)
+    }
+}
+
+impl From<SystemTime> for LocalDateTime {
+    /// Takes the UTC wall clock time of the instant.
+    fn from(val: SystemTime) -> Self {
+        let (seconds, nanoseconds) = epoch_seconds(val);
+        LocalDateTime {
+            seconds,
+            nanoseconds,
+        }
+    }
+}
+
+impl From<LocalDateTime> for SystemTime {
+    /// Reads the wall clock time as UTC.
+    fn from(val: LocalDateTime) -> Self {
+        system_time(val.seconds, val.nanoseconds)
+    }
+}
+
+impl From<time::Duration> for Duration {
+    fn from(val: time::Duration) -> Self {
+        Duration::new(0, 0, val.as_secs() as i64, i64::from(val.subsec_nanos()))
+    }
+}
+
+impl TryFrom<Duration> for time::Duration {
+    type Error = Duration;
+
+    /// Counts days as 86400 seconds. Durations with months, which have no
+    /// fixed length, and negative durations cannot be converted.
+    fn try_from(val: Duration) -> Result<Self, Self::Error> {
+        let nanoseconds = val.day_time_nanoseconds();
+        if val.months != 0 || nanoseconds < 0 {
+            return Err(val);
+        }
+        let seconds = nanoseconds / i128::from(NANOS_PER_SECOND);
+        let nanos = (nanoseconds % i128::from(NANOS_PER_SECOND)) as u32;
+        u64::try_from(seconds)
+            .map(|seconds| time::Duration::new(seconds, nanos))
+            .map_err(|_| val)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = self.ymd();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_time(f, self.nanoseconds)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_time(f, self.nanoseconds)?;
+        write_offset(f, self.tz_offset_seconds)
+    }
+}
+
+impl fmt::Display for LocalDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_local_date_time(f, self.seconds, self.nanoseconds)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_local_date_time(f, self.seconds, self.nanoseconds)?;
+        write_offset(f, self.tz_offset_seconds)
+    }
+}
+
+impl fmt::Display for DateTimeZoneId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_local_date_time(f, self.seconds, self.nanoseconds)?;
+        write!(f, "[{}]", self.tz_id)
+    }
+}
+
+impl fmt::Display for Duration {
+    /// Formats as ISO 8601, e.g. `P1M2DT3.5S`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "P{}M{}DT", self.months, self.days)?;
+        let nanoseconds =
+            i128::from(self.seconds) * i128::from(NANOS_PER_SECOND) + i128::from(self.nanoseconds);
+        let sign = if nanoseconds < 0 { "-" } else { "" };
+        let nanoseconds = nanoseconds.abs();
+        let fraction = nanoseconds % i128::from(NANOS_PER_SECOND);
+        write!(f, "{}{}", sign, nanoseconds / i128::from(NANOS_PER_SECOND))?;
+        if fraction != 0 {
+            write!(f, ".{:09}", fraction)?;
+        }
+        write!(f, "S")
+    }
+}
+
+fn int(value: &Value) -> Option<i64> {
+    match *value {
+        Value::Integer(v) => Some(v),
+        _ => None,
+    }
+}
+
+impl StructType for Date {
+    const SIGNATURE: u8 = b'D';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![self.days.into()]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [days] => Some(Date { days: int(days)? }),
+            _ => None,
+        }
+    }
+}
+
+impl StructType for Time {
+    const SIGNATURE: u8 = b'T';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![self.nanoseconds.into(), self.tz_offset_seconds.into()]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [nanoseconds, tz_offset_seconds] => Some(Time {
+                nanoseconds: int(nanoseconds)?,
+                tz_offset_seconds: int(tz_offset_seconds)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl StructType for LocalTime {
+    const SIGNATURE: u8 = b't';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![self.nanoseconds.into()]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [nanoseconds] => Some(LocalTime {
+                nanoseconds: int(nanoseconds)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl StructType for DateTime {
+    const SIGNATURE: u8 = b'F';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![
+            self.seconds.into(),
+            self.nanoseconds.into(),
+            self.tz_offset_seconds.into(),
+        ]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [seconds, nanoseconds, tz_offset_seconds] => Some(DateTime {
+                seconds: int(seconds)?,
+                nanoseconds: int(nanoseconds)?,
+                tz_offset_seconds: int(tz_offset_seconds)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl StructType for DateTimeZoneId {
+    const SIGNATURE: u8 = b'f';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![
+            self.seconds.into(),
+            self.nanoseconds.into(),
+            self.tz_id.into(),
+        ]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [seconds, nanoseconds, Value::String(tz_id)] => Some(DateTimeZoneId {
+                seconds: int(seconds)?,
+                nanoseconds: int(nanoseconds)?,
+                tz_id: tz_id.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl StructType for LocalDateTime {
+    const SIGNATURE: u8 = b'd';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![self.seconds.into(), self.nanoseconds.into()]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [seconds, nanoseconds] => Some(LocalDateTime {
+                seconds: int(seconds)?,
+                nanoseconds: int(nanoseconds)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl StructType for Duration {
+    const SIGNATURE: u8 = b'E';
+
+    fn into_fields(self) -> Vec<Value> {
+        vec![
+            self.months.into(),
+            self.days.into(),
+            self.seconds.into(),
+            self.nanoseconds.into(),
+        ]
+    }
+
+    fn from_fields(fields: &[Value]) -> Option<Self> {
+        match fields {
+            [months, days, seconds, nanoseconds] => Some(Duration {
+                months: int(months)?,
+                days: int(days)?,
+                seconds: int(seconds)?,
+                nanoseconds: int(nanoseconds)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl_Value_conversions!(Date);
+impl_Value_conversions!(Time);
+impl_Value_conversions!(LocalTime);
+impl_Value_conversions!(DateTime);
+impl_Value_conversions!(DateTimeZoneId);
+impl_Value_conversions!(LocalDateTime);
+impl_Value_conversions!(Duration);
+
+#[cfg(feature = "chrono")]
+mod chrono_support {
+    use std::convert::TryFrom;
+
+    use chrono::{FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Timelike};
+
+    use super::*;
+
+    impl From<NaiveDate> for Date {
+        fn from(val: NaiveDate) -> Self {
+            Date {
+                days: val.signed_duration_since(NaiveDate::default()).num_days(),
+            }
+        }
+    }
+
+    impl TryFrom<Date> for NaiveDate {
+        type Error = Date;
+
+        fn try_from(val: Date) -> Result<Self, Self::Error> {
+            NaiveDate::default()
+                .checked_add_signed(TimeDelta::try_days(val.days).ok_or(val)?)
+                .ok_or(val)
+        }
+    }
+
+    impl From<NaiveTime> for LocalTime {
+        fn from(val: NaiveTime) -> Self {
+            LocalTime {
+                nanoseconds: i64::from(val.num_seconds_from_midnight()) * NANOS_PER_SECOND
+                    + i64::from(val.nanosecond()),
+            }
+        }
+    }
+
+    impl TryFrom<LocalTime> for NaiveTime {
+        type Error = LocalTime;
+
+        fn try_from(val: LocalTime) -> Result<Self, Self::Error> {
+            let seconds = val.nanoseconds.div_euclid(NANOS_PER_SECOND);
+            let nanos = val.nanoseconds.rem_euclid(NANOS_PER_SECOND);
+            u32::try_from(seconds)
+                .ok()
+                .and_then(|s| NaiveTime::from_num_seconds_from_midnight_opt(s, nanos as u32))
+                .ok_or(val)
+        }
+    }
+
+    impl From<NaiveDateTime> for LocalDateTime {
+        fn from(val: NaiveDateTime) -> Self {
+            let utc = val.and_utc();
+            LocalDateTime {
+                seconds: utc.timestamp(),
+                nanoseconds: i64::from(utc.timestamp_subsec_nanos()),
+            }
+        }
+    }
+
+    impl TryFrom<LocalDateTime> for NaiveDateTime {
+        type Error = LocalDateTime;
+
+        fn try_from(val: LocalDateTime) -> Result<Self, Self::Error> {
+            chrono::DateTime::from_timestamp(val.seconds, val.nanoseconds as u32)
+                .map(|t| t.naive_utc())
+                .ok_or(val)
+        }
+    }
+
+    impl From<chrono::DateTime<FixedOffset>> for DateTime {
+        fn from(val: chrono::DateTime<FixedOffset>) -> Self {
+            let local = LocalDateTime::from(val.naive_local());
+            DateTime {
+                seconds: local.seconds,
+                nanoseconds: local.nanoseconds,
+                tz_offset_seconds: i64::from(val.offset().local_minus_utc()),
+            }
+        }
+    }
+
+    impl TryFrom<DateTime> for chrono::DateTime<FixedOffset> {
+        type Error = DateTime;
+
+        fn try_from(val: DateTime) -> Result<Self, Self::Error> {
+            let offset = i32::try_from(val.tz_offset_seconds)
+                .ok()
+                .and_then(FixedOffset::east_opt)
+                .ok_or(val)?;
+            let local = NaiveDateTime::try_from(val.local()).map_err(|_| val)?;
+            local.and_local_timezone(offset).single().ok_or(val)
+        }
+    }
+
+    impl TryFrom<Duration> for TimeDelta {
+        type Error = Duration;
+
+        /// Counts days as 86400 seconds. Durations with months cannot be
+        /// converted.
+        fn try_from(val: Duration) -> Result<Self, Self::Error> {
+            if val.months != 0 {
+                return Err(val);
+            }
+            TimeDelta::try_days(val.days)
+                .and_then(|d| d.checked_add(&TimeDelta::try_seconds(val.seconds)?))
+                .and_then(|d| d.checked_add(&TimeDelta::nanoseconds(val.nanoseconds)))
+                .ok_or(val)
+        }
+    }
+
+    impl From<TimeDelta> for Duration {
+        fn from(val: TimeDelta) -> Self {
+            let seconds = val.num_seconds();
+            let nanoseconds = (val - TimeDelta::seconds(seconds))
+                .num_nanoseconds()
+                .unwrap_or(0);
+            Duration::new(0, 0, seconds, nanoseconds)
+        }
+    }
+}
+
+#[cfg(feature = "time")]
+mod time_support {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    impl From<::time::Date> for Date {
+        fn from(val: ::time::Date) -> Self {
+            Date {
+                days: i64::from(val.to_julian_day()) - i64::from(EPOCH_JULIAN_DAY),
+            }
+        }
+    }
+
+    const EPOCH_JULIAN_DAY: i32 = 2_440_588;
+
+    impl TryFrom<Date> for ::time::Date {
+        type Error = Date;
+
+        fn try_from(val: Date) -> Result<Self, Self::Error> {
+            i32::try_from(val.days + i64::from(EPOCH_JULIAN_DAY))
+                .ok()
+                .and_then(|d| ::time::Date::from_julian_day(d).ok())
+                .ok_or(val)
+        }
+    }
+
+    impl From<::time::Time> for LocalTime {
+        fn from(val: ::time::Time) -> Self {
+            let (hour, minute, second, nano) = val.as_hms_nano();
+            let seconds = i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second);
+            LocalTime {
+                nanoseconds: seconds * NANOS_PER_SECOND + i64::from(nano),
+            }
+        }
+    }
+
+    impl TryFrom<LocalTime> for ::time::Time {
+        type Error = LocalTime;
+
+        fn try_from(val: LocalTime) -> Result<Self, Self::Error> {
+            let seconds = val.nanoseconds.div_euclid(NANOS_PER_SECOND);
+            let nanos = val.nanoseconds.rem_euclid(NANOS_PER_SECOND) as u32;
+            u8::try_from(seconds / 3600)
+                .ok()
+                .and_then(|h| {
+                    ::time::Time::from_hms_nano(
+                        h,
+                        (seconds / 60 % 60) as u8,
+                        (seconds % 60) as u8,
+                        nanos,
+                    )
+                    .ok()
+                })
+                .ok_or(val)
+        }
+    }
+
+    impl From<::time::PrimitiveDateTime> for LocalDateTime {
+        fn from(val: ::time::PrimitiveDateTime) -> Self {
+            LocalDateTime::new(val.date().into(), val.time().into())
+        }
+    }
+
+    impl TryFrom<LocalDateTime> for ::time::PrimitiveDateTime {
+        type Error = LocalDateTime;
+
+        fn try_from(val: LocalDateTime) -> Result<Self, Self::Error> {
+            let date = ::time::Date::try_from(val.date()).map_err(|_| val)?;
+            let time = ::time::Time::try_from(val.time()).map_err(|_| val)?;
+            Ok(::time::PrimitiveDateTime::new(date, time))
+        }
+    }
+
+    impl From<::time::OffsetDateTime> for DateTime {
+        fn from(val: ::time::OffsetDateTime) -> Self {
+            let local = LocalDateTime::new(val.date().into(), val.time().into());
+            DateTime {
+                seconds: local.seconds,
+                nanoseconds: local.nanoseconds,
+                tz_offset_seconds: i64::from(val.offset().whole_seconds()),
+            }
+        }
+    }
+
+    impl TryFrom<DateTime> for ::time::OffsetDateTime {
+        type Error = DateTime;
+
+        fn try_from(val: DateTime) -> Result<Self, Self::Error> {
+            let offset = i32::try_from(val.tz_offset_seconds)
+                .ok()
+                .and_then(|s| ::time::UtcOffset::from_whole_seconds(s).ok())
+                .ok_or(val)?;
+            let local = ::time::PrimitiveDateTime::try_from(val.local()).map_err(|_| val)?;
+            Ok(local.assume_offset(offset))
+        }
+    }
+
+    impl TryFrom<Duration> for ::time::Duration {
+        type Error = Duration;
+
+        /// Counts days as 86400 seconds. Durations with months cannot be
+        /// converted.
+        fn try_from(val: Duration) -> Result<Self, Self::Error> {
+            if val.months != 0 {
+                return Err(val);
+            }
+            let nanoseconds = val.day_time_nanoseconds();
+            let seconds =
+                i64::try_from(nanoseconds / i128::from(NANOS_PER_SECOND)).map_err(|_| val)?;
+            let nanos = (nanoseconds % i128::from(NANOS_PER_SECOND)) as i32;
+            Ok(::time::Duration::new(seconds, nanos))
+        }
+    }
+
+    impl From<::time::Duration> for Duration {
+        fn from(val: ::time::Duration) -> Self {
+            Duration::new(
+                0,
+                0,
+                val.whole_seconds(),
+                i64::from(val.subsec_nanoseconds()),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_round_trip() {
+        for &(days, ymd) in &[
+            (0, (1970, 1, 1)),
+            (-1, (1969, 12, 31)),
+            (11016, (2000, 2, 29)),
+            (-719_528, (0, 1, 1)),
+        ] {
+            assert_eq!(Date { days }.ymd(), ymd);
+            assert_eq!(Date::from_ymd(ymd.0, ymd.1, ymd.2), Some(Date { days }));
+        }
+        assert_eq!(Date::from_ymd(2001, 2, 29), None);
+    }
+
+    #[test]
+    fn date_time_system_time() {
+        let t = UNIX_EPOCH - time::Duration::from_millis(1500);
+        let dt = DateTime::from_system_time(t, 3600);
+        assert_eq!(dt.seconds, -2 + 3600);
+        assert_eq!(dt.nanoseconds, 500_000_000);
+        assert_eq!(SystemTime::from(dt), t);
+        assert_eq!(dt.to_string(), "1970-01-01T00:59:58.500000000+01:00");
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let values = vec![
+            Value::from(Date::from_ymd(2019, 3, 14).unwrap()),
+            Value::from(Time::new(
+                LocalTime::from_hms_nano(12, 30, 0, 5).unwrap(),
+                -18000,
+            )),
+            Value::from(DateTimeZoneId {
+                seconds: 1_552_563_000,
+                nanoseconds: 0,
+                tz_id: String::from("Europe/Berlin"),
+            }),
+            Value::from(Duration::new(14, 16, 12, 0)),
+        ];
+        for value in values {
+            let bytes = value.clone().pack_into().unwrap();
+            assert_eq!(Value::unpack(&mut &bytes[..]).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn duration_std() {
+        let d = Duration::new(0, 1, 2, 3);
+        assert_eq!(
+            time::Duration::try_from(d),
+            Ok(time::Duration::new(86_402, 3))
+        );
+        assert!(time::Duration::try_from(Duration::new(1, 0, 0, 0)).is_err());
+        assert_eq!(d.to_string(), "P0M1DT2.000000003S");
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_round_trip() {
+        use chrono::{FixedOffset, TimeZone};
+
+        let t = FixedOffset::east_opt(-3600)
+            .unwrap()
+            .with_ymd_and_hms(1969, 7, 20, 20, 17, 40)
+            .unwrap();
+        let dt = DateTime::from(t);
+        assert_eq!(dt.to_string(), "1969-07-20T20:17:40-01:00");
+        assert_eq!(chrono::DateTime::try_from(dt), Ok(t));
+        assert_eq!(SystemTime::from(dt), SystemTime::from(t));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn time_round_trip() {
+        let date = ::time::Date::from_calendar_date(1969, ::time::Month::July, 20).unwrap();
+        let time = ::time::Time::from_hms_milli(20, 17, 40, 500).unwrap();
+        let offset = ::time::UtcOffset::from_hms(-1, 0, 0).unwrap();
+        let t = ::time::PrimitiveDateTime::new(date, time).assume_offset(offset);
+        let dt = DateTime::from(t);
+        assert_eq!(dt.to_string(), "1969-07-20T20:17:40.500000000-01:00");
+        assert_eq!(::time::OffsetDateTime::try_from(dt), Ok(t));
+    }
+}
@@ -1,5 +1,7 @@
 use std::{
     collections::HashMap,
+    error::Error,
+    fmt,
     io::{self, Read},
 };
 
@@ -7,106 +9,368 @@ use byteorder::{BigEndian, ReadBytesExt};
 
 use crate::Value;
 
-pub type UnpackResult = Result<Value, io::Error>;
+/// Upper bound on up-front allocations driven by a declared size, so that a
+/// corrupt size cannot trigger a huge allocation before the data runs out.
+const MAX_PREALLOCATION: usize = 1024;
+
+/// How deeply lists, maps and structures may be nested. Decoding recurses
+/// once for each level, so anything deeper is refused rather than risk
+/// running out of stack.
+pub(crate) const MAX_DEPTH: usize = 256;
+
+pub type UnpackResult = Result<Value, UnpackError>;
+
+/// Failure to decode a PackStream value. Every variant carries the byte
+/// offset, relative to the start of the value, at which decoding failed.
+///
+#[derive(Debug)]
+pub enum UnpackError {
+    UnknownMarker {
+        marker: u8,
+        offset: usize,
+    },
+    InvalidUtf8 {
+        offset: usize,
+    },
+    NonStringKey {
+        offset: usize,
+    },
+    UnexpectedEof {
+        offset: usize,
+    },
+    SizeLimitExceeded {
+        size: usize,
+        limit: usize,
+        offset: usize,
+    },
+    TooDeep {
+        offset: usize,
+    },
+    Io {
+        error: io::Error,
+        offset: usize,
+    },
+}
+
+impl UnpackError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            UnpackError::UnknownMarker { offset, .. } => offset,
+            UnpackError::InvalidUtf8 { offset } => offset,
+            UnpackError::NonStringKey { offset } => offset,
+            UnpackError::UnexpectedEof { offset } => offset,
+            UnpackError::SizeLimitExceeded { offset, .. } => offset,
+            UnpackError::TooDeep { offset } => offset,
+            UnpackError::Io { offset, .. } => offset,
+        }
+    }
+}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnpackError::UnknownMarker { marker, offset } => {
+                write!(f, "Unknown marker {:02X} at byte {}", marker, offset)
+            }
+            UnpackError::InvalidUtf8 { offset } => {
+                write!(f, "Invalid UTF-8 in string at byte {}", offset)
+            }
+            UnpackError::NonStringKey { offset } => {
+                write!(f, "Map key is not a string at byte {}", offset)
+            }
+            UnpackError::UnexpectedEof { offset } => {
+                write!(f, "Unexpected end of input at byte {}", offset)
+            }
+            UnpackError::SizeLimitExceeded {
+                size,
+                limit,
+                offset,
+            } => write!(
+                f,
+                "Size {} exceeds limit of {} at byte {}",
+                size, limit, offset
+            ),
+            UnpackError::TooDeep { offset } => {
+                write!(f, "Values nested too deeply at byte {}", offset)
+            }
+            UnpackError::Io { ref error, offset } => {
+                write!(f, "Read error at byte {}: {}", offset, error)
+            }
+        }
+    }
+}
+
+impl Error for UnpackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            UnpackError::Io { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Wraps the source stream to keep track of the current byte offset.
+///
+struct Input<'a> {
+    stream: &'a mut dyn Read,
+    offset: usize,
+    limit: usize,
+    depth: usize,
+}
+
+impl<'a> Read for Input<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+impl<'a> Input<'a> {
+    fn read_with<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> io::Result<T>,
+    ) -> Result<T, UnpackError> {
+        let offset = self.offset;
+        f(self).map_err(|error| match error.kind() {
+            io::ErrorKind::UnexpectedEof => UnpackError::UnexpectedEof { offset },
+            _ => UnpackError::Io { error, offset },
+        })
+    }
+
+    fn check_size(&self, size: usize, offset: usize) -> Result<usize, UnpackError> {
+        if size > self.limit {
+            Err(UnpackError::SizeLimitExceeded {
+                size,
+                limit: self.limit,
+                offset,
+            })
+        } else {
+            Ok(size)
+        }
+    }
+}
 
 pub fn unpack(stream: &mut dyn Read) -> UnpackResult {
-    let marker = stream.read_u8()?;
+    unpack_with_limit(stream, usize::MAX)
+}
+
+/// Unpacks a value, rejecting any string, list, map or structure that
+/// declares more than `limit` bytes or items.
+///
+pub fn unpack_with_limit(stream: &mut dyn Read, limit: usize) -> UnpackResult {
+    let mut input = Input {
+        stream,
+        offset: 0,
+        limit,
+        depth: 0,
+    };
+    unpack_value(&mut input)
+}
+
+/// Unpacks the next value, one level deeper than the one containing it.
+///
+fn unpack_value(input: &mut Input) -> UnpackResult {
+    if input.depth == MAX_DEPTH {
+        return Err(UnpackError::TooDeep {
+            offset: input.offset,
+        });
+    }
+    input.depth += 1;
+    let value = unpack_marked(input);
+    input.depth -= 1;
+    value
+}
+
+fn unpack_marked(input: &mut Input) -> UnpackResult {
+    let offset = input.offset;
+    let marker = input.read_with(|i| i.read_u8())?;
     match marker {
         0x00..=0x7F => Ok(Value::Integer(i64::from(marker))),
-        0x80..=0x8F => unpack_string((marker & 0x0F) as usize, stream),
-        0x90..=0x9F => unpack_list((marker & 0x0F) as usize, stream),
-        0xA0..=0xAF => unpack_map((marker & 0x0F) as usize, stream),
-        0xB0..=0xBF => unpack_structure((marker & 0x0F) as usize, stream),
+        0x80..=0x8F => unpack_string((marker & 0x0F) as usize, input),
+        0x90..=0x9F => unpack_list((marker & 0x0F) as usize, input),
+        0xA0..=0xAF => unpack_map((marker & 0x0F) as usize, input),
+        0xB0..=0xBF => unpack_structure((marker & 0x0F) as usize, input),
         0xC0 => Ok(Value::Null),
-        0xC1 => Ok(Value::Float(stream.read_f64::<BigEndian>()?)),
+        0xC1 => Ok(Value::Float(
+            input.read_with(|i| i.read_f64::<BigEndian>())?,
+        )),
         0xC2 => Ok(Value::Boolean(false)),
         0xC3 => Ok(Value::Boolean(true)),
-        0xC8 => Ok(Value::Integer(i64::from(stream.read_i8()?))),
-        0xC9 => Ok(Value::Integer(i64::from(stream.read_i16::<BigEndian>()?))),
-        0xCA => Ok(Value::Integer(i64::from(stream.read_i32::<BigEndian>()?))),
-        0xCB => Ok(Value::Integer(stream.read_i64::<BigEndian>()?)),
+        0xC8 => Ok(Value::Integer(i64::from(input.read_with(|i| i.read_i8())?))),
+        0xC9 => Ok(Value::Integer(i64::from(
+            input.read_with(|i| i.read_i16::<BigEndian>())?,
+        ))),
+        0xCA => Ok(Value::Integer(i64::from(
+            input.read_with(|i| i.read_i32::<BigEndian>())?,
+        ))),
+        0xCB => Ok(Value::Integer(
+            input.read_with(|i| i.read_i64::<BigEndian>())?,
+        )),
         0xD0 => {
-            let size = stream.read_u8()? as usize;
-            unpack_string(size, stream)
+            let size = input.read_with(|i| i.read_u8())? as usize;
+            unpack_string(input.check_size(size, offset)?, input)
         }
         0xD1 => {
-            let size = stream.read_u16::<BigEndian>()? as usize;
-            unpack_string(size, stream)
+            let size = input.read_with(|i| i.read_u16::<BigEndian>())? as usize;
+            unpack_string(input.check_size(size, offset)?, input)
         }
         0xD2 => {
-            let size = stream.read_u32::<BigEndian>()? as usize;
-            unpack_string(size, stream)
+            let size = input.read_with(|i| i.read_u32::<BigEndian>())? as usize;
+            unpack_string(input.check_size(size, offset)?, input)
         }
         0xD4 => {
-            let size = stream.read_u8()? as usize;
-            unpack_list(size, stream)
+            let size = input.read_with(|i| i.read_u8())? as usize;
+            unpack_list(input.check_size(size, offset)?, input)
         }
         0xD5 => {
-            let size = stream.read_u16::<BigEndian>()? as usize;
-            unpack_list(size, stream)
+            let size = input.read_with(|i| i.read_u16::<BigEndian>())? as usize;
+            unpack_list(input.check_size(size, offset)?, input)
         }
         0xD6 => {
-            let size = stream.read_u32::<BigEndian>()? as usize;
-            unpack_list(size, stream)
+            let size = input.read_with(|i| i.read_u32::<BigEndian>())? as usize;
+            unpack_list(input.check_size(size, offset)?, input)
         }
         0xD8 => {
-            let size = stream.read_u8()? as usize;
-            unpack_map(size, stream)
+            let size = input.read_with(|i| i.read_u8())? as usize;
+            unpack_map(input.check_size(size, offset)?, input)
         }
         0xD9 => {
-            let size = stream.read_u16::<BigEndian>()? as usize;
-            unpack_map(size, stream)
+            let size = input.read_with(|i| i.read_u16::<BigEndian>())? as usize;
+            unpack_map(input.check_size(size, offset)?, input)
         }
         0xDA => {
-            let size = stream.read_u32::<BigEndian>()? as usize;
-            unpack_map(size, stream)
+            let size = input.read_with(|i| i.read_u32::<BigEndian>())? as usize;
+            unpack_map(input.check_size(size, offset)?, input)
         }
         0xDC => {
-            let size = stream.read_u8()? as usize;
-            unpack_structure(size, stream)
+            let size = input.read_with(|i| i.read_u8())? as usize;
+            unpack_structure(input.check_size(size, offset)?, input)
         }
         0xDD => {
-            let size = stream.read_u16::<BigEndian>()? as usize;
-            unpack_structure(size, stream)
+            let size = input.read_with(|i| i.read_u16::<BigEndian>())? as usize;
+            unpack_structure(input.check_size(size, offset)?, input)
         }
         0xF0..=0xFF => Ok(Value::Integer(i64::from(marker) - 0x100)),
-        _ => panic!("Illegal value with marker {:02X}", marker),
+        _ => Err(UnpackError::UnknownMarker { marker, offset }),
     }
 }
 
-fn unpack_string(size: usize, stream: &mut dyn Read) -> UnpackResult {
-    let mut s = String::with_capacity(size);
-    stream.take(size as u64).read_to_string(&mut s)?;
-    Ok(Value::String(s))
+fn unpack_string(size: usize, input: &mut Input) -> UnpackResult {
+    let offset = input.offset;
+    let mut buf = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+    input.read_with(|i| i.take(size as u64).read_to_end(&mut buf))?;
+    if buf.len() < size {
+        return Err(UnpackError::UnexpectedEof {
+            offset: input.offset,
+        });
+    }
+    String::from_utf8(buf)
+        .map(Value::String)
+        .map_err(|e| UnpackError::InvalidUtf8 {
+            offset: offset + e.utf8_error().valid_up_to(),
+        })
 }
 
-fn unpack_list(size: usize, stream: &mut dyn Read) -> UnpackResult {
-    let mut value = Vec::with_capacity(size);
+fn unpack_list(size: usize, input: &mut Input) -> UnpackResult {
+    let mut value = Vec::with_capacity(size.min(MAX_PREALLOCATION));
     for _ in 0..size {
-        value.push(unpack(stream)?);
+        value.push(unpack_value(input)?);
     }
     Ok(Value::List(value))
 }
 
-fn unpack_map(size: usize, stream: &mut dyn Read) -> UnpackResult {
-    let mut value = HashMap::with_capacity(size);
+fn unpack_map(size: usize, input: &mut Input) -> UnpackResult {
+    let mut value = HashMap::with_capacity(size.min(MAX_PREALLOCATION));
     for _ in 0..size {
-        let key = unpack(stream)?;
-        match key {
+        let offset = input.offset;
+        match unpack_value(input)? {
             Value::String(k) => {
-                value.insert(k, unpack(stream)?);
+                value.insert(k, unpack_value(input)?);
             }
-            _ => panic!("Key is not a string"),
+            _ => return Err(UnpackError::NonStringKey { offset }),
         }
     }
     Ok(Value::Map(value))
 }
 
-fn unpack_structure(size: usize, stream: &mut dyn Read) -> UnpackResult {
-    let signature: u8 = stream.read_u8()?;
+fn unpack_structure(size: usize, input: &mut Input) -> UnpackResult {
+    let signature: u8 = input.read_with(|i| i.read_u8())?;
     let mut fields: Vec<Value> = Vec::with_capacity(size);
     for _ in 0..size {
-        fields.push(unpack(stream)?);
+        fields.push(unpack_value(input)?);
+    }
+    Ok(Value::from_structure(signature, fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unpack_bytes(bytes: &[u8]) -> UnpackResult {
+        unpack(&mut &bytes[..])
+    }
+
+    #[test]
+    fn unknown_marker() {
+        match unpack_bytes(&[0x92, 0x01, 0xE0]) {
+            Err(UnpackError::UnknownMarker {
+                marker: 0xE0,
+                offset: 2,
+            }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        match unpack_bytes(&[0x83, b'a', 0xFF, b'c']) {
+            Err(UnpackError::InvalidUtf8 { offset: 2 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn non_string_key() {
+        match unpack_bytes(&[0xA2, 0x81, b'a', 0x01, 0x02, 0x03]) {
+            Err(UnpackError::NonStringKey { offset: 4 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        match unpack_bytes(&[0xC9, 0x01]) {
+            Err(UnpackError::UnexpectedEof { offset: 1 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match unpack_bytes(&[0x85, b'a', b'b']) {
+            Err(UnpackError::UnexpectedEof { offset: 3 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn size_limit_exceeded() {
+        match unpack_with_limit(&mut &[0x90, 0xD6, 0xFF, 0xFF, 0xFF, 0xFF][1..], 1000) {
+            Err(UnpackError::SizeLimitExceeded {
+                size: 0xFFFF_FFFF,
+                limit: 1000,
+                offset: 0,
+            }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn too_deep() {
+        let mut nested = vec![0x91; MAX_DEPTH - 1];
+        nested.push(0x01);
+        assert!(unpack_bytes(&nested).is_ok());
+        let nested = vec![0x91; 100_000];
+        match unpack_bytes(&nested) {
+            Err(UnpackError::TooDeep { offset }) => assert_eq!(offset, MAX_DEPTH),
+            other => panic!("unexpected {:?}", other),
+        }
     }
-    Ok(Value::Structure { signature, fields })
 }
@@ -0,0 +1,684 @@
+use std::{collections::HashMap, convert::TryInto, fmt, str};
+
+use crate::{
+    unpack::{UnpackError, MAX_DEPTH},
+    Value,
+};
+
+/// A PackStream value borrowed from the buffer it was decoded from. Strings
+/// point into the buffer, and lists, maps and structures are read lazily
+/// as they are iterated, so a single field can be picked out of a large
+/// value without allocating. `to_value` builds the owned equivalent.
+///
+/// The whole value is checked when it is decoded, so reading its contents
+/// later cannot fail, and does not check them again.
+///
+#[derive(Clone, Copy)]
+pub enum ValueRef<'a> {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(&'a str),
+    List(ListRef<'a>),
+    Map(MapRef<'a>),
+    Structure { signature: u8, fields: ListRef<'a> },
+}
+
+/// The items of a list or the fields of a structure, still encoded.
+///
+#[derive(Clone, Copy)]
+pub struct ListRef<'a> {
+    data: &'a [u8],
+    start: usize,
+    len: usize,
+}
+
+/// The entries of a map, still encoded.
+///
+#[derive(Clone, Copy)]
+pub struct MapRef<'a> {
+    data: &'a [u8],
+    start: usize,
+    len: usize,
+}
+
+impl<'a> ValueRef<'a> {
+    /// Decodes the value at the start of `data`. Any bytes after it are
+    /// ignored. Nesting is limited as it is for `Value::unpack`.
+    ///
+    pub fn unpack(data: &'a [u8]) -> Result<ValueRef<'a>, UnpackError> {
+        ValueRef::unpack_with_limit(data, usize::MAX)
+    }
+
+    /// Like `unpack`, but fails with `UnpackError::SizeLimitExceeded` on
+    /// any string, list, map or structure declaring more than `limit` bytes
+    /// or items.
+    ///
+    pub fn unpack_with_limit(data: &'a [u8], limit: usize) -> Result<ValueRef<'a>, UnpackError> {
+        read(data, &mut 0, limit, 0)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            ValueRef::Boolean(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            ValueRef::Integer(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            ValueRef::Float(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            ValueRef::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<ListRef<'a>> {
+        match *self {
+            ValueRef::List(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<MapRef<'a>> {
+        match *self {
+            ValueRef::Map(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Copies the value out of the buffer in a single pass, decoding
+    /// structures with a dedicated `Value` variant as `Value::unpack` does.
+    ///
+    pub fn to_value(&self) -> Value {
+        match *self {
+            ValueRef::Null => Value::Null,
+            ValueRef::Boolean(v) => Value::Boolean(v),
+            ValueRef::Integer(v) => Value::Integer(v),
+            ValueRef::Float(v) => Value::Float(v),
+            ValueRef::String(v) => Value::String(String::from(v)),
+            ValueRef::List(ref items) => Value::List(checked_values(
+                items.data,
+                &mut items.start.clone(),
+                items.len,
+            )),
+            ValueRef::Map(ref entries) => Value::Map(checked_map(
+                entries.data,
+                &mut entries.start.clone(),
+                entries.len,
+            )),
+            ValueRef::Structure {
+                signature,
+                ref fields,
+            } => Value::from_structure(
+                signature,
+                checked_values(fields.data, &mut fields.start.clone(), fields.len),
+            ),
+        }
+    }
+}
+
+impl<'a> ListRef<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The item at `index`, found by skipping over those before it. They
+    /// are not decoded, but skipping a list, map or structure reads the
+    /// header of everything inside it.
+    ///
+    pub fn get(&self, index: usize) -> Option<ValueRef<'a>> {
+        self.iter().nth(index)
+    }
+
+    pub fn iter(&self) -> ListIter<'a> {
+        ListIter {
+            data: self.data,
+            pos: self.start,
+            remaining: self.len,
+        }
+    }
+}
+
+impl<'a> MapRef<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The value for `key`, found by skipping over the entries before it,
+    /// as `ListRef::get` does.
+    ///
+    pub fn get(&self, key: &str) -> Option<ValueRef<'a>> {
+        self.iter().find(|&(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> MapIter<'a> {
+        MapIter {
+            items: ListIter {
+                data: self.data,
+                pos: self.start,
+                remaining: 2 * self.len,
+            },
+        }
+    }
+}
+
+impl<'a> IntoIterator for ListRef<'a> {
+    type Item = ValueRef<'a>;
+    type IntoIter = ListIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for MapRef<'a> {
+    type Item = (&'a str, ValueRef<'a>);
+    type IntoIter = MapIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Decodes the items of a `ListRef` one at a time.
+///
+pub struct ListIter<'a> {
+    data: &'a [u8],
+    pos: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for ListIter<'a> {
+    type Item = ValueRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(read_checked(self.data, &mut self.pos))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for ListIter<'a> {}
+
+/// Decodes the entries of a `MapRef` one at a time.
+///
+pub struct MapIter<'a> {
+    items: ListIter<'a>,
+}
+
+impl<'a> Iterator for MapIter<'a> {
+    type Item = (&'a str, ValueRef<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.items.next()?;
+        let value = self.items.next()?;
+        Some((
+            key.as_str().expect("checked when the map was decoded"),
+            value,
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.items.remaining / 2;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for MapIter<'a> {}
+
+impl<'a> fmt::Debug for ValueRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValueRef::Null => write!(f, "null"),
+            ValueRef::Boolean(ref value) => <bool as fmt::Debug>::fmt(value, f),
+            ValueRef::Integer(ref value) => <i64 as fmt::Debug>::fmt(value, f),
+            ValueRef::Float(ref value) => <f64 as fmt::Debug>::fmt(value, f),
+            ValueRef::String(value) => <str as fmt::Debug>::fmt(value, f),
+            ValueRef::List(ref items) => <ListRef as fmt::Debug>::fmt(items, f),
+            ValueRef::Map(ref entries) => <MapRef as fmt::Debug>::fmt(entries, f),
+            ValueRef::Structure {
+                signature,
+                ref fields,
+            } => write!(f, "#{:02X} {:?}", signature, fields),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for ListRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a> fmt::Debug for MapRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+fn byte(data: &[u8], pos: &mut usize) -> Result<u8, UnpackError> {
+    Ok(bytes(data, pos, 1)?[0])
+}
+
+/// The next `n` bytes, failing at `pos` if there are not that many.
+///
+fn bytes<'a>(data: &'a [u8], pos: &mut usize, n: usize) -> Result<&'a [u8], UnpackError> {
+    let offset = *pos;
+    match data.get(offset..).and_then(|rest| rest.get(..n)) {
+        Some(bytes) => {
+            *pos += n;
+            Ok(bytes)
+        }
+        None => Err(UnpackError::UnexpectedEof { offset }),
+    }
+}
+
+/// A size field of `n` bytes, checked against `limit`. `offset` is where
+/// the value began.
+///
+fn size(
+    data: &[u8],
+    pos: &mut usize,
+    n: usize,
+    limit: usize,
+    offset: usize,
+) -> Result<usize, UnpackError> {
+    let size = bytes(data, pos, n)?
+        .iter()
+        .fold(0usize, |size, &b| (size << 8) | usize::from(b));
+    if size > limit {
+        return Err(UnpackError::SizeLimitExceeded {
+            size,
+            limit,
+            offset,
+        });
+    }
+    Ok(size)
+}
+
+/// Decodes the value at `pos`, moving `pos` past it. Lists, maps and
+/// structures are checked all the way down, to at most `MAX_DEPTH` levels,
+/// but not copied.
+///
+fn read<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+    limit: usize,
+    depth: usize,
+) -> Result<ValueRef<'a>, UnpackError> {
+    let offset = *pos;
+    if depth == MAX_DEPTH {
+        return Err(UnpackError::TooDeep { offset });
+    }
+    let depth = depth + 1;
+    let marker = byte(data, pos)?;
+    let value = match marker {
+        0x00..=0x7F => ValueRef::Integer(i64::from(marker)),
+        0x80..=0x8F => read_string(data, pos, usize::from(marker & 0x0F))?,
+        0x90..=0x9F => ValueRef::List(read_list(
+            data,
+            pos,
+            usize::from(marker & 0x0F),
+            limit,
+            depth,
+        )?),
+        0xA0..=0xAF => ValueRef::Map(read_map(
+            data,
+            pos,
+            usize::from(marker & 0x0F),
+            limit,
+            depth,
+        )?),
+        0xB0..=0xBF => read_structure(data, pos, usize::from(marker & 0x0F), limit, depth)?,
+        0xC0 => ValueRef::Null,
+        0xC1 => ValueRef::Float(f64::from_be_bytes(bytes(data, pos, 8)?.try_into().unwrap())),
+        0xC2 => ValueRef::Boolean(false),
+        0xC3 => ValueRef::Boolean(true),
+        0xC8 => ValueRef::Integer(i64::from(byte(data, pos)? as i8)),
+        0xC9 => ValueRef::Integer(i64::from(i16::from_be_bytes(
+            bytes(data, pos, 2)?.try_into().unwrap(),
+        ))),
+        0xCA => ValueRef::Integer(i64::from(i32::from_be_bytes(
+            bytes(data, pos, 4)?.try_into().unwrap(),
+        ))),
+        0xCB => ValueRef::Integer(i64::from_be_bytes(bytes(data, pos, 8)?.try_into().unwrap())),
+        0xD0..=0xD2 => {
+            let n = 1 << (marker - 0xD0);
+            let size = size(data, pos, n, limit, offset)?;
+            read_string(data, pos, size)?
+        }
+        0xD4..=0xD6 => {
+            let n = 1 << (marker - 0xD4);
+            let size = size(data, pos, n, limit, offset)?;
+            ValueRef::List(read_list(data, pos, size, limit, depth)?)
+        }
+        0xD8..=0xDA => {
+            let n = 1 << (marker - 0xD8);
+            let size = size(data, pos, n, limit, offset)?;
+            ValueRef::Map(read_map(data, pos, size, limit, depth)?)
+        }
+        0xDC..=0xDD => {
+            let n = 1 << (marker - 0xDC);
+            let size = size(data, pos, n, limit, offset)?;
+            read_structure(data, pos, size, limit, depth)?
+        }
+        0xF0..=0xFF => ValueRef::Integer(i64::from(marker) - 0x100),
+        _ => return Err(UnpackError::UnknownMarker { marker, offset }),
+    };
+    Ok(value)
+}
+
+fn read_string<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+    size: usize,
+) -> Result<ValueRef<'a>, UnpackError> {
+    let offset = *pos;
+    if data.len() - offset < size {
+        return Err(UnpackError::UnexpectedEof { offset: data.len() });
+    }
+    let bytes = bytes(data, pos, size)?;
+    str::from_utf8(bytes)
+        .map(ValueRef::String)
+        .map_err(|e| UnpackError::InvalidUtf8 {
+            offset: offset + e.valid_up_to(),
+        })
+}
+
+fn read_list<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+    len: usize,
+    limit: usize,
+    depth: usize,
+) -> Result<ListRef<'a>, UnpackError> {
+    let start = *pos;
+    for _ in 0..len {
+        read(data, pos, limit, depth)?;
+    }
+    Ok(ListRef { data, start, len })
+}
+
+fn read_map<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+    len: usize,
+    limit: usize,
+    depth: usize,
+) -> Result<MapRef<'a>, UnpackError> {
+    let start = *pos;
+    for _ in 0..len {
+        let offset = *pos;
+        match read(data, pos, limit, depth)? {
+            ValueRef::String(_) => read(data, pos, limit, depth)?,
+            _ => return Err(UnpackError::NonStringKey { offset }),
+        };
+    }
+    Ok(MapRef { data, start, len })
+}
+
+fn read_structure<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+    len: usize,
+    limit: usize,
+    depth: usize,
+) -> Result<ValueRef<'a>, UnpackError> {
+    let signature = byte(data, pos)?;
+    let fields = read_list(data, pos, len, limit, depth)?;
+    Ok(ValueRef::Structure { signature, fields })
+}
+
+/// The header of a value in data that `read` has already checked.
+///
+enum Header {
+    String(usize),
+    List(usize),
+    Map(usize),
+    Structure(u8, usize),
+    /// Anything else, which has a fixed size and is cheap to read again.
+    Scalar,
+}
+
+/// Reads the header at `pos`, moving `pos` past it to the contents. For
+/// a scalar, `pos` is left where it was.
+///
+fn checked_header(data: &[u8], pos: &mut usize) -> Header {
+    let marker = data[*pos];
+    let sized = |pos: &mut usize, n: usize| {
+        let size = data[*pos..*pos + n]
+            .iter()
+            .fold(0usize, |size, &b| (size << 8) | usize::from(b));
+        *pos += n;
+        size
+    };
+    let start = *pos;
+    *pos += 1;
+    let tiny = usize::from(marker & 0x0F);
+    match marker {
+        0x80..=0x8F => Header::String(tiny),
+        0x90..=0x9F => Header::List(tiny),
+        0xA0..=0xAF => Header::Map(tiny),
+        0xB0..=0xBF => {
+            *pos += 1;
+            Header::Structure(data[*pos - 1], tiny)
+        }
+        0xD0..=0xD2 => Header::String(sized(pos, 1 << (marker - 0xD0))),
+        0xD4..=0xD6 => Header::List(sized(pos, 1 << (marker - 0xD4))),
+        0xD8..=0xDA => Header::Map(sized(pos, 1 << (marker - 0xD8))),
+        0xDC..=0xDD => {
+            let len = sized(pos, 1 << (marker - 0xDC));
+            *pos += 1;
+            Header::Structure(data[*pos - 1], len)
+        }
+        _ => {
+            *pos = start;
+            Header::Scalar
+        }
+    }
+}
+
+/// Decodes the value at `pos` in data that `read` has already checked,
+/// moving `pos` past it. Strings are not checked for UTF-8 again. A list,
+/// map or structure is passed over by reading the header of everything
+/// inside it, however deep, without decoding any of it.
+///
+fn read_checked<'a>(data: &'a [u8], pos: &mut usize) -> ValueRef<'a> {
+    match checked_header(data, pos) {
+        Header::String(size) => ValueRef::String(checked_str(data, pos, size)),
+        Header::List(len) => ValueRef::List(skip_list(data, pos, len)),
+        Header::Map(len) => {
+            let start = *pos;
+            skip(data, pos, 2 * len);
+            ValueRef::Map(MapRef { data, start, len })
+        }
+        Header::Structure(signature, len) => ValueRef::Structure {
+            signature,
+            fields: skip_list(data, pos, len),
+        },
+        Header::Scalar => read(data, pos, usize::MAX, 0).expect("checked when first decoded"),
+    }
+}
+
+/// Copies the value at `pos` in data that `read` has already checked out
+/// of the buffer, moving `pos` past it. Unlike going through `read_checked`
+/// item by item, every byte is read only once.
+///
+fn checked_value(data: &[u8], pos: &mut usize) -> Value {
+    match checked_header(data, pos) {
+        Header::String(size) => Value::String(String::from(checked_str(data, pos, size))),
+        Header::List(len) => Value::List(checked_values(data, pos, len)),
+        Header::Map(len) => Value::Map(checked_map(data, pos, len)),
+        Header::Structure(signature, len) => {
+            Value::from_structure(signature, checked_values(data, pos, len))
+        }
+        Header::Scalar => read(data, pos, usize::MAX, 0)
+            .expect("checked when first decoded")
+            .to_value(),
+    }
+}
+
+fn checked_values(data: &[u8], pos: &mut usize, len: usize) -> Vec<Value> {
+    (0..len).map(|_| checked_value(data, pos)).collect()
+}
+
+fn checked_map(data: &[u8], pos: &mut usize, len: usize) -> HashMap<String, Value> {
+    (0..len)
+        .map(|_| {
+            let key = match checked_value(data, pos) {
+                Value::String(key) => key,
+                _ => unreachable!("checked when first decoded"),
+            };
+            (key, checked_value(data, pos))
+        })
+        .collect()
+}
+
+fn checked_str<'a>(data: &'a [u8], pos: &mut usize, size: usize) -> &'a str {
+    let bytes = &data[*pos..*pos + size];
+    *pos += size;
+    // SAFETY: `read` found these bytes to be UTF-8 before any `ListRef` or
+    // `MapRef` pointing at them could be made.
+    unsafe { str::from_utf8_unchecked(bytes) }
+}
+
+/// Moves `pos` past `n` checked values.
+///
+fn skip(data: &[u8], pos: &mut usize, n: usize) {
+    for _ in 0..n {
+        read_checked(data, pos);
+    }
+}
+
+fn skip_list<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> ListRef<'a> {
+    let start = *pos;
+    skip(data, pos, len);
+    ListRef { data, start, len }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{parameters, Date};
+
+    fn packed(value: Value) -> Vec<u8> {
+        value.pack_into().unwrap()
+    }
+
+    #[test]
+    fn decodes_what_unpack_decodes() {
+        let value: Value = parameters!(
+            "name" => "Ann",
+            "scores" => vec![Value::Integer(-17), 300.into(), 70_000.into(), (1i64 << 40).into()],
+            "ratio" => 0.5,
+            "nested" => parameters!("flag" => true, "none" => Value::Null),
+            "long" => "x".repeat(300),
+            "many" => (0..300).collect::<Vec<i64>>(),
+            "born" => Value::Date(Date::from_ymd(1997, 5, 19).unwrap())
+        )
+        .into();
+        let data = packed(value.clone());
+        let value_ref = ValueRef::unpack(&data).unwrap();
+        assert_eq!(value_ref.to_value(), value);
+        assert_eq!(value_ref.to_value(), Value::unpack(&mut &data[..]).unwrap());
+    }
+
+    #[test]
+    fn borrows_and_projects_lazily() {
+        let data = packed(Value::Structure {
+            signature: 0x71,
+            fields: vec![vec![Value::from("big"), vec![1, 2, 3].into(), "wanted".into()].into()],
+        });
+        let fields = match ValueRef::unpack(&data).unwrap() {
+            ValueRef::Structure {
+                signature: 0x71,
+                fields,
+            } => fields,
+            other => panic!("unexpected {:?}", other),
+        };
+        let record = fields.get(0).and_then(|v| v.as_list()).unwrap();
+        assert_eq!(record.len(), 3);
+        let wanted = record.get(2).and_then(|v| v.as_str()).unwrap();
+        assert_eq!(wanted, "wanted");
+        assert!(data.as_ptr_range().contains(&wanted.as_ptr()));
+        assert_eq!(record.iter().count(), 3);
+        assert_eq!(format!("{:?}", record), r#"["big", [1, 2, 3], "wanted"]"#);
+
+        let data = packed(parameters!("a" => 1, "b" => "two").into());
+        let map = ValueRef::unpack(&data).unwrap().as_map().unwrap();
+        assert_eq!(map.get("b").and_then(|v| v.as_str()), Some("two"));
+        assert!(map.get("c").is_none());
+    }
+
+    #[test]
+    fn reports_errors_where_unpack_does() {
+        let deep = vec![0x91; 1000];
+        let cases: &[&[u8]] = &[
+            &deep,
+            &[0x92, 0x01, 0xE0],
+            &[0x83, b'a', 0xFF, b'c'],
+            &[0xA2, 0x81, b'a', 0x01, 0x02, 0x03],
+            &[0xC9, 0x01],
+            &[0x85, b'a', b'b'],
+            &[0x91],
+        ];
+        for &data in cases {
+            let expected = Value::unpack(&mut &data[..]).unwrap_err();
+            let error = ValueRef::unpack(data).unwrap_err();
+            assert_eq!(error.to_string(), expected.to_string());
+        }
+        match ValueRef::unpack_with_limit(&[0xD6, 0xFF, 0xFF, 0xFF, 0xFF], 1000) {
+            Err(UnpackError::SizeLimitExceeded {
+                size: 0xFFFF_FFFF,
+                limit: 1000,
+                offset: 0,
+            }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn copies_nested_values() {
+        let mut data = vec![0x91; MAX_DEPTH - 4];
+        data.extend_from_slice(&[0x92, 0x81, b'a', 0xA1, 0x81, b'k', 0xB1, 0x44, 0x01]);
+        let value = ValueRef::unpack(&data).unwrap();
+        assert_eq!(value.to_value(), Value::unpack(&mut &data[..]).unwrap());
+        let mut inner = value;
+        for _ in 0..MAX_DEPTH - 4 {
+            inner = inner.as_list().unwrap().get(0).unwrap();
+        }
+        let items = inner.as_list().unwrap();
+        assert_eq!(items.get(0).and_then(|v| v.as_str()), Some("a"));
+        assert!(items.get(1).unwrap().as_map().unwrap().get("k").is_some());
+    }
+}